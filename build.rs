@@ -87,7 +87,12 @@ fn cargo_build_bin(
 
     let filtered_env: HashMap<String, String> = std::env::vars()
         .filter(|&(ref k, _)| {
-            k == "TERM" || k == "TZ" || k == "LANG" || k == "PATH" || k == "RUSTUP_HOME"
+            k == "TERM"
+                || k == "TZ"
+                || k == "LANG"
+                || k == "PATH"
+                || k == "RUSTUP_HOME"
+                || k == "ENARX_SGX_HEAP_PAGES"
         })
         .collect();
 
@@ -111,6 +116,19 @@ fn cargo_build_bin(
 
     rerun_src(&path);
 
+    // Export the version of the binary, e.g. as `ENARX_SHIM_SEV_VERSION`
+    let manifest = std::fs::read_to_string(in_dir.join("Cargo.toml"))?;
+    if let Some(version) = manifest
+        .lines()
+        .find_map(|line| line.strip_prefix("version = "))
+    {
+        println!(
+            "cargo:rustc-env=ENARX_{}_VERSION={}",
+            bin_name.to_uppercase().replace('-', "_"),
+            version.trim_matches('"')
+        );
+    }
+
     let target_dir = out_dir.join(path);
 
     let stdout: Stdio = OpenOptions::new()
@@ -195,6 +213,7 @@ fn create(path: &Path) {
 fn main() {
     println!("cargo:rerun-if-env-changed=OUT_DIR");
     println!("cargo:rerun-if-env-changed=PROFILE");
+    println!("cargo:rerun-if-env-changed=ENARX_SGX_HEAP_PAGES");
 
     let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
     let out_dir_proto = out_dir.join("protos");
@@ -243,6 +262,9 @@ fn main() {
             #[cfg(feature = "backend-sgx")]
             "shim-sgx" => cargo_build_bin(&path, &out_dir, target, "shim-sgx").unwrap(),
 
+            // Built as a dependency of the shims
+            "shim-common" => rerun_src(&path),
+
             _ => eprintln!("Unknown internal directory: {}", dir_name),
         }
 
@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The arguments and environment of the exec
+
+/// The maximum size of the arguments and environment of the exec
+pub const EXEC_ARGS_MAX: usize = 16 * 1024;
+
+/// The arguments and environment of the exec, if the host does not supply them
+pub const EXEC_ARGS_DEFAULT: &[u8] = b"\x01\0\0\0\0\0\0\0/init\0LANG=C\0";
+
+/// The arguments and environment of the exec
+///
+/// Serialized as the number of arguments (little-endian `u64`), followed by
+/// the arguments and environment variables, each terminated by a NUL byte.
+pub struct ExecArgs<'a> {
+    argc: usize,
+    strings: &'a [u8],
+}
+
+impl<'a> ExecArgs<'a> {
+    /// Parse and validate the untrusted `bytes`
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        let argc = bytes.get(..8)?.try_into().ok().map(u64::from_le_bytes)?;
+        let argc = usize::try_from(argc).ok()?;
+        let strings = bytes.get(8..)?;
+
+        if strings.last() != Some(&0) {
+            return None;
+        }
+
+        let args = Self { argc, strings };
+        let count = args.strings().try_fold(0usize, |n, s| {
+            core::str::from_utf8(s).ok()?;
+            n.checked_add(1)
+        })?;
+
+        if argc == 0 || argc > count {
+            return None;
+        }
+
+        Some(args)
+    }
+
+    fn strings(&self) -> impl Iterator<Item = &'a [u8]> {
+        // The last NUL terminates the last string
+        let strings = self.strings;
+        let end = strings.len().saturating_sub(1);
+        strings[..end].split(|b| *b == 0)
+    }
+
+    fn str(bytes: &'a [u8]) -> &'a str {
+        // Validated in `parse()`
+        core::str::from_utf8(bytes).unwrap()
+    }
+
+    /// The arguments, starting with the name of the exec
+    pub fn args(&self) -> impl Iterator<Item = &'a str> {
+        self.strings().take(self.argc).map(Self::str)
+    }
+
+    /// The environment variables
+    pub fn env(&self) -> impl Iterator<Item = &'a str> {
+        self.strings().skip(self.argc).map(Self::str)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default() {
+        let args = ExecArgs::parse(EXEC_ARGS_DEFAULT).unwrap();
+        assert_eq!(args.args().collect::<Vec<_>>(), ["/init"]);
+        assert_eq!(args.env().collect::<Vec<_>>(), ["LANG=C"]);
+    }
+
+    #[test]
+    fn empty_strings() {
+        let args = ExecArgs::parse(b"\x02\0\0\0\0\0\0\0/init\0\0").unwrap();
+        assert_eq!(args.args().collect::<Vec<_>>(), ["/init", ""]);
+        assert_eq!(args.env().count(), 0);
+    }
+
+    #[test]
+    fn invalid() {
+        // Too short
+        assert!(ExecArgs::parse(b"\x01\0\0\0").is_none());
+        // No arguments
+        assert!(ExecArgs::parse(b"\0\0\0\0\0\0\0\0LANG=C\0").is_none());
+        // More arguments than strings
+        assert!(ExecArgs::parse(b"\x02\0\0\0\0\0\0\0/init\0").is_none());
+        // Not terminated
+        assert!(ExecArgs::parse(b"\x01\0\0\0\0\0\0\0/init").is_none());
+        // Not UTF-8
+        assert!(ExecArgs::parse(b"\x01\0\0\0\0\0\0\0\xff\0").is_none());
+    }
+}
@@ -0,0 +1,12 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Code shared by the SEV and the SGX shim
+
+#![cfg_attr(not(test), no_std)]
+#![deny(clippy::all)]
+#![deny(clippy::integer_arithmetic)]
+#![deny(missing_docs)]
+#![warn(rust_2018_idioms)]
+
+pub mod exec_args;
+pub mod signal;
@@ -0,0 +1,451 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The signal state of the exec and the frame pushed to run its handlers
+//!
+//! The shims keep the state in a lock of their own and deliver the signals
+//! on the return from a syscall.
+
+/// The number of signals
+pub const NSIG: usize = 64;
+
+/// The size of the kernel signal set
+pub const SIGSET_SIZE: usize = 8;
+
+/// The default action of a signal
+pub const SIG_DFL: u64 = 0;
+
+/// Ignore a signal
+pub const SIG_IGN: u64 = 1;
+
+/// Use the alternate signal stack
+pub const SA_ONSTACK: u64 = 0x0800_0000;
+
+/// Restart a syscall interrupted by the signal
+pub const SA_RESTART: u64 = 0x1000_0000;
+
+/// The action has a `restorer`, calling `rt_sigreturn`
+pub const SA_RESTORER: u64 = 0x0400_0000;
+
+/// Do not block the signal in its own handler
+pub const SA_NODEFER: u64 = 0x4000_0000;
+
+/// Reset the action to the default on delivery
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+/// The size of the red zone below the stack pointer, which a signal frame must not overwrite
+pub const RED_ZONE: u64 = 128;
+
+/// The minimal size of an alternate signal stack
+const MINSIGSTKSZ: u64 = 2048;
+
+/// `si_code` of a signal sent by `kill()`
+const SI_USER: i32 = 0;
+
+/// The size of the legacy region of the `xsave` area, as saved by `fxsave`
+pub const FPSTATE_SIZE: usize = 512;
+
+/// The x87 state component bit of `XSTATE_BV`
+pub const XFEATURE_X87: u64 = 1 << 0;
+
+/// The SSE state component bit of `XSTATE_BV`
+pub const XFEATURE_SSE: u64 = 1 << 1;
+
+/// The bits of `mxcsr`, which are not reserved
+const MXCSR_MASK: u32 = 0xffff;
+
+/// A signal action, as passed to `rt_sigaction`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SigAction {
+    /// The handler, `SIG_DFL` or `SIG_IGN`
+    pub handler: u64,
+    /// `SA_*` flags
+    pub flags: u64,
+    /// The address the handler returns to
+    pub restorer: u64,
+    /// The signals blocked while the handler runs
+    pub mask: u64,
+}
+
+/// A signal stack, as passed to `sigaltstack`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SigStack {
+    /// The lowest address of the stack
+    pub sp: u64,
+    /// `SS_ONSTACK` or `SS_DISABLE`
+    pub flags: i32,
+    /// The size of the stack
+    pub size: u64,
+}
+
+/// The `ucontext_t` the handler of a signal gets as third argument
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct UContext {
+    flags: u64,
+    link: u64,
+    /// The alternate signal stack at the time of the signal
+    pub stack: SigStack,
+    /// The registers of the interrupted code, indexed by `libc::REG_*`
+    pub gregs: [u64; 23],
+    /// The address of the `FpState` of the interrupted code
+    pub fpregs: u64,
+    reserved: [u64; 8],
+    /// The signal mask of the interrupted code
+    pub mask: u64,
+}
+
+impl UContext {
+    /// Create a context with its `FpState` at `fpregs`
+    pub fn new(stack: SigStack, gregs: [u64; 23], fpregs: u64, mask: u64) -> Self {
+        Self {
+            flags: 0,
+            link: 0,
+            stack,
+            gregs,
+            fpregs,
+            reserved: [0; 8],
+            mask,
+        }
+    }
+}
+
+/// The `siginfo_t` the handler of a signal gets as second argument
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    fields: [u32; 29],
+}
+
+impl SigInfo {
+    /// Describe a signal sent by a process outside of the keep
+    pub fn new(sig: usize) -> Self {
+        Self {
+            signo: sig as _,
+            errno: 0,
+            code: SI_USER,
+            fields: [0; 29],
+        }
+    }
+}
+
+/// The x87 and SSE state, which `fpregs` of a `ucontext_t` points to
+///
+/// This is the legacy region of the `xsave` area, in the layout of `fxsave`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FpState(pub [u8; FPSTATE_SIZE]);
+
+impl Default for FpState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FpState {
+    const MXCSR: usize = 24;
+    const X87: core::ops::Range<usize> = 0..24;
+    const ST: core::ops::Range<usize> = 32..160;
+    const XMM: core::ops::Range<usize> = 160..416;
+
+    /// The initial state, like after `fninit` and with the default `mxcsr`
+    pub const fn new() -> Self {
+        let mut bytes = [0; FPSTATE_SIZE];
+        // fcw = 0x037f
+        bytes[0] = 0x7f;
+        bytes[1] = 0x03;
+        // mxcsr = 0x1f80
+        bytes[24] = 0x80;
+        bytes[25] = 0x1f;
+        Self(bytes)
+    }
+
+    /// Take the state from the legacy region of an `xsave` area
+    ///
+    /// `xsave` does not write the components, which are in their initial
+    /// state according to `xstate_bv`, so these are initialized here.
+    pub fn from_xsave(legacy: &[u8; FPSTATE_SIZE], xstate_bv: u64) -> Self {
+        let init = Self::new();
+        let mut state = Self(*legacy);
+
+        if xstate_bv & XFEATURE_X87 == 0 {
+            state.0[Self::X87].copy_from_slice(&init.0[Self::X87]);
+            state.0[Self::ST].copy_from_slice(&init.0[Self::ST]);
+        }
+
+        if xstate_bv & XFEATURE_SSE == 0 {
+            state.0[Self::XMM].copy_from_slice(&init.0[Self::XMM]);
+        }
+
+        state
+    }
+
+    /// The `mxcsr` register
+    pub fn mxcsr(&self) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.0[Self::MXCSR..][..4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Clear the reserved bits of `mxcsr`, which make `fxrstor` fault
+    ///
+    /// A state from a signal frame is under control of the exec, so it has
+    /// to be sanitized before it is restored.
+    pub fn sanitize(&mut self) {
+        let mxcsr = self.mxcsr() & MXCSR_MASK;
+        self.0[Self::MXCSR..][..4].copy_from_slice(&mxcsr.to_le_bytes());
+    }
+}
+
+/// The frame pushed onto the stack of the exec to run a signal handler
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SigFrame {
+    /// The return address of the handler
+    pub restorer: u64,
+    /// The context restored by `rt_sigreturn`
+    pub uc: UContext,
+    /// The signal information
+    pub info: SigInfo,
+    /// The x87 and SSE state `uc.fpregs` points to
+    pub fpstate: FpState,
+}
+
+/// The signal state of the exec
+pub struct Signals {
+    actions: [SigAction; NSIG],
+    mask: u64,
+    pending: u64,
+    altstack: SigStack,
+}
+
+/// The bit of `sig` in a signal set
+fn bit(sig: usize) -> u64 {
+    1u64.wrapping_shl(sig.wrapping_sub(1) as u32)
+}
+
+/// The signals, which can neither be caught nor blocked
+const UNBLOCKABLE: u64 = 1 << (libc::SIGKILL - 1) | 1 << (libc::SIGSTOP - 1);
+
+/// Whether the default action of `sig` is to ignore it
+pub fn default_ignored(sig: usize) -> bool {
+    matches!(
+        sig as libc::c_int,
+        libc::SIGCHLD | libc::SIGCONT | libc::SIGURG | libc::SIGWINCH
+    )
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Signals {
+    /// The state of an exec, which did not change any signal handling
+    pub const fn new() -> Self {
+        Self {
+            actions: [SigAction {
+                handler: SIG_DFL,
+                flags: 0,
+                restorer: 0,
+                mask: 0,
+            }; NSIG],
+            mask: 0,
+            pending: 0,
+            altstack: SigStack {
+                sp: 0,
+                flags: libc::SS_DISABLE,
+                size: 0,
+            },
+        }
+    }
+
+    /// Replace the action of `sig` with `act`, if set, and return the old one
+    pub fn action(&mut self, sig: usize, act: Option<SigAction>) -> Result<SigAction, libc::c_int> {
+        let slot = sig
+            .checked_sub(1)
+            .and_then(|i| self.actions.get_mut(i))
+            .ok_or(libc::EINVAL)?;
+        let old = *slot;
+
+        if let Some(act) = act {
+            if bit(sig) & UNBLOCKABLE != 0 {
+                return Err(libc::EINVAL);
+            }
+            *slot = act;
+        }
+
+        Ok(old)
+    }
+
+    /// Change the signal mask like `rt_sigprocmask` and return the old one
+    pub fn procmask(&mut self, how: libc::c_int, set: Option<u64>) -> Result<u64, libc::c_int> {
+        let old = self.mask;
+
+        if let Some(set) = set {
+            self.mask = match how {
+                libc::SIG_BLOCK => old | set,
+                libc::SIG_UNBLOCK => old & !set,
+                libc::SIG_SETMASK => set,
+                _ => return Err(libc::EINVAL),
+            };
+            self.mask &= !UNBLOCKABLE;
+        }
+
+        Ok(old)
+    }
+
+    /// The current signal mask
+    pub fn mask(&self) -> u64 {
+        self.mask
+    }
+
+    /// Restore the signal mask saved in a signal frame
+    pub fn set_mask(&mut self, mask: u64) {
+        self.mask = mask & !UNBLOCKABLE;
+    }
+
+    /// Whether `sp` is on the alternate signal stack
+    pub fn on_altstack(&self, sp: u64) -> bool {
+        self.altstack.flags & libc::SS_DISABLE == 0
+            && sp >= self.altstack.sp
+            && sp.wrapping_sub(self.altstack.sp) < self.altstack.size
+    }
+
+    /// The alternate signal stack as seen from code running at `sp`
+    pub fn altstack(&self, sp: u64) -> SigStack {
+        let mut stack = self.altstack;
+        if self.on_altstack(sp) {
+            stack.flags = libc::SS_ONSTACK;
+        }
+        stack
+    }
+
+    /// Replace the alternate signal stack like `sigaltstack` and return the old one
+    ///
+    /// `sp` is the stack pointer of the calling code.
+    pub fn set_altstack(
+        &mut self,
+        new: Option<SigStack>,
+        sp: u64,
+    ) -> Result<SigStack, libc::c_int> {
+        let old = self.altstack(sp);
+
+        if let Some(new) = new {
+            if old.flags == libc::SS_ONSTACK {
+                return Err(libc::EPERM);
+            }
+
+            self.altstack = match new.flags {
+                libc::SS_DISABLE => SigStack {
+                    flags: libc::SS_DISABLE,
+                    ..Default::default()
+                },
+                0 | libc::SS_ONSTACK if new.size < MINSIGSTKSZ => return Err(libc::ENOMEM),
+                0 | libc::SS_ONSTACK => {
+                    new.sp.checked_add(new.size).ok_or(libc::EINVAL)?;
+                    SigStack { flags: 0, ..new }
+                }
+                _ => return Err(libc::EINVAL),
+            };
+        }
+
+        Ok(old)
+    }
+
+    /// Mark `sig`, received from the host, as pending
+    pub fn raise(&mut self, sig: usize) {
+        if (1..=NSIG).contains(&sig) {
+            self.pending |= bit(sig);
+        }
+    }
+
+    /// Take the next pending signal, which is not blocked, and its action
+    pub fn next_pending(&mut self) -> Option<(usize, SigAction)> {
+        let deliverable = self.pending & !self.mask;
+        if deliverable == 0 {
+            return None;
+        }
+
+        let sig = deliverable.trailing_zeros() as usize;
+        let sig = sig.checked_add(1)?;
+        self.pending &= !bit(sig);
+
+        self.actions.get(sig.checked_sub(1)?).map(|act| (sig, *act))
+    }
+
+    /// Update the signal state for running the handler of `sig`
+    ///
+    /// Returns the signal mask to restore after the handler.
+    pub fn enter(&mut self, sig: usize, act: &SigAction) -> u64 {
+        let old = self.mask;
+
+        self.mask |= act.mask;
+        if act.flags & SA_NODEFER == 0 {
+            self.mask |= bit(sig);
+        }
+        self.mask &= !UNBLOCKABLE;
+
+        if act.flags & SA_RESETHAND != 0 {
+            if let Some(slot) = sig.checked_sub(1).and_then(|i| self.actions.get_mut(i)) {
+                *slot = SigAction::default();
+            }
+        }
+
+        old
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fpstate() {
+        let init = FpState::new();
+        assert_eq!(init.mxcsr(), 0x1f80);
+
+        let mut legacy = [0xff; FPSTATE_SIZE];
+        let state = FpState::from_xsave(&legacy, XFEATURE_X87 | XFEATURE_SSE);
+        assert_eq!(state.0, legacy);
+
+        // Components in their initial state are not taken from the area
+        legacy[..32].copy_from_slice(&init.0[..32]);
+        let state = FpState::from_xsave(&legacy, XFEATURE_X87);
+        assert!(state.0[160..416].iter().all(|b| *b == 0));
+        assert!(state.0[32..160].iter().all(|b| *b == 0xff));
+
+        let mut state = FpState([0xff; FPSTATE_SIZE]);
+        state.sanitize();
+        assert_eq!(state.mxcsr(), 0xffff);
+    }
+
+    #[test]
+    fn frame() {
+        let mut signals = Signals::new();
+        let act = SigAction {
+            handler: 0x1000,
+            flags: SA_RESTORER | SA_RESETHAND,
+            restorer: 0x2000,
+            mask: bit(libc::SIGUSR2 as _),
+        };
+
+        assert!(signals.action(libc::SIGKILL as _, Some(act)).is_err());
+        signals.action(libc::SIGUSR1 as _, Some(act)).unwrap();
+
+        signals.raise(libc::SIGUSR1 as _);
+        let (sig, act) = signals.next_pending().unwrap();
+        assert_eq!(sig, libc::SIGUSR1 as usize);
+        assert!(signals.next_pending().is_none());
+
+        let old = signals.enter(sig, &act);
+        assert_eq!(old, 0);
+        assert_eq!(signals.mask(), bit(sig) | bit(libc::SIGUSR2 as _));
+        assert_eq!(signals.action(sig, None).unwrap().handler, SIG_DFL);
+    }
+}
@@ -250,15 +250,7 @@ impl EnarxAllocator {
 
                         if snp_active() {
                             // pvalidate the newly assigned memory region
-                            let virt_region = Span::new(free_start as usize, line.count);
-                            let virt_line = Line::from(virt_region);
-
-                            for addr in (virt_line.start..virt_line.end)
-                                .step_by(Page::<Size4KiB>::SIZE as _)
-                            {
-                                let va = VirtAddr::new(addr as _);
-                                unsafe { pvalidate(va, PvalidateSize::Size4K, true).unwrap() };
-                            }
+                            pvalidate_region(free_start, line.count, true);
                         }
 
                         unsafe {
@@ -288,6 +280,119 @@ impl EnarxAllocator {
         }
     }
 
+    /// Return completely free ballooned memory regions to the host
+    ///
+    /// Starting with the most recent, and therefore largest, region, each
+    /// free region is reserved in the heap as a whole, so it is never handed
+    /// out again, and then given back to the host.
+    pub fn deflate(&mut self) {
+        let mut index = HOSTMAP.num_entries();
+
+        // The first entry is the initial memory, which is never given back
+        while let Some(prev) = index.checked_sub(1).filter(|prev| *prev > 0) {
+            index = prev;
+
+            let region = match HOSTMAP.entry(index) {
+                Some(region) => region,
+                None => continue,
+            };
+
+            if self.allocator.free() < region.count {
+                continue;
+            }
+
+            let shim_phys_page = ShimPhysAddr::<u8>::try_from(region.start).unwrap();
+            let region_start: *mut u8 = ShimVirtAddr::from(shim_phys_page).into();
+
+            let layout = Layout::from_size_align(region.count, align_of::<Page4KiB>()).unwrap();
+            let reserved = match self.reserve(region_start, layout) {
+                Some(reserved) => reserved,
+                None => continue,
+            };
+
+            if snp_active() {
+                pvalidate_region(region_start, region.count, false);
+            }
+
+            let ret = HOST_CALL_ALLOC
+                .try_alloc()
+                .ok_or(libc::EIO)
+                .and_then(|mut host_call| host_call.deflate(region.start, region.count));
+
+            if ret.is_err() {
+                if snp_active() {
+                    pvalidate_region(region_start, region.count, true);
+                }
+                unsafe { self.allocator.deallocate(reserved, layout) };
+                return;
+            }
+
+            HOSTMAP.remove_entry(region.start);
+
+            // Grow again from about the size of the returned region
+            self.last_alloc = region.count.checked_div(2).unwrap().max(Page4KiB::SIZE);
+        }
+    }
+
+    /// Reserve the memory at `start` of `layout` in the heap, if it is free
+    ///
+    /// The heap hands out the first free block, which fits, so the free
+    /// memory in front of `start` is held until the block at `start` is
+    /// found. The held blocks are chained through their first bytes.
+    fn reserve(&mut self, start: *mut u8, layout: Layout) -> Option<NonNull<u8>> {
+        /// The head of a held block
+        struct Held {
+            next: Option<NonNull<Held>>,
+            layout: Layout,
+        }
+
+        let mut held: Option<NonNull<Held>> = None;
+        let mut hold = |block: NonNull<u8>, layout: Layout| unsafe {
+            let block = block.cast::<Held>();
+            block.as_ptr().write(Held { next: held, layout });
+            held = Some(block);
+        };
+
+        let found = loop {
+            let block = match self.allocator.allocate_first_fit(layout) {
+                Ok(block) => block,
+                Err(_) => break None,
+            };
+
+            let offset = (start as usize).checked_sub(block.as_ptr() as usize);
+            match offset {
+                Some(0) => break Some(block),
+
+                // Behind `start`, so the memory at `start` is in use
+                None => {
+                    unsafe { self.allocator.deallocate(block, layout) };
+                    break None;
+                }
+
+                // Entirely in front of `start`
+                Some(offset) if offset >= layout.size() => hold(block, layout),
+
+                // Overlapping `start`, so hold only the memory in front of it
+                Some(offset) => {
+                    unsafe { self.allocator.deallocate(block, layout) };
+                    let front = Layout::from_size_align(offset, layout.align()).unwrap();
+                    match self.allocator.allocate_first_fit(front) {
+                        Ok(block) => hold(block, front),
+                        Err(_) => break None,
+                    }
+                }
+            }
+        };
+
+        while let Some(block) = held {
+            let Held { next, layout } = unsafe { block.as_ptr().read() };
+            unsafe { self.allocator.deallocate(block.cast(), layout) };
+            held = next;
+        }
+
+        found
+    }
+
     fn try_alloc_half(&mut self, mut size: usize) -> (*mut u8, usize) {
         assert!(size >= size_of::<Page4KiB>());
         loop {
@@ -433,7 +538,10 @@ impl EnarxAllocator {
         Ok(())
     }
 
-    /// FIXME: unmap
+    /// Unmap the given virtual address range
+    ///
+    /// Pages, which are not mapped, are skipped. Only frames handed out by the
+    /// allocator are returned to it, not those of the exec image.
     pub fn unmap_memory(&mut self, virt_addr: VirtAddr, size: usize) -> Result<(), UnmapError> {
         if size == 0 {
             return Ok(());
@@ -447,15 +555,28 @@ impl EnarxAllocator {
             Page::range_inclusive(start_page, end_page)
         };
 
+        let exec = unsafe {
+            let start = &crate::_ENARX_EXEC_START as *const _ as usize;
+            let end = &crate::_ENARX_EXEC_END as *const _ as usize;
+            start..end
+        };
+
         for frame_from in page_range_to {
-            let phys = {
-                let (phys_frame, _) = SHIM_PAGETABLE.write().unmap(frame_from)?;
-                phys_frame.start_address()
+            let phys = match SHIM_PAGETABLE.write().unmap(frame_from) {
+                Ok((phys_frame, flush)) => {
+                    flush.ignore();
+                    phys_frame.start_address()
+                }
+                Err(UnmapError::PageNotMapped) => continue,
+                Err(e) => return Err(e),
             };
 
             let free_start_phys = Address::<usize, _>::from(phys.as_u64() as *const u8);
             let shim_phys_page = ShimPhysAddr::from(free_start_phys);
             let shim_virt: *mut u8 = ShimVirtAddr::from(shim_phys_page).into();
+            if exec.contains(&(shim_virt as usize)) {
+                continue;
+            }
             unsafe {
                 self.dealloc_pages(shim_virt, Page::<Size4KiB>::SIZE as usize);
             }
@@ -493,6 +614,16 @@ impl EnarxAllocator {
     }
 }
 
+/// Set the SNP validation state of all pages in the `count` bytes at `start`
+fn pvalidate_region(start: *mut u8, count: usize, valid: bool) {
+    let virt_line = Line::from(Span::new(start as usize, count));
+
+    for addr in (virt_line.start..virt_line.end).step_by(Page::<Size4KiB>::SIZE as _) {
+        let va = VirtAddr::new(addr as _);
+        unsafe { pvalidate(va, PvalidateSize::Size4K, valid).unwrap() };
+    }
+}
+
 unsafe impl paging::FrameAllocator<Size4KiB> for EnarxAllocator {
     #[allow(unused_unsafe)]
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
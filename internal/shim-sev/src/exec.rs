@@ -4,6 +4,7 @@
 
 use crate::addr::ShimPhysAddr;
 use crate::allocator::ALLOCATOR;
+use crate::hostcall::HOST_CALL_ALLOC;
 use crate::random::random;
 use crate::shim_stack::init_stack_with_guard;
 use crate::snp::cpuid;
@@ -17,6 +18,7 @@ use goblin::elf::header::header64::Header;
 use goblin::elf::header::ELFMAG;
 use goblin::elf::program_header::program_header64::*;
 use nbytes::bytes;
+use shim_common::exec_args::{ExecArgs, EXEC_ARGS_DEFAULT, EXEC_ARGS_MAX};
 use spinning::{Lazy, RwLock};
 use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
 use x86_64::{PhysAddr, VirtAddr};
@@ -37,6 +39,25 @@ const EXEC_STACK_VIRT_ADDR_BASE: VirtAddr = VirtAddr::new_truncate(0x7ff0_0000_0
 #[allow(clippy::integer_arithmetic)]
 const EXEC_STACK_SIZE: u64 = bytes![2; MiB];
 
+/// Fetch the arguments and environment of the exec from the host into `buf`
+fn fetch_exec_args(buf: &mut [u8]) -> Option<&[u8]> {
+    let mut host_call = HOST_CALL_ALLOC.try_alloc()?;
+    let mut len = 0;
+
+    loop {
+        let (copied, total) = host_call.exec_args(buf.get_mut(len..)?, len).ok()?;
+        len = len.checked_add(copied)?;
+
+        if total > buf.len() || (copied == 0 && len < total) {
+            return None;
+        }
+
+        if len >= total {
+            return buf.get(..len);
+        }
+    }
+}
+
 /// The randomized virtual address of the exec
 #[cfg(not(feature = "gdb"))]
 pub static EXEC_VIRT_ADDR: Lazy<RwLock<VirtAddr>> = Lazy::new(|| {
@@ -128,22 +149,20 @@ fn crt0setup(
     stack_slice: &'static mut [u8],
     header: &Header,
 ) -> (VirtAddr, u64) {
+    let mut buf = [0u8; EXEC_ARGS_MAX];
+    let exec_args = fetch_exec_args(&mut buf)
+        .and_then(ExecArgs::parse)
+        .or_else(|| ExecArgs::parse(EXEC_ARGS_DEFAULT))
+        .unwrap();
+
     let mut builder = Builder::new(stack_slice);
-    builder.push("/init").unwrap();
+    for arg in exec_args.args() {
+        builder.push(arg).unwrap();
+    }
     let mut builder = builder.done().unwrap();
-    builder.push("LANG=C").unwrap();
-    // FIXME - v0.1.0 KEEP-CONFIG HACK
-    // We don't yet have a well-defined way to pass runtime configuration from
-    // the frontend/CLI into the keep. This is a hack to simulate that process.
-    // For v0.1.0 the keep configuration is hardcoded as follows:
-    //   * the .wasm module is open on fd3 and gets no arguments or env vars
-    //   * stdin, stdout, and stderr are enabled and should go to fd 0,1,2
-    //   * logging should be turned on at "debug" level
-    // This is one possible way we could provide that information to the code
-    // inside the keep. The actual implementation may be completely different.
-    builder.push("ENARX_STDIO_FDS=0,1,2").unwrap();
-    builder.push("ENARX_MODULE_FD=3").unwrap();
-    builder.push("RUST_LOG=enarx=debug,wasmldr=debug").unwrap();
+    for var in exec_args.env() {
+        builder.push(var).unwrap();
+    }
     let mut builder = builder.done().unwrap();
 
     let ph_header = app_virt_start + header.e_phoff;
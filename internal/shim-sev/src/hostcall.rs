@@ -23,6 +23,38 @@ use crate::snp::snp_active;
 use crate::spin::RwLocked;
 use crate::{_ENARX_SALLYPORT_END, _ENARX_SALLYPORT_START};
 
+/// Fetch the keep configuration from the host
+///
+/// Arguments: the number of bytes to fetch and the offset into the
+/// configuration. The host replies with the number of bytes copied to the
+/// block buffer and the total size of the configuration.
+pub const SYS_ENARX_KEEP_CONFIG: i64 = 0xEA10;
+
+/// Terminate the keep, because the shim failed
+///
+/// Arguments: the exit status. Unlike `exit_group`, this tells the host the
+/// keep crashed rather than the workload exiting.
+pub const SYS_ENARX_SHIM_EXIT: i64 = 0xEA12;
+
+/// Fetch the arguments and environment of the exec from the host
+///
+/// Arguments: the number of bytes to fetch and the offset into the data.
+/// The host replies like for `SYS_ENARX_KEEP_CONFIG`.
+pub const SYS_ENARX_EXEC_ARGS: i64 = 0xEA13;
+
+/// Return a ballooned memory region to the host
+///
+/// Arguments: the guest physical address and the size of the region, as
+/// added by ballooning. The region must not be accessed afterwards.
+pub const SYS_ENARX_MEM_DEFLATE: i64 = 0xEA14;
+
+/// Fetch a signal the host received for the exec
+///
+/// The host replies with the number of a pending signal, which it then
+/// forgets, or zero. It fails proxied syscalls with `EINTR` to announce
+/// pending signals.
+pub const SYS_ENARX_SIGNAL: i64 = 0xEA15;
+
 /// Host file descriptor
 #[derive(Copy, Clone)]
 pub struct HostFd(libc::c_int);
@@ -194,6 +226,14 @@ impl HostCall {
         Ok(unsafe { self.hostcall() }?[0].into())
     }
 
+    /// Return the `size` bytes of ballooned memory at `gpa` to the host
+    pub fn deflate(&mut self, gpa: PhysAddr, size: usize) -> Result<(), libc::c_int> {
+        self.block.as_mut().unwrap().msg.req =
+            request!(SYS_ENARX_MEM_DEFLATE => gpa.as_u64(), size);
+        unsafe { self.hostcall() }?;
+        Ok(())
+    }
+
     /// Get host memory info
     pub fn mem_info(&mut self) -> Result<MemInfo, libc::c_int> {
         self.block.as_mut().unwrap().msg.req = request!(SYS_ENARX_MEM_INFO);
@@ -208,15 +248,41 @@ impl HostCall {
         Ok(mem_info)
     }
 
+    /// Copy the arguments and environment of the exec, starting at `offset`, into `buf`
+    ///
+    /// Returns the number of bytes copied and the total size of the data.
+    pub fn exec_args(
+        &mut self,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> Result<(usize, usize), libc::c_int> {
+        let count = buf.len().min(Block::buf_capacity());
+        self.block.as_mut().unwrap().msg.req = request!(SYS_ENARX_EXEC_ARGS => count, offset);
+
+        let ret = unsafe { self.hostcall() }?;
+        let copied: usize = ret[0].into();
+        let total: usize = ret[1].into();
+
+        // be careful with the reply as it is untrusted
+        if copied > count {
+            return Err(libc::EIO);
+        }
+
+        let c = self.as_mut_block().cursor();
+        unsafe { c.copy_into_slice(count, &mut buf[..copied]) }.or(Err(libc::EIO))?;
+
+        Ok((copied, total))
+    }
+
     /// Exit the shim with a `status` code
     ///
     /// # Panics
     ///
     /// Panics, if the shim resumes to run.
     #[inline(always)]
-    pub fn exit_group(&mut self, status: i32) -> ! {
+    pub fn shim_exit(&mut self, status: i32) -> ! {
         unsafe {
-            let request = request!(libc::SYS_exit_group => status);
+            let request = request!(SYS_ENARX_SHIM_EXIT => status);
             self.block.as_mut().unwrap().msg.req = request;
 
             let _ = self.hostcall();
@@ -257,7 +323,7 @@ pub fn shim_write_all(fd: HostFd, bytes: &[u8]) -> Result<(), libc::c_int> {
 /// if it cannot talk to the host.
 pub fn shim_exit(status: i32) -> ! {
     if let Some(mut host_call) = HOST_CALL_ALLOC.try_alloc() {
-        host_call.exit_group(status)
+        host_call.shim_exit(status)
     }
 
     // provoke triple fault, causing a VM shutdown
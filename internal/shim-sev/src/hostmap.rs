@@ -126,6 +126,47 @@ impl HostMap {
             }
         }
     }
+    /// Remove the entry starting at `vm_phys`, keeping the entries sorted
+    fn do_remove_entry(&mut self, vm_phys: PhysAddr) -> Option<Span<PhysAddr, usize>> {
+        let mut removed = None;
+        let mut hole: Option<*mut HostMemEntry> = None;
+        let mut page = &mut self.host_mem as *mut HostMemListPage;
+
+        unsafe {
+            loop {
+                for ent in (*page).ent.iter_mut() {
+                    match hole {
+                        None if ent.shim.count == 0 => return None,
+                        None if ent.shim.start == vm_phys => {
+                            removed = Some(ent.shim);
+                            hole = Some(ent);
+                        }
+                        None => {}
+                        // Move all following entries one slot down
+                        Some(h) => {
+                            *h = *ent;
+                            if ent.shim.count == 0 {
+                                return removed;
+                            }
+                            hole = Some(ent);
+                        }
+                    }
+                }
+
+                page = match (*page).header.next {
+                    None => break,
+                    Some(ref mut p) => *p as *mut _,
+                };
+            }
+
+            if let Some(h) = hole {
+                (*h).shim.count = 0;
+            }
+        }
+
+        removed
+    }
+
     fn do_extend_slots(&mut self, mem_slots: usize, allocator: &mut Heap) {
         // Allocate enough pages to hold all memory slots in advance
         // There is already one HostMemListPage present, so we can ignore the rest of the division.
@@ -245,6 +286,48 @@ impl RwLocked<HostMap> {
         }
     }
 
+    /// Return the number of map entries
+    pub fn num_entries(&self) -> usize {
+        let this = self.read();
+
+        let mut len = 0usize;
+        let mut page = &this.host_mem;
+        loop {
+            for i in page.ent.iter() {
+                if i.shim.count == 0 {
+                    return len;
+                }
+                len = len.checked_add(1).unwrap();
+            }
+            match page.header.next {
+                None => return len,
+                Some(ref p) => page = *p,
+            }
+        }
+    }
+
+    /// Return the map entry at `index`, in the order they were added
+    ///
+    /// The entry at index 0 is the initial memory, the others were added
+    /// by ballooning.
+    pub fn entry(&self, mut index: usize) -> Option<Span<PhysAddr, usize>> {
+        let this = self.read();
+
+        let mut page = &this.host_mem;
+        loop {
+            if let Some(i) = page.ent.get(index) {
+                return Some(i.shim).filter(|span| span.count != 0);
+            }
+            index = index.checked_sub(HOST_MEM_LIST_NUM_ENTRIES).unwrap();
+            page = page.header.next.as_deref()?;
+        }
+    }
+
+    /// Remove the map entry starting at `vm_phys`
+    pub fn remove_entry(&self, vm_phys: PhysAddr) -> Option<Span<PhysAddr, usize>> {
+        self.write().do_remove_entry(vm_phys)
+    }
+
     /// Return the first unused physical address
     pub fn end_of_mem(&self) -> PhysAddr {
         self.read().end_of_mem
@@ -39,6 +39,7 @@ pub mod pagetables;
 pub mod paging;
 pub mod random;
 pub mod shim_stack;
+pub mod signal;
 pub mod snp;
 pub mod spin;
 pub mod sse;
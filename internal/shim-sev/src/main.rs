@@ -131,7 +131,6 @@ fn panic(_info: &core::panic::PanicInfo<'_>) -> ! {
                 print::_eprint(format_args!("{}\n", _info));
                 shim_sev::debug::print_stack_trace();
             }
-            // FIXME: might want to have a custom panic hostcall
             shim_sev::hostcall::shim_exit(255);
         }
     }
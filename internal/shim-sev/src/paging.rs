@@ -7,8 +7,10 @@ use crate::snp::get_cbit_mask;
 
 use spinning::{Lazy, RwLock};
 use x86_64::registers::control::Cr3;
-use x86_64::structures::paging::mapper::PageTableFrameMapping;
-use x86_64::structures::paging::{MappedPageTable, PageTable, PhysFrame};
+use x86_64::structures::paging::mapper::{PageTableFrameMapping, Translate, TranslateResult};
+use x86_64::structures::paging::{
+    MappedPageTable, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
 use x86_64::VirtAddr;
 
 /// A `PageTableFrameMapping` specialized to encrypted physical pages.
@@ -54,3 +56,67 @@ pub static SHIM_PAGETABLE: Lazy<RwLock<MappedPageTable<'static, EncPhysOffset>>>
             enc_offset_page_table,
         )
     });
+
+/// Check that `size` bytes at `ptr` are mapped in user space
+///
+/// With `writable`, the pages also have to be writable. This covers
+/// everything the exec can access itself (its segments, brk and mmap
+/// regions and its stack), but neither shim memory nor unmapped pages.
+pub fn is_user_mapped(ptr: usize, size: usize, writable: bool) -> bool {
+    let end = match ptr.checked_add(size) {
+        Some(end) => end,
+        None => return false,
+    };
+
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if writable {
+        required |= PageTableFlags::WRITABLE;
+    }
+
+    let page_table = SHIM_PAGETABLE.read();
+    let mut addr = ptr;
+
+    while addr < end {
+        let virt = match VirtAddr::try_new(addr as u64) {
+            Ok(virt) => virt,
+            Err(_) => return false,
+        };
+
+        let (size, flags) = match page_table.translate(virt) {
+            TranslateResult::Mapped { frame, flags, .. } => (frame.size(), flags),
+            _ => return false,
+        };
+
+        if !flags.contains(required) {
+            return false;
+        }
+
+        // Continue at the next page
+        let next = virt.align_down(size).as_u64().checked_add(size);
+        addr = match next {
+            Some(next) => next as usize,
+            None => return false,
+        };
+    }
+
+    true
+}
+
+/// Return the address of the first mapped page in the `size` bytes at `addr`
+///
+/// Returns `None`, if the whole range is unmapped.
+pub fn first_mapped(addr: VirtAddr, size: u64) -> Option<VirtAddr> {
+    let end = addr.as_u64().checked_add(size)?;
+    let page_table = SHIM_PAGETABLE.read();
+    let mut page = addr.align_down(Size4KiB::SIZE);
+
+    while page.as_u64() < end {
+        if let TranslateResult::Mapped { .. } = page_table.translate(page) {
+            return Some(page);
+        }
+
+        page = VirtAddr::try_new(page.as_u64().checked_add(Size4KiB::SIZE)?).ok()?;
+    }
+
+    None
+}
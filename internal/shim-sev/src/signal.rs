@@ -0,0 +1,16 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Signal handling of the exec
+//!
+//! The host forwards the signals it receives by failing a proxied syscall
+//! with `EINTR`. The shim then fetches them with
+//! [`SYS_ENARX_SIGNAL`](crate::hostcall::SYS_ENARX_SIGNAL) and delivers them
+//! on the return from that syscall. A workload busy computing without doing
+//! any syscalls therefore does not see its signals until its next syscall.
+
+pub use shim_common::signal::*;
+
+use crate::spin::Locked;
+
+/// The signal state of the exec
+pub static SIGNALS: Locked<Signals> = Locked::new(Signals::new());
@@ -7,8 +7,12 @@ use crate::allocator::ALLOCATOR;
 use crate::debug::_enarx_asm_triple_fault;
 use crate::eprintln;
 use crate::exec::{NEXT_BRK_RWLOCK, NEXT_MMAP_RWLOCK};
-use crate::hostcall::{HostCall, HOST_CALL_ALLOC};
-use crate::paging::SHIM_PAGETABLE;
+use crate::hostcall::{HostCall, HOST_CALL_ALLOC, SYS_ENARX_KEEP_CONFIG, SYS_ENARX_SIGNAL};
+use crate::paging::{first_mapped, is_user_mapped, SHIM_PAGETABLE};
+use crate::signal::{
+    default_ignored, FpState, SigAction, SigFrame, SigInfo, SigStack, UContext, NSIG, RED_ZONE,
+    SA_ONSTACK, SA_RESTART, SA_RESTORER, SIGNALS, SIGSET_SIZE, SIG_DFL, SIG_IGN,
+};
 
 use core::arch::asm;
 use core::convert::TryFrom;
@@ -26,16 +30,89 @@ use sallyport::syscall::{
 use sallyport::untrusted::{
     AddressValidator, UntrustedRef, UntrustedRefMut, Validate, ValidateSlice,
 };
-use sallyport::{Cursor, Request};
+use sallyport::{request, Block, Cursor, Request};
 use x86_64::instructions::segmentation::{Segment64, FS, GS};
 use x86_64::instructions::tlb::flush_all;
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::mapper::{FlagUpdateError, Mapper};
 use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
-use x86_64::{align_up, VirtAddr};
+use x86_64::{align_down, align_up, VirtAddr};
 
+/// The registers of the exec saved by [`_syscall_enter`]
+///
+/// Everything else is preserved by `syscall_rust` via the SYS-V ABI or
+/// clobbered by the `syscall` instruction anyway.
 #[repr(C)]
-struct X8664DoubleReturn {
+#[derive(Copy, Clone, Debug)]
+struct SyscallFrame {
+    /// The x87 and SSE state, saved by `fxsave64`
+    fpstate: FpState,
+    _align: u64,
     rax: u64,
+    r8: u64,
+    r9: u64,
+    r10: u64,
     rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rbp: u64,
+    rip: u64,
+    rflags: u64,
+    rsp: u64,
+}
+
+/// The flags of `rflags` a signal handler may change via `rt_sigreturn`
+const USER_RFLAGS: RFlags = RFlags::from_bits_truncate(
+    RFlags::CARRY_FLAG.bits()
+        | RFlags::PARITY_FLAG.bits()
+        | RFlags::AUXILIARY_CARRY_FLAG.bits()
+        | RFlags::ZERO_FLAG.bits()
+        | RFlags::SIGN_FLAG.bits()
+        | RFlags::DIRECTION_FLAG.bits()
+        | RFlags::OVERFLOW_FLAG.bits()
+        | RFlags::ALIGNMENT_CHECK.bits(),
+);
+
+impl SyscallFrame {
+    /// The registers as saved in a `ucontext_t`
+    fn gregs(&self) -> [u64; 23] {
+        let mut gregs = [0; 23];
+        gregs[libc::REG_R8 as usize] = self.r8;
+        gregs[libc::REG_R9 as usize] = self.r9;
+        gregs[libc::REG_R10 as usize] = self.r10;
+        gregs[libc::REG_RDI as usize] = self.rdi;
+        gregs[libc::REG_RSI as usize] = self.rsi;
+        gregs[libc::REG_RBP as usize] = self.rbp;
+        gregs[libc::REG_RDX as usize] = self.rdx;
+        gregs[libc::REG_RAX as usize] = self.rax;
+        gregs[libc::REG_RSP as usize] = self.rsp;
+        gregs[libc::REG_RIP as usize] = self.rip;
+        gregs[libc::REG_EFL as usize] = self.rflags;
+        gregs
+    }
+
+    /// Restore the registers from a `ucontext_t`, which is under control of the exec
+    fn set_gregs(&mut self, gregs: &[u64; 23]) {
+        self.r8 = gregs[libc::REG_R8 as usize];
+        self.r9 = gregs[libc::REG_R9 as usize];
+        self.r10 = gregs[libc::REG_R10 as usize];
+        self.rdi = gregs[libc::REG_RDI as usize];
+        self.rsi = gregs[libc::REG_RSI as usize];
+        self.rbp = gregs[libc::REG_RBP as usize];
+        self.rdx = gregs[libc::REG_RDX as usize];
+        self.rax = gregs[libc::REG_RAX as usize];
+        self.rsp = gregs[libc::REG_RSP as usize];
+        self.rip = gregs[libc::REG_RIP as usize];
+
+        let user = gregs[libc::REG_EFL as usize] & USER_RFLAGS.bits();
+        self.rflags = self.rflags & !USER_RFLAGS.bits() | user;
+    }
+
+    /// Re-execute the `syscall` instruction of syscall `nr` on return
+    fn restart(&mut self, nr: usize) {
+        self.rip = self.rip.wrapping_sub(2);
+        self.rax = nr as _;
+    }
 }
 
 /// syscall service routine
@@ -66,33 +143,34 @@ pub unsafe extern "sysv64" fn _syscall_enter() -> ! {
         "push   rbp",
         "mov    rbp,                    rsp",               // Save stack frame
 
-        // Arguments in registers:
-        // SYSV:    rdi, rsi, rdx, rcx, r8, r9
-        // SYSCALL: rdi, rsi, rdx, r10, r8, r9 and syscall number in rax
-        "mov    rcx,                    r10",
-
-        // These will be preserved by `syscall_rust` via the SYS-V ABI
-        // rbx, rsp, rbp, r12, r13, r14, r15
-
-        // save registers
+        // save the registers as `SyscallFrame`
         "push   rdi",
         "push   rsi",
+        "push   rdx",
         "push   r10",
         "push   r9",
         "push   r8",
+        "push   rax",                                       // syscall number
+        "sub    rsp,                    0x8",               // align the stack
+        "sub    rsp,                    {FPSTATE}",
+        "fxsave64 [rsp]",                                   // save the x87 and SSE state
 
-        // syscall number on the stack as the seventh argument
-        "push   rax",
+        // These will be preserved by `syscall_rust` via the SYS-V ABI
+        // rbx, rsp, rbp, r12, r13, r14, r15
 
+        "mov    rdi,                    rsp",               // `SyscallFrame` as first argument
         "call   {syscall_rust}",
 
-        // skip rax pop, as it is the return value
+        "fxrstor64 [rsp]",                                  // restore the x87 and SSE state
+        "add    rsp,                    {FPSTATE}",
         "add    rsp,                    0x8",
 
-        // restore registers
+        // restore registers, `rax` is the return value
+        "pop    rax",
         "pop    r8",
         "pop    r9",
         "pop    r10",
+        "pop    rdx",
         "pop    rsi",
         "pop    rdi",
 
@@ -112,6 +190,7 @@ pub unsafe extern "sysv64" fn _syscall_enter() -> ! {
 
         USR = const USR_RSP_OFF,
         KRN = const KERNEL_RSP_OFF,
+        FPSTATE = const size_of::<FpState>(),
 
         syscall_rust = sym syscall_rust,
 
@@ -155,35 +234,104 @@ impl ProxySyscall for Request {
 
 /// Handle a syscall in rust
 #[allow(clippy::many_single_char_names)]
-extern "sysv64" fn syscall_rust(
-    a: Register<usize>,
-    b: Register<usize>,
-    c: Register<usize>,
-    d: Register<usize>,
-    e: Register<usize>,
-    f: Register<usize>,
-    nr: usize,
-) -> X8664DoubleReturn {
-    let orig_rdx: usize = c.into();
+extern "sysv64" fn syscall_rust(frame: &mut SyscallFrame) {
+    let nr = frame.rax as usize;
+    let [a, b, c, d, e, f]: [Register<usize>; 6] = [
+        frame.rdi.into(),
+        frame.rsi.into(),
+        frame.rdx.into(),
+        frame.r10.into(),
+        frame.r8.into(),
+        frame.r9.into(),
+    ];
 
     let mut h = Handler {
         hostcall: HOST_CALL_ALLOC.try_alloc().unwrap(),
         argv: [a.into(), b.into(), c.into(), d.into(), e.into(), f.into()],
     };
 
-    let ret = h.syscall(a, b, c, d, e, f, nr);
+    let ret = match nr as i64 {
+        SYS_ENARX_KEEP_CONFIG => h.keep_config(a.into(), b.into(), c.into()),
+        libc::SYS_rt_sigaction => h.rt_sigaction(a.into(), b.into(), c.into(), d.into()),
+        libc::SYS_rt_sigprocmask => h.rt_sigprocmask(a.into(), b.into(), c.into(), d.into()),
+        libc::SYS_sigaltstack => h.sigaltstack(a.into(), b.into(), frame.rsp),
+        libc::SYS_rt_sigreturn => {
+            h.rt_sigreturn(frame);
+            h.deliver_signal(frame, None);
+            return;
+        }
+        _ => h.syscall(a, b, c, d, e, f, nr),
+    };
 
     match ret {
-        Err(e) => X8664DoubleReturn {
-            rax: e.checked_neg().unwrap() as _,
-            // Preserve `rdx` as it is normally not clobbered with a syscall
-            rdx: orig_rdx as _,
-        },
-        Ok([rax, rdx]) => X8664DoubleReturn {
-            rax: rax.into(),
-            rdx: rdx.into(),
-        },
+        // `rdx` is preserved, as it is normally not clobbered with a syscall
+        Err(e) => frame.rax = e.checked_neg().unwrap() as _,
+        Ok([rax, rdx]) => {
+            frame.rax = rax.into();
+            frame.rdx = rdx.into();
+        }
+    }
+
+    // The host interrupts a syscall, if it has signals for the exec
+    let interrupted = matches!(ret, Err(libc::EINTR));
+    if interrupted {
+        h.fetch_signals();
     }
+    h.deliver_signal(frame, interrupted.then(|| nr));
+}
+
+/// The end of the lower canonical half of the address space, available to the exec
+const USER_SPACE_END: u64 = 0x8000_0000_0000;
+
+/// Return `addr` as `VirtAddr`, if `size` bytes at `addr` fit in user space
+fn user_range(addr: u64, size: usize) -> Option<VirtAddr> {
+    match addr.checked_add(size as u64) {
+        Some(end) if addr != 0 && end <= USER_SPACE_END => Some(VirtAddr::new(addr)),
+        _ => None,
+    }
+}
+
+/// Find the next unmapped range of `size` bytes for `mmap`, starting at the mmap base
+fn next_mmap(size: usize) -> Result<VirtAddr, libc::c_int> {
+    let mut virt_addr = *NEXT_MMAP_RWLOCK.read().deref();
+
+    loop {
+        user_range(virt_addr.as_u64(), size).ok_or(libc::ENOMEM)?;
+
+        match first_mapped(virt_addr, size as _) {
+            None => return Ok(virt_addr),
+            Some(mapped) => virt_addr = mapped + Page::<Size4KiB>::SIZE,
+        }
+    }
+}
+
+/// Convert `PROT_*` flags to page table flags for user pages
+fn prot_flags(prot: i32) -> PageTableFlags {
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    if prot & libc::PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+
+    if prot & libc::PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    flags
+}
+
+/// Set the page table `flags` of all pages in the `len` bytes at `addr`
+fn update_flags(addr: VirtAddr, len: usize, flags: PageTableFlags) -> Result<(), FlagUpdateError> {
+    let start_page: Page = Page::containing_address(addr);
+    let end_page: Page = Page::containing_address(addr + len - 1u64);
+
+    for page in Page::range_inclusive(start_page, end_page) {
+        unsafe { SHIM_PAGETABLE.write().update_flags(page, flags)? }.ignore();
+    }
+
+    flush_all();
+
+    Ok(())
 }
 
 /// The syscall Handler
@@ -192,17 +340,322 @@ struct Handler {
     argv: [usize; 6],
 }
 
+impl Handler {
+    /// Copy the keep configuration supplied by the host into `buf`
+    ///
+    /// Returns the total size of the configuration, if `len` is zero,
+    /// otherwise the number of bytes copied, starting at `offset`.
+    fn keep_config(&mut self, buf: usize, len: usize, offset: usize) -> sallyport::Result {
+        self.trace("keep_config", 3);
+
+        if len == 0 {
+            let ret = unsafe { self.proxy(request!(SYS_ENARX_KEEP_CONFIG => 0, 0))? };
+            return Ok([ret[1], Default::default()]);
+        }
+
+        let buf = UntrustedRefMut::from(buf as *mut u8);
+        let buf = buf.validate_slice(len, self).ok_or(libc::EFAULT)?;
+
+        let count = len.min(Block::buf_capacity());
+        let ret = unsafe { self.proxy(request!(SYS_ENARX_KEEP_CONFIG => count, offset))? };
+
+        // be careful with `copied` as it is untrusted
+        let copied: usize = ret[0].into();
+        if copied > count {
+            self.attacked();
+        }
+
+        let c = self.new_cursor();
+        unsafe {
+            c.copy_into_slice(count, &mut buf[..copied])
+                .or(Err(libc::EFAULT))?;
+        }
+
+        Ok([copied.into(), Default::default()])
+    }
+
+    /// Read the host file `fd`, starting at `offset`, into `buf`
+    ///
+    /// The part of `buf` past the end of the file is left untouched.
+    fn pread_into(&mut self, fd: i32, buf: &mut [u8], offset: i64) -> Result<(), libc::c_int> {
+        let mut done = 0usize;
+
+        while done < buf.len() {
+            let count = buf[done..].len().min(Block::buf_capacity());
+            let pos = offset.checked_add(done as i64).ok_or(libc::EOVERFLOW)?;
+
+            let c = self.new_cursor();
+            let (_, hostbuf) = c.alloc::<u8>(count).or(Err(libc::EMSGSIZE))?;
+            let host_virt = Self::translate_shim_to_host_addr(hostbuf.as_ptr());
+
+            let ret = unsafe {
+                self.proxy(request!(libc::SYS_pread64 => fd, host_virt, count, pos as usize))?
+            };
+
+            // be careful with `read` as it is untrusted
+            let read: usize = ret[0].into();
+            if read > count {
+                self.attacked();
+            }
+            if read == 0 {
+                break;
+            }
+
+            let end = done.checked_add(read).ok_or(libc::EIO)?;
+            let c = self.new_cursor();
+            unsafe {
+                c.copy_into_slice(count, &mut buf[done..end])
+                    .or(Err(libc::EFAULT))?;
+            }
+
+            done = end;
+        }
+
+        Ok(())
+    }
+
+    /// Fetch the signals the host received for the exec
+    fn fetch_signals(&mut self) {
+        // The host hands out one signal at a time, but never more than exist
+        for _ in 0..NSIG {
+            let sig: usize = match unsafe { self.proxy(request!(SYS_ENARX_SIGNAL)) } {
+                Ok(ret) => ret[0].into(),
+                Err(_) => break,
+            };
+
+            if sig == 0 {
+                break;
+            }
+
+            SIGNALS.lock().raise(sig);
+        }
+    }
+
+    /// Deliver the next pending, unblocked signal on the return to the exec
+    ///
+    /// `nr` is the number of the syscall, which was interrupted by the host
+    /// to deliver signals. It is restarted, if the signals are ignored or
+    /// their handler asks for it.
+    fn deliver_signal(&mut self, frame: &mut SyscallFrame, mut nr: Option<usize>) {
+        loop {
+            let next = SIGNALS.lock().next_pending();
+            let (sig, action) = match next {
+                Some(next) => next,
+                None => break,
+            };
+
+            match action.handler {
+                SIG_DFL if default_ignored(sig) => continue,
+                SIG_DFL => self.terminate(sig),
+                SIG_IGN => continue,
+                _ => {}
+            }
+
+            let restart = nr.take().filter(|_| action.flags & SA_RESTART != 0);
+            if self
+                .push_signal_frame(frame, sig, &action, restart)
+                .is_none()
+            {
+                self.terminate(libc::SIGSEGV as _);
+            }
+
+            return;
+        }
+
+        if let Some(nr) = nr {
+            frame.restart(nr);
+        }
+    }
+
+    /// Return to the handler of `sig` with a signal frame on the stack of the exec
+    fn push_signal_frame(
+        &mut self,
+        frame: &mut SyscallFrame,
+        sig: usize,
+        action: &SigAction,
+        restart: Option<usize>,
+    ) -> Option<()> {
+        if action.flags & SA_RESTORER == 0 {
+            return None;
+        }
+
+        let mut saved = *frame;
+        if let Some(nr) = restart {
+            saved.restart(nr);
+        }
+
+        let mut signals = SIGNALS.lock();
+        let altstack = signals.altstack(frame.rsp);
+
+        let top = if action.flags & SA_ONSTACK != 0 && altstack.flags == 0 {
+            altstack.sp.checked_add(altstack.size)?
+        } else {
+            frame.rsp.checked_sub(RED_ZONE)?
+        };
+
+        // Like after a `call`, the stack is aligned for the handler
+        let sp = top.checked_sub(size_of::<SigFrame>() as u64)?;
+        let sp = align_down(sp, 16).checked_sub(size_of::<u64>() as u64)?;
+
+        let sigframe = UntrustedRefMut::from(sp as *mut SigFrame);
+        let sigframe = sigframe.validate(self)?;
+
+        *sigframe = SigFrame {
+            restorer: action.restorer,
+            uc: UContext::new(altstack, saved.gregs(), 0, signals.mask()),
+            info: SigInfo::new(sig),
+            fpstate: frame.fpstate,
+        };
+        sigframe.uc.fpregs = &sigframe.fpstate as *const FpState as _;
+        signals.enter(sig, action);
+
+        frame.rip = action.handler;
+        frame.rsp = sp;
+        frame.rdi = sig as _;
+        frame.rsi = &sigframe.info as *const SigInfo as _;
+        frame.rdx = &sigframe.uc as *const UContext as _;
+        frame.rax = 0;
+        frame.rflags &= !(RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG).bits();
+
+        Some(())
+    }
+
+    /// Return from a signal handler to the code it interrupted
+    fn rt_sigreturn(&mut self, frame: &mut SyscallFrame) {
+        self.trace("rt_sigreturn", 0);
+
+        // The handler returned to the restorer, which popped the return address
+        let uc = UntrustedRef::from(frame.rsp as *const UContext);
+        let uc = match uc.validate(self) {
+            Some(uc) => *uc,
+            None => self.terminate(libc::SIGSEGV as _),
+        };
+
+        if user_range(uc.gregs[libc::REG_RIP as usize], 1).is_none() {
+            self.terminate(libc::SIGSEGV as _);
+        }
+
+        // Without a saved state, the state is reset like on Linux
+        let mut fpstate = match uc.fpregs {
+            0 => FpState::new(),
+            fpregs => match UntrustedRef::from(fpregs as *const FpState).validate(self) {
+                Some(fpstate) => *fpstate,
+                None => self.terminate(libc::SIGSEGV as _),
+            },
+        };
+        fpstate.sanitize();
+
+        frame.set_gregs(&uc.gregs);
+        frame.fpstate = fpstate;
+        SIGNALS.lock().set_mask(uc.mask);
+    }
+
+    /// Terminate the exec like the default action of `sig`
+    ///
+    /// The exit status is the one a shell reports for a process killed by `sig`.
+    fn terminate(&mut self, sig: usize) -> ! {
+        let status = sig.wrapping_add(128);
+        loop {
+            let _ = unsafe { self.proxy(request!(libc::SYS_exit_group => status)) };
+        }
+    }
+
+    /// Do an `rt_sigaction()` syscall
+    fn rt_sigaction(
+        &mut self,
+        sig: usize,
+        act: usize,
+        oldact: usize,
+        size: usize,
+    ) -> sallyport::Result {
+        self.trace("rt_sigaction", 4);
+
+        if size != SIGSET_SIZE {
+            return Err(libc::EINVAL);
+        }
+
+        let act = match act {
+            0 => None,
+            act => {
+                let act = UntrustedRef::from(act as *const SigAction);
+                Some(*act.validate(self).ok_or(libc::EFAULT)?)
+            }
+        };
+
+        let old = SIGNALS.lock().action(sig, act)?;
+
+        if oldact != 0 {
+            let oldact = UntrustedRefMut::from(oldact as *mut SigAction);
+            *oldact.validate(self).ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+
+    /// Do an `rt_sigprocmask()` syscall
+    fn rt_sigprocmask(
+        &mut self,
+        how: libc::c_int,
+        set: usize,
+        oldset: usize,
+        size: usize,
+    ) -> sallyport::Result {
+        self.trace("rt_sigprocmask", 4);
+
+        if size != SIGSET_SIZE {
+            return Err(libc::EINVAL);
+        }
+
+        let set = match set {
+            0 => None,
+            set => {
+                let set = UntrustedRef::from(set as *const u64);
+                Some(*set.validate(self).ok_or(libc::EFAULT)?)
+            }
+        };
+
+        let old = SIGNALS.lock().procmask(how, set)?;
+
+        if oldset != 0 {
+            let oldset = UntrustedRefMut::from(oldset as *mut u64);
+            *oldset.validate(self).ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+
+    /// Do a `sigaltstack()` syscall from code running at `sp`
+    fn sigaltstack(&mut self, ss: usize, old_ss: usize, sp: u64) -> sallyport::Result {
+        self.trace("sigaltstack", 2);
+
+        let ss = match ss {
+            0 => None,
+            ss => {
+                let ss = UntrustedRef::from(ss as *const SigStack);
+                Some(*ss.validate(self).ok_or(libc::EFAULT)?)
+            }
+        };
+
+        let old = SIGNALS.lock().set_altstack(ss, sp)?;
+
+        if old_ss != 0 {
+            let old_ss = UntrustedRefMut::from(old_ss as *mut SigStack);
+            *old_ss.validate(self).ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+}
+
 impl AddressValidator for Handler {
     #[inline(always)]
-    fn validate_const_mem_fn(&self, _ptr: *const (), _size: usize) -> bool {
-        // FIXME: https://github.com/enarx/enarx/issues/630
-        true
+    fn validate_const_mem_fn(&self, ptr: *const (), size: usize) -> bool {
+        is_user_mapped(ptr as _, size, false)
     }
 
     #[inline(always)]
-    fn validate_mut_mem_fn(&self, _ptr: *mut (), _size: usize) -> bool {
-        // FIXME: https://github.com/enarx/enarx/issues/630
-        true
+    fn validate_mut_mem_fn(&self, ptr: *mut (), size: usize) -> bool {
+        is_user_mapped(ptr as _, size, true)
     }
 }
 
@@ -340,39 +793,13 @@ impl MemorySyscallHandler for Handler {
         self.trace("mprotect", 3);
         let addr = addr.as_ptr();
 
-        use x86_64::structures::paging::mapper::Mapper;
-
-        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
-
-        if prot & libc::PROT_WRITE != 0 {
-            flags |= PageTableFlags::WRITABLE;
-        }
-
-        if prot & libc::PROT_EXEC == 0 {
-            flags |= PageTableFlags::NO_EXECUTE;
-        }
-
-        let start_addr = VirtAddr::from_ptr(addr);
-        let start_page: Page = Page::containing_address(start_addr);
-        let end_page: Page = Page::containing_address(start_addr + len - 1u64);
-        let page_range = Page::range_inclusive(start_page, end_page);
-        for page in page_range {
-            unsafe {
-                let ret = SHIM_PAGETABLE.write().update_flags(page, flags);
-                match ret {
-                    Ok(flush) => flush.ignore(),
-                    Err(e) => {
-                        eprintln!(
-                            "SC> mprotect({:#?}, {}, {}, …) = EINVAL ({:#?})",
-                            addr, len, prot, e
-                        );
-                        return Err(libc::EINVAL);
-                    }
-                }
-            }
-        }
-
-        flush_all();
+        update_flags(VirtAddr::from_ptr(addr), len, prot_flags(prot)).map_err(|e| {
+            eprintln!(
+                "SC> mprotect({:#?}, {}, {}, …) = EINVAL ({:#?})",
+                addr, len, prot, e
+            );
+            libc::EINVAL
+        })?;
 
         eprintln!("SC> mprotect({:#?}, {}, {}, …) = 0", addr, len, prot);
 
@@ -390,64 +817,138 @@ impl MemorySyscallHandler for Handler {
     ) -> sallyport::Result {
         self.trace("mmap", 6);
 
-        const PA: i32 = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+        let hint = addr.as_ptr() as u64;
+        let anonymous = flags & libc::MAP_ANONYMOUS != 0;
+
+        // `MAP_NORESERVE` needs no handling, as all memory is allocated upfront.
+        // Shared anonymous memory is private to the keep anyway.
+        match flags & (libc::MAP_PRIVATE | libc::MAP_SHARED) {
+            libc::MAP_PRIVATE => {}
+            libc::MAP_SHARED if anonymous => {}
+            // The host must never see the contents of file mappings
+            libc::MAP_SHARED => return Err(libc::ENODEV),
+            _ => return Err(libc::EINVAL),
+        }
 
-        match (addr.as_ptr(), length, prot, flags, fd, offset) {
-            (ptr, _, _, PA, -1, 0) if ptr.is_null() => {
-                let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if length == 0 {
+            return Err(libc::EINVAL);
+        }
 
-                if prot & libc::PROT_WRITE != 0 {
-                    flags |= PageTableFlags::WRITABLE;
-                }
+        if !anonymous
+            && (offset < 0 || align_down(offset as _, Page::<Size4KiB>::SIZE) != offset as u64)
+        {
+            return Err(libc::EINVAL);
+        }
 
-                if prot & libc::PROT_EXEC == 0 {
-                    flags |= PageTableFlags::NO_EXECUTE;
-                }
+        if length as u64 > USER_SPACE_END {
+            return Err(libc::ENOMEM);
+        }
+        let len_aligned = align_up(length as _, Page::<Size4KiB>::SIZE) as usize;
 
-                let virt_addr = *NEXT_MMAP_RWLOCK.read().deref();
-                let len_aligned = align_up(length as _, Page::<Size4KiB>::SIZE) as _;
+        let virt_addr = if flags & (libc::MAP_FIXED | libc::MAP_FIXED_NOREPLACE) != 0 {
+            if !VirtAddr::new_truncate(hint).is_aligned(Page::<Size4KiB>::SIZE) {
+                return Err(libc::EINVAL);
+            }
 
-                let mem_slice = ALLOCATOR
-                    .write()
-                    .allocate_and_map_memory(
-                        virt_addr,
-                        len_aligned,
-                        flags,
-                        PageTableFlags::PRESENT
-                            | PageTableFlags::WRITABLE
-                            | PageTableFlags::USER_ACCESSIBLE,
-                    )
-                    .map_err(|_| {
-                        eprintln!("SC> mmap(0, {}, …) = ENOMEM", length);
-                        libc::ENOMEM
-                    })?;
-                eprintln!("SC> mmap(0, {}, …) = {:#?}", length, mem_slice.as_ptr());
-                unsafe {
-                    core::ptr::write_bytes(mem_slice.as_mut_ptr(), 0, length);
+            let virt_addr = user_range(hint, len_aligned).ok_or(libc::ENOMEM)?;
+
+            if first_mapped(virt_addr, len_aligned as _).is_some() {
+                if flags & libc::MAP_FIXED_NOREPLACE != 0 {
+                    eprintln!("SC> mmap({:#x}, {}, …) = EEXIST", hint, length);
+                    return Err(libc::EEXIST);
                 }
-                *NEXT_MMAP_RWLOCK.write().deref_mut() = virt_addr + (len_aligned as u64);
 
-                //eprintln!("next_mmap = {:#x}", *NEXT_MMAP_RWLOCK::read().deref());
+                ALLOCATOR
+                    .write()
+                    .unmap_memory(virt_addr, len_aligned)
+                    .map_err(|_| libc::EINVAL)?;
+            }
 
-                Ok([mem_slice.as_ptr().into(), Default::default()])
+            virt_addr
+        } else {
+            // Honor the hint, if the pages are free, else take the next free range
+            let hint = VirtAddr::new_truncate(hint).align_down(Page::<Size4KiB>::SIZE);
+
+            match user_range(hint.as_u64(), len_aligned) {
+                Some(hint) if first_mapped(hint, len_aligned as _).is_none() => hint,
+                _ => next_mmap(len_aligned)?,
             }
-            (addr, ..) => {
-                eprintln!("SC> mmap({:#?}, {}, …)", addr, length);
-                unimplemented!()
+        };
+
+        let mut page_flags = prot_flags(prot);
+
+        // The file contents have to be written by the shim first
+        if !anonymous {
+            page_flags |= PageTableFlags::WRITABLE;
+        }
+
+        let mem_slice = ALLOCATOR
+            .write()
+            .allocate_and_map_memory(
+                virt_addr,
+                len_aligned,
+                page_flags,
+                PageTableFlags::PRESENT
+                    | PageTableFlags::WRITABLE
+                    | PageTableFlags::USER_ACCESSIBLE,
+            )
+            .map_err(|_| {
+                eprintln!("SC> mmap({:#x}, {}, …) = ENOMEM", hint, length);
+                libc::ENOMEM
+            })?;
+
+        unsafe {
+            core::ptr::write_bytes(mem_slice.as_mut_ptr(), 0, len_aligned);
+        }
+
+        if !anonymous {
+            let filled = self
+                .pread_into(fd, &mut mem_slice[..length], offset)
+                .and_then(|_| {
+                    update_flags(virt_addr, len_aligned, prot_flags(prot)).map_err(|_| libc::EINVAL)
+                });
+
+            if let Err(e) = filled {
+                let _ = ALLOCATOR.write().unmap_memory(virt_addr, len_aligned);
+                eprintln!("SC> mmap({:#x}, {}, …) = {}", hint, length, e);
+                return Err(e);
             }
         }
+
+        let mut next_mmap = NEXT_MMAP_RWLOCK.write();
+        if virt_addr >= *next_mmap.deref() {
+            *next_mmap.deref_mut() = virt_addr + (len_aligned as u64);
+        }
+
+        eprintln!(
+            "SC> mmap({:#x}, {}, …) = {:#?}",
+            hint,
+            length,
+            mem_slice.as_ptr()
+        );
+
+        Ok([mem_slice.as_ptr().into(), Default::default()])
     }
 
     fn munmap(&mut self, addr: UntrustedRef<'_, u8>, length: usize) -> sallyport::Result {
         self.trace("munmap", 2);
 
-        let addr = addr.validate_slice(length, self).ok_or(libc::EINVAL)?;
+        // Like Linux, unmapped pages in the range are fine, an unaligned start is not
+        let addr = addr.as_ptr() as u64;
+        if length == 0 || !VirtAddr::new_truncate(addr).is_aligned(Page::<Size4KiB>::SIZE) {
+            return Err(libc::EINVAL);
+        }
+        let virt_addr = user_range(addr, length).ok_or(libc::EINVAL)?;
+
+        let mut allocator = ALLOCATOR.write();
 
-        ALLOCATOR
-            .write()
-            .unmap_memory(VirtAddr::from_ptr(addr.as_ptr()), length)
+        allocator
+            .unmap_memory(virt_addr, length)
             .map_err(|_| libc::EINVAL)?;
 
+        // Give memory back to the host, which is not needed anymore
+        allocator.deflate();
+
         Ok(Default::default())
     }
 
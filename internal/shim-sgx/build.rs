@@ -1,5 +1,40 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use std::path::PathBuf;
+
+/// The default number of heap pages (128 MiB)
+const HEAP_PAGES_DEFAULT: usize = 32768;
+
+/// The smallest enclave size (2 GiB)
+const ENCL_SIZE_BITS_MIN: u8 = 31;
+
 fn main() {
     println!("cargo:rerun-if-changed=layout.ld");
+    println!("cargo:rerun-if-env-changed=ENARX_SGX_HEAP_PAGES");
+
+    let heap_pages = match std::env::var("ENARX_SGX_HEAP_PAGES") {
+        Ok(pages) => pages
+            .parse()
+            .expect("ENARX_SGX_HEAP_PAGES must be a number of pages"),
+        Err(_) => HEAP_PAGES_DEFAULT,
+    };
+
+    // Leave at least as much room for the shim and the exec as for the heap
+    let mut encl_size_bits = ENCL_SIZE_BITS_MIN;
+    while (1usize << encl_size_bits) / 2 < heap_pages * 4096 {
+        encl_size_bits += 1;
+    }
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    std::fs::write(
+        out_dir.join("size.rs"),
+        format!(
+            "/// The number of heap pages, set with `ENARX_SGX_HEAP_PAGES` at build time\n\
+             pub const HEAP_PAGES: usize = {};\n\
+             /// The log2 of the enclave size, large enough for the heap\n\
+             pub const ENCL_SIZE_BITS: u8 = {};\n",
+            heap_pages, encl_size_bits
+        ),
+    )
+    .unwrap();
 }
@@ -2,10 +2,66 @@
 
 //! FIXME: add docs
 
+use crate::handler::SYS_ENARX_EXEC_ARGS;
+
 use core::arch::asm;
 
 use crt0stack::{Builder, Entry, Handle, OutOfSpace};
 use goblin::elf::header::{header64::Header, ELFMAG};
+use shim_common::exec_args::{ExecArgs, EXEC_ARGS_DEFAULT, EXEC_ARGS_MAX};
+
+/// The size of the buffer the crt0 stack is built in
+///
+/// The buffer is the top of the stack of the exec, so it is kept small. It
+/// holds the arguments and environment, a pointer to each of them and the aux
+/// vector. `EXEC_ARGS_MAX` bytes of strings fit, if they are 7 bytes long on
+/// average, including the NUL; if they do not fit, the exec fails to start.
+const CRT0_SIZE: usize = 2 * EXEC_ARGS_MAX + 4096;
+
+/// The arguments and environment of the exec as fetched from the host
+///
+/// Kept off the stack, which is shared with the exec.
+static mut EXEC_ARGS: [u8; EXEC_ARGS_MAX] = [0; EXEC_ARGS_MAX];
+
+fn syscall(nr: i64, a: usize, b: usize, c: usize) -> isize {
+    let ret: isize;
+
+    // The syscall traps and is handled by the shim itself.
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") nr as isize => ret,
+            in("rdi") a,
+            in("rsi") b,
+            in("rdx") c,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack)
+        );
+    }
+
+    ret
+}
+
+/// Fetch the arguments and environment of the exec from the host into `buf`
+fn fetch_exec_args(buf: &mut [u8]) -> Option<&[u8]> {
+    let total = usize::try_from(syscall(SYS_ENARX_EXEC_ARGS, 0, 0, 0)).ok()?;
+    if total > buf.len() {
+        return None;
+    }
+
+    let mut len = 0;
+    while len < total {
+        let rest = &mut buf[len..total];
+        let ret = syscall(SYS_ENARX_EXEC_ARGS, rest.as_mut_ptr() as _, rest.len(), len);
+        match usize::try_from(ret) {
+            Ok(n) if n > 0 && n <= rest.len() => len += n,
+            _ => return None,
+        }
+    }
+
+    Some(&buf[..total])
+}
 
 fn exit(code: usize) -> ! {
     loop {
@@ -35,29 +91,22 @@ fn crt0setup<'a>(
     hdr: &Header,
     crt0: &'a mut [u8],
     off: *const (),
+    exec_args: &ExecArgs<'_>,
 ) -> Result<Handle<'a>, OutOfSpace> {
     let rand = unsafe { core::mem::transmute([random(), random()]) };
     let phdr = off as u64 + hdr.e_phoff;
 
     // Set the arguments
     let mut builder = Builder::new(crt0);
-    builder.push("/init")?;
+    for arg in exec_args.args() {
+        builder.push(arg)?;
+    }
 
     // Set the environment
     let mut builder = builder.done()?;
-    builder.push("LANG=C")?;
-    // FIXME - v0.1.0 KEEP-CONFIG HACK
-    // We don't yet have a well-defined way to pass runtime configuration from
-    // the frontend/CLI into the keep. This is a hack to simulate that process.
-    // For v0.1.0 the keep configuration is hardcoded as follows:
-    //   * the .wasm module is open on fd3 and gets no arguments or env vars
-    //   * stdin, stdout, and stderr are enabled and should go to fd 0,1,2
-    //   * logging should be turned on at "debug" level
-    // This is one possible way we could provide that information to the code
-    // inside the keep. The actual implementation may be completely different.
-    builder.push("ENARX_STDIO_FDS=0,1,2")?;
-    builder.push("ENARX_MODULE_FD=3")?;
-    builder.push("RUST_LOG=enarx=debug,wasmldr=debug")?;
+    for var in exec_args.env() {
+        builder.push(var)?;
+    }
 
     // Set the aux vector
     let mut builder = builder.done()?;
@@ -86,6 +135,7 @@ fn crt0setup<'a>(
 /// # Safety
 ///
 /// The caller has to ensure `offset` points to a valid, aligned Elf header and is non-null.
+/// It must be called only once.
 pub unsafe fn entry(offset: *const ()) -> ! {
     // Validate the ELF header.
     let hdr = &*(offset as *const Header);
@@ -93,10 +143,18 @@ pub unsafe fn entry(offset: *const ()) -> ! {
         exit(1);
     }
 
+    let exec_args = match fetch_exec_args(&mut EXEC_ARGS)
+        .and_then(ExecArgs::parse)
+        .or_else(|| ExecArgs::parse(EXEC_ARGS_DEFAULT))
+    {
+        Some(exec_args) => exec_args,
+        None => exit(1),
+    };
+
     // Prepare the crt0 stack.
-    let mut crt0 = [0u8; 1024];
+    let mut crt0 = [0u8; CRT0_SIZE];
     let space = random() as usize & 0xf0;
-    let handle = match crt0setup(hdr, &mut crt0[space..], offset) {
+    let handle = match crt0setup(hdr, &mut crt0[space..], offset, &exec_args) {
         Err(OutOfSpace) => exit(1),
         Ok(handle) => handle,
     };
@@ -34,7 +34,7 @@ impl<'a> BaseSyscallHandler for super::Handler<'a> {
     /// tripping the circuit breaker causes the enclave to immediately
     /// EEXIT.
     fn attacked(&mut self) -> ! {
-        self.exit(1)
+        self.shim_exit(1)
     }
 
     #[inline]
@@ -1,8 +1,134 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use sallyport::syscall::{BaseSyscallHandler, EnarxSyscallHandler, SGX_QUOTE_SIZE, SGX_TECH};
+use core::arch::asm;
+
+use sallyport::syscall::{
+    BaseSyscallHandler, EnarxSyscallHandler, SGX_QUOTE_SIZE, SGX_TECH, SYS_ENARX_GETATT,
+};
 use sallyport::untrusted::{UntrustedRef, UntrustedRefMut, ValidateSlice};
-use sallyport::Reply;
+use sallyport::{request, Block};
+use sgx::enclu::EREPORT;
+
+/// Fetch the keep configuration from the host
+///
+/// Arguments: the number of bytes to fetch and the offset into the
+/// configuration. The host replies with the number of bytes copied to the
+/// block buffer and the total size of the configuration.
+pub const SYS_ENARX_KEEP_CONFIG: i64 = 0xEA10;
+
+/// Terminate the keep, because the shim failed
+///
+/// Arguments: the exit status. Unlike `exit_group`, this tells the host the
+/// keep crashed rather than the workload exiting.
+pub const SYS_ENARX_SHIM_EXIT: i64 = 0xEA12;
+
+/// Fetch the arguments and environment of the exec from the host
+///
+/// Arguments and reply like for `SYS_ENARX_KEEP_CONFIG`.
+pub const SYS_ENARX_EXEC_ARGS: i64 = 0xEA13;
+
+/// Fetch a signal the host received for the exec
+///
+/// The host replies with the number of a pending signal, which it then
+/// forgets, or zero. It fails proxied syscalls with `EINTR` to announce
+/// pending signals.
+pub const SYS_ENARX_SIGNAL: i64 = 0xEA15;
+
+const TARGET_INFO_SIZE: usize = 512;
+const REPORT_DATA_SIZE: usize = 64;
+const REPORT_SIZE: usize = 432;
+
+/// The `TARGETINFO` structure consumed by `EREPORT`
+#[repr(C, align(512))]
+struct TargetInfo([u8; TARGET_INFO_SIZE]);
+
+/// The `REPORTDATA` structure consumed by `EREPORT`
+#[repr(C, align(128))]
+struct ReportData([u8; REPORT_DATA_SIZE]);
+
+/// The `REPORT` structure produced by `EREPORT`
+#[repr(C, align(512))]
+struct Report([u8; REPORT_SIZE]);
+
+impl TargetInfo {
+    /// Build a `TARGETINFO` from the bytes supplied by the host
+    ///
+    /// Only the measurement, attributes, config SVN, MISCSELECT and config
+    /// ID are taken over; all reserved fields are zeroed.
+    fn new(raw: &[u8; TARGET_INFO_SIZE]) -> Self {
+        let mut ti = Self([0u8; TARGET_INFO_SIZE]);
+        ti.0[..56].copy_from_slice(&raw[..56]);
+        ti.0[64..128].copy_from_slice(&raw[64..128]);
+        ti
+    }
+
+    /// Create a `REPORT` for the enclave described by `self`
+    fn report(&self, data: &ReportData) -> Report {
+        let mut report = Report([0u8; REPORT_SIZE]);
+
+        // EREPORT: rbx = &TARGETINFO, rcx = &REPORTDATA, rdx = &REPORT
+        //
+        // `rbx` is reserved by LLVM, so it is swapped in and restored by hand.
+        unsafe {
+            asm!(
+                "xchg {ti}, rbx",
+                "enclu",
+                "mov rbx, {ti}",
+                ti = inout(reg) self => _,
+                in("rax") EREPORT,
+                in("rcx") data,
+                in("rdx") &mut report,
+                options(nostack),
+            );
+        }
+
+        report
+    }
+}
+
+impl<'a> super::Handler<'a> {
+    /// Copy the data supplied by the host for the sallyport call `num` into `buf`
+    ///
+    /// Used for `SYS_ENARX_KEEP_CONFIG` and `SYS_ENARX_EXEC_ARGS`. Returns
+    /// the total size of the data, if `len` is zero, otherwise the number of
+    /// bytes copied, starting at `offset`.
+    pub(super) fn host_data(
+        &mut self,
+        num: i64,
+        buf: usize,
+        len: usize,
+        offset: usize,
+    ) -> sallyport::Result {
+        match num {
+            SYS_ENARX_EXEC_ARGS => self.trace("exec_args", 3),
+            _ => self.trace("keep_config", 3),
+        }
+
+        if len == 0 {
+            let ret = unsafe { self.proxy(request!(num => 0, 0))? };
+            return Ok([ret[1], Default::default()]);
+        }
+
+        let buf = UntrustedRefMut::from(buf as *mut u8);
+        let buf = buf.validate_slice(len, self).ok_or(libc::EFAULT)?;
+
+        let count = len.min(Block::buf_capacity());
+        let ret = unsafe { self.proxy(request!(num => count, offset))? };
+
+        let copied: usize = ret[0].into();
+        if copied > count {
+            self.attacked();
+        }
+
+        let c = self.new_cursor();
+        unsafe {
+            c.copy_into_slice(count, &mut buf[..copied])
+                .or(Err(libc::EFAULT))?;
+        }
+
+        Ok([copied.into(), Default::default()])
+    }
+}
 
 impl<'a> EnarxSyscallHandler for super::Handler<'a> {
     // NOTE: The 'nonce' field is called 'hash' here, as it is used to pass in
@@ -12,109 +138,90 @@ impl<'a> EnarxSyscallHandler for super::Handler<'a> {
         &mut self,
         hash: UntrustedRef<'_, u8>,
         hash_len: libc::size_t,
-        _buf: UntrustedRefMut<'_, u8>,
-        _buf_len: libc::size_t,
+        buf: UntrustedRefMut<'_, u8>,
+        buf_len: libc::size_t,
     ) -> sallyport::Result {
-        self.trace("get_att", 0);
-
-        match hash.validate_slice(hash_len, self) {
-            None => Reply::from(Ok([SGX_QUOTE_SIZE.into(), SGX_TECH.into()])),
-            Some(..) => Reply::from(Err(libc::ENOSYS)),
-        }
-        .into()
+        self.trace("get_att", 4);
 
-        /*
         // If hash is NULL ptr, it is a Quote size request; return expected Quote size
         // without proxying to host. Otherwise get hash value.
         let hash = match hash.validate_slice(hash_len, self) {
-            None => {
-                let rep: sallyport::Reply = Ok([SGX_QUOTE_SIZE.into(), SGX_TECH.into()]).into();
-                return sallyport::Result::from(rep);
-            }
+            None => return Ok([SGX_QUOTE_SIZE.into(), SGX_TECH.into()]),
+            Some(h) if h.len() != REPORT_DATA_SIZE => return Err(libc::EINVAL),
             Some(h) => {
-                if h.len() != 64 {
-                    return Err(libc::EINVAL);
-                }
-                let mut hash = [0u8; 64];
-                hash.copy_from_slice(h);
-                hash
+                let mut data = ReportData([0u8; REPORT_DATA_SIZE]);
+                data.0.copy_from_slice(h);
+                data
             }
         };
 
-        // Used internally for buffer size to host when getting TargetInfo
-        const REPORT_LEN: usize = 512;
-
         // Validate output buf memory
         let buf = buf.validate_slice(buf_len, self).ok_or(libc::EFAULT)?;
 
-        // Request TargetInfo from host by passing nonce as 0
-        let c = self.new_cursor();
-        let (_, shim_buf_ptr) = c.alloc::<u8>(buf_len).or(Err(libc::EMSGSIZE))?;
-        let req = request!(SYS_ENARX_GETATT => 0, 0, shim_buf_ptr.as_ptr(), REPORT_LEN);
-        unsafe { self.proxy(req)? };
-
-        // Retrieve TargetInfo from sallyport block and call EREPORT to
-        // create Report from TargetInfo.
-        let mut ti = [0u8; 512];
-        let ti_len = ti.len();
-        let c = self.new_cursor();
+        // Request the TargetInfo of the quoting enclave from the host
+        let req = request!(SYS_ENARX_GETATT => 0, TARGET_INFO_SIZE, 0);
+        let ret = unsafe { self.proxy(req)? };
 
-        unsafe {
-            c.copy_into_slice(buf_len, &mut ti[..ti_len])
-                .or(Err(libc::EFAULT))?;
-        }
+        let ti = match usize::from(ret[0]) {
+            // The host has no quoting enclave; we cannot produce a Quote.
+            0 => return Err(libc::ENOSYS),
 
-        // Cannot generate a Report from dummy values
-        if ti.eq(&SGX_DUMMY_TI) {
-            buf.copy_from_slice(&SGX_DUMMY_QUOTE);
-            let rep: sallyport::Reply = Ok([SGX_QUOTE_SIZE.into(), SGX_TECH.into()]).into();
-            return sallyport::Result::from(rep);
-        }
+            TARGET_INFO_SIZE => {
+                let mut raw = [0u8; TARGET_INFO_SIZE];
+                let c = self.new_cursor();
+                unsafe {
+                    c.copy_into_slice(TARGET_INFO_SIZE, &mut raw[..])
+                        .or(Err(libc::EFAULT))?;
+                }
+                TargetInfo::new(&raw)
+            }
 
-        // Generate Report
-        let mut target_info: TargetInfo = Default::default();
-        let mut f = [0u8; 8];
-        let mut x = [0u8; 8];
-        f.copy_from_slice(&ti[32..40]);
-        x.copy_from_slice(&ti[40..48]);
-        let f = u64::from_le_bytes(f);
-        let x = u64::from_le_bytes(x);
-        let att = Attributes::new(
-            Flags::from_bits(f).ok_or(libc::EBADMSG)?,
-            Xfrm::from_bits(x).ok_or(libc::EBADMSG)?,
-        );
-        target_info.mrenclave.copy_from_slice(&ti[0..32]);
-        target_info.attributes = att;
-        let report: Report = unsafe { target_info.get_report(&ReportData(hash)) };
-
-        // Request Quote from host
-        let report_slice = &[report];
-        let report_bytes = unsafe {
-            core::slice::from_raw_parts(
-                report_slice.as_ptr() as *const _ as *const u8,
-                core::mem::size_of::<Report>(),
-            )
+            _ => self.attacked(),
         };
 
-        let c = self.new_cursor();
-        let (c, shim_nonce_ptr) = c.copy_from_slice(report_bytes).or(Err(libc::EMSGSIZE))?;
-        let (_, shim_buf_ptr) = c.alloc::<u8>(buf_len).or(Err(libc::EMSGSIZE))?;
-        let req = request!(SYS_ENARX_GETATT => shim_nonce_ptr.as_ptr(), report_bytes.len(), shim_buf_ptr.as_ptr(), buf_len);
-        let result = unsafe { self.proxy(req)? };
+        // Create the Report targeted at the quoting enclave and hand it to
+        // the host to be turned into a Quote.
+        let report = ti.report(&hash);
 
-        // Pass Quote back to code layer in buf
         let c = self.new_cursor();
-        let (c, _) = c.alloc::<u8>(report_bytes.len()).or(Err(libc::EMSGSIZE))?;
+        c.copy_from_slice(&report.0).or(Err(libc::EMSGSIZE))?;
+
+        // The host keeps the Quote, which is fetched block by block, like
+        // the keep configuration.
+        let mut len = 0;
+        loop {
+            let count = (buf.len() - len).min(Block::buf_capacity());
+            let req = match len {
+                0 => request!(SYS_ENARX_GETATT => REPORT_SIZE, count, 0),
+                offset => request!(SYS_ENARX_GETATT => 0, count, offset),
+            };
+            let ret = unsafe { self.proxy(req)? };
+
+            let copied: usize = ret[0].into();
+            let total: usize = ret[1].into();
+            if copied > count || len + copied > total {
+                self.attacked();
+            }
+            if total > buf.len() {
+                return Err(libc::EMSGSIZE);
+            }
 
-        let result_len: usize = result[0].into();
-        if result_len > buf_len {
-            self.attacked()
-        }
+            // Pass Quote back to code layer in buf
+            let c = self.new_cursor();
+            unsafe {
+                c.copy_into_slice(count, &mut buf[len..][..copied])
+                    .or(Err(libc::EFAULT))?;
+            }
+            len += copied;
 
-        unsafe {
-            c.copy_into_slice(buf_len, &mut buf[..result_len])
-                .or(Err(libc::EFAULT))?;
+            if len == total {
+                break;
+            }
+            if copied == 0 {
+                self.attacked();
+            }
         }
-        */
+
+        Ok([len.into(), SGX_TECH.into()])
     }
 }
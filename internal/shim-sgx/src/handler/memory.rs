@@ -14,15 +14,19 @@ impl<'a> MemorySyscallHandler for super::Handler<'a> {
 
     /// Do a mprotect() system call
     // Until EDMM, we can't change any page permissions.
-    // What you get is what you get. Fake success.
+    // What you get is what you get. Fake success, but remember whether
+    // heap pages may be written to on behalf of the exec.
     fn mprotect(
         &mut self,
-        _addr: UntrustedRef<'_, u8>,
-        _len: libc::size_t,
-        _prot: libc::c_int,
+        addr: UntrustedRef<'_, u8>,
+        len: libc::size_t,
+        prot: libc::c_int,
     ) -> sallyport::Result {
         self.trace("mprotect", 3);
 
+        crate::heap::HEAP
+            .write()
+            .protect(addr.as_ptr() as _, len, prot);
         Ok(Default::default())
     }
 
@@ -31,6 +31,8 @@ mod memory;
 mod other;
 mod process;
 
+pub use enarx::{SYS_ENARX_EXEC_ARGS, SYS_ENARX_SIGNAL};
+
 use core::arch::asm;
 use core::fmt::Write;
 use core::mem::size_of;
@@ -116,7 +118,7 @@ impl<'a> Handler<'a> {
                         h.gdb_session();
 
                         if r == unsafe { read_unaligned(h.ssa.gpr.rip as _) } {
-                            h.exit(1)
+                            h.shim_exit(1)
                         }
                     }
                 }
@@ -126,23 +128,59 @@ impl<'a> Handler<'a> {
             Some(ExceptionVector::Page) => {
                 h.print_ssa_stack_trace();
                 h.gdb_session();
-                h.exit(1)
+                h.shim_exit(1)
             }
 
             _ => h.attacked(),
         }
     }
 
+    /// Terminate the keep, because the shim failed
+    fn shim_exit(&mut self, status: i32) -> ! {
+        loop {
+            let _ = unsafe { self.proxy(request!(enarx::SYS_ENARX_SHIM_EXIT => status)) };
+        }
+    }
+
     fn handle_syscall(&mut self) {
-        let ret = self.syscall(
-            self.ssa.gpr.rdi.into(),
-            self.ssa.gpr.rsi.into(),
-            self.ssa.gpr.rdx.into(),
-            self.ssa.gpr.r10.into(),
-            self.ssa.gpr.r8.into(),
-            self.ssa.gpr.r9.into(),
-            self.ssa.gpr.rax as usize,
-        );
+        let nr = self.ssa.gpr.rax as usize;
+
+        let ret = match nr as i64 {
+            n @ (enarx::SYS_ENARX_KEEP_CONFIG | enarx::SYS_ENARX_EXEC_ARGS) => self.host_data(
+                n,
+                self.ssa.gpr.rdi as _,
+                self.ssa.gpr.rsi as _,
+                self.ssa.gpr.rdx as _,
+            ),
+            libc::SYS_rt_sigaction => self.rt_sigaction(
+                self.ssa.gpr.rdi as _,
+                self.ssa.gpr.rsi as _,
+                self.ssa.gpr.rdx as _,
+                self.ssa.gpr.r10 as _,
+            ),
+            libc::SYS_rt_sigprocmask => self.rt_sigprocmask(
+                self.ssa.gpr.rdi as _,
+                self.ssa.gpr.rsi as _,
+                self.ssa.gpr.rdx as _,
+                self.ssa.gpr.r10 as _,
+            ),
+            libc::SYS_sigaltstack => self.sigaltstack(self.ssa.gpr.rdi as _, self.ssa.gpr.rsi as _),
+            libc::SYS_rt_sigreturn => {
+                // Restores all registers, including `rip`
+                self.rt_sigreturn();
+                self.deliver_signal(None);
+                return;
+            }
+            _ => self.syscall(
+                self.ssa.gpr.rdi.into(),
+                self.ssa.gpr.rsi.into(),
+                self.ssa.gpr.rdx.into(),
+                self.ssa.gpr.r10.into(),
+                self.ssa.gpr.r8.into(),
+                self.ssa.gpr.r9.into(),
+                self.ssa.gpr.rax as usize,
+            ),
+        };
 
         self.ssa.gpr.rip += 2;
 
@@ -153,6 +191,13 @@ impl<'a> Handler<'a> {
                 self.ssa.gpr.rdx = rdx.into();
             }
         }
+
+        // The host interrupts a syscall, if it has signals for the exec
+        let interrupted = matches!(ret, Err(libc::EINTR));
+        if interrupted {
+            self.fetch_signals();
+        }
+        self.deliver_signal(interrupted.then(|| nr));
     }
 
     fn handle_cpuid(&mut self) {
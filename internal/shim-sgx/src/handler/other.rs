@@ -1,7 +1,16 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::Handler;
+use crate::heap::HEAP;
+use crate::signal::RED_ZONE;
+use crate::{ENARX_EXEC_END, ENARX_EXEC_START, ENARX_STACK_END, ENARX_STACK_START};
 
+use core::mem::size_of;
+use core::ops::Range;
+
+use goblin::elf::header::header64::Header;
+use goblin::elf::program_header::program_header64::ProgramHeader;
+use goblin::elf::program_header::{PF_R, PF_W, PT_LOAD};
 use sallyport::syscall::{NetworkSyscallHandler, SyscallHandler, SystemSyscallHandler};
 use sallyport::untrusted::AddressValidator;
 
@@ -9,14 +18,98 @@ impl<'a> NetworkSyscallHandler for Handler<'a> {}
 impl<'a> SystemSyscallHandler for Handler<'a> {}
 impl<'a> SyscallHandler for Handler<'a> {}
 
+/// Whether `size` bytes at `ptr` lie in loaded segments of the exec, which
+/// are readable and, if `write` is set, writable
+///
+/// The host loads the segments with the permissions of their program
+/// headers, which are part of the measured exec.
+fn in_exec_segments(ptr: usize, size: usize, write: bool) -> bool {
+    let start = unsafe { &ENARX_EXEC_START as *const u8 as usize };
+    let end = unsafe { &ENARX_EXEC_END as *const u8 as usize };
+
+    let hdr = unsafe { &*(start as *const Header) };
+    let phdrs = usize::try_from(hdr.e_phoff)
+        .ok()
+        .and_then(|off| start.checked_add(off))
+        .filter(|phdrs| {
+            let len = usize::from(hdr.e_phnum) * size_of::<ProgramHeader>();
+            phdrs.checked_add(len).map_or(false, |e| e <= end)
+        });
+    let phdrs = match phdrs {
+        Some(phdrs) => unsafe {
+            core::slice::from_raw_parts(phdrs as *const ProgramHeader, hdr.e_phnum.into())
+        },
+        None => return false,
+    };
+
+    let flags = PF_R | if write { PF_W } else { 0 };
+    let segment = |addr: usize| {
+        phdrs.iter().find_map(|phdr| {
+            let bot = start.checked_add(usize::try_from(phdr.p_vaddr).ok()?)?;
+            let top = bot.checked_add(usize::try_from(phdr.p_memsz).ok()?)?;
+            let valid = phdr.p_type == PT_LOAD && phdr.p_flags & flags == flags;
+            (valid && bot <= addr && addr < top && top <= end).then(|| top)
+        })
+    };
+
+    // The range may span adjacent segments
+    let last = match ptr.checked_add(size.saturating_sub(1)) {
+        Some(last) => last,
+        None => return false,
+    };
+    let mut addr = ptr;
+    while addr <= last {
+        addr = match segment(addr) {
+            Some(top) => top,
+            None => return false,
+        };
+    }
+
+    true
+}
+
+impl<'a> Handler<'a> {
+    /// Check that `size` bytes at `ptr` are memory of the exec, which is
+    /// writable, if `write` is set
+    ///
+    /// That is the exec segments with their permissions, the heap pages
+    /// allocated via `brk` and `mmap` with the protection they were mapped
+    /// with, and the part of the stack in use by the exec. Shim memory and
+    /// memory outside the enclave are rejected.
+    pub(super) fn is_exec_mem(&self, ptr: usize, size: usize, write: bool) -> bool {
+        let end = match ptr.checked_add(size) {
+            Some(end) => end,
+            None => return false,
+        };
+
+        let inside = |range: Range<usize>| range.start <= ptr && end <= range.end;
+
+        if in_exec_segments(ptr, size, write) {
+            return true;
+        }
+
+        // The shim handles the syscall further down the same stack.
+        let (stack_start, stack_end) = unsafe {
+            (
+                &ENARX_STACK_START as *const u8 as usize,
+                &ENARX_STACK_END as *const u8 as usize,
+            )
+        };
+        let sp = self.ssa.gpr.rsp.saturating_sub(RED_ZONE) as usize;
+        if inside(sp.max(stack_start)..stack_end) {
+            return true;
+        }
+
+        HEAP.read().contains(ptr, size, write)
+    }
+}
+
 impl<'a> AddressValidator for Handler<'a> {
-    fn validate_const_mem_fn(&self, _ptr: *const (), _size: usize) -> bool {
-        // FIXME: https://github.com/enarx/enarx/issues/630
-        true
+    fn validate_const_mem_fn(&self, ptr: *const (), size: usize) -> bool {
+        self.is_exec_mem(ptr as _, size, false)
     }
 
-    fn validate_mut_mem_fn(&self, _ptr: *mut (), _size: usize) -> bool {
-        // FIXME: https://github.com/enarx/enarx/issues/630
-        true
+    fn validate_mut_mem_fn(&self, ptr: *mut (), size: usize) -> bool {
+        self.is_exec_mem(ptr as _, size, true)
     }
 }
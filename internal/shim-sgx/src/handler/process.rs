@@ -1,7 +1,87 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::handler::SYS_ENARX_SIGNAL;
+use crate::signal::{
+    default_ignored, FpState, SigAction, SigFrame, SigInfo, SigStack, UContext, FPSTATE_SIZE, NSIG,
+    RED_ZONE, SA_ONSTACK, SA_RESTART, SA_RESTORER, SIGNALS, SIGNAL_RESERVE, SIGSET_SIZE, SIG_DFL,
+    SIG_IGN, XFEATURE_SSE, XFEATURE_X87,
+};
+use crate::{ENARX_STACK_END, ENARX_STACK_START};
+
+use core::mem::size_of;
+
+use sallyport::request;
 use sallyport::syscall::{BaseSyscallHandler, ProcessSyscallHandler};
 use sallyport::syscall::{ARCH_GET_FS, ARCH_GET_GS, ARCH_SET_FS, ARCH_SET_GS};
+use sallyport::untrusted::{UntrustedRef, UntrustedRefMut, Validate};
+use sgx::ssa::{GenPurposeRegs, StateSaveArea};
+use x86_64::registers::rflags::RFlags;
+
+/// The flags of `rflags` a signal handler may change via `rt_sigreturn`
+const USER_RFLAGS: RFlags = RFlags::from_bits_truncate(
+    RFlags::CARRY_FLAG.bits()
+        | RFlags::PARITY_FLAG.bits()
+        | RFlags::AUXILIARY_CARRY_FLAG.bits()
+        | RFlags::ZERO_FLAG.bits()
+        | RFlags::SIGN_FLAG.bits()
+        | RFlags::DIRECTION_FLAG.bits()
+        | RFlags::OVERFLOW_FLAG.bits()
+        | RFlags::ALIGNMENT_CHECK.bits(),
+);
+
+/// The registers as saved in a `ucontext_t`
+fn gregs(gpr: &GenPurposeRegs) -> [u64; 23] {
+    let mut gregs = [0; 23];
+    gregs[libc::REG_R8 as usize] = gpr.r8;
+    gregs[libc::REG_R9 as usize] = gpr.r9;
+    gregs[libc::REG_R10 as usize] = gpr.r10;
+    gregs[libc::REG_R11 as usize] = gpr.r11;
+    gregs[libc::REG_R12 as usize] = gpr.r12;
+    gregs[libc::REG_R13 as usize] = gpr.r13;
+    gregs[libc::REG_R14 as usize] = gpr.r14;
+    gregs[libc::REG_R15 as usize] = gpr.r15;
+    gregs[libc::REG_RDI as usize] = gpr.rdi;
+    gregs[libc::REG_RSI as usize] = gpr.rsi;
+    gregs[libc::REG_RBP as usize] = gpr.rbp;
+    gregs[libc::REG_RBX as usize] = gpr.rbx;
+    gregs[libc::REG_RDX as usize] = gpr.rdx;
+    gregs[libc::REG_RAX as usize] = gpr.rax;
+    gregs[libc::REG_RCX as usize] = gpr.rcx;
+    gregs[libc::REG_RSP as usize] = gpr.rsp;
+    gregs[libc::REG_RIP as usize] = gpr.rip;
+    gregs[libc::REG_EFL as usize] = gpr.rflags;
+    gregs
+}
+
+/// Restore the registers from a `ucontext_t`, which is under control of the exec
+fn set_gregs(gpr: &mut GenPurposeRegs, gregs: &[u64; 23]) {
+    gpr.r8 = gregs[libc::REG_R8 as usize];
+    gpr.r9 = gregs[libc::REG_R9 as usize];
+    gpr.r10 = gregs[libc::REG_R10 as usize];
+    gpr.r11 = gregs[libc::REG_R11 as usize];
+    gpr.r12 = gregs[libc::REG_R12 as usize];
+    gpr.r13 = gregs[libc::REG_R13 as usize];
+    gpr.r14 = gregs[libc::REG_R14 as usize];
+    gpr.r15 = gregs[libc::REG_R15 as usize];
+    gpr.rdi = gregs[libc::REG_RDI as usize];
+    gpr.rsi = gregs[libc::REG_RSI as usize];
+    gpr.rbp = gregs[libc::REG_RBP as usize];
+    gpr.rbx = gregs[libc::REG_RBX as usize];
+    gpr.rdx = gregs[libc::REG_RDX as usize];
+    gpr.rax = gregs[libc::REG_RAX as usize];
+    gpr.rcx = gregs[libc::REG_RCX as usize];
+    gpr.rsp = gregs[libc::REG_RSP as usize];
+    gpr.rip = gregs[libc::REG_RIP as usize];
+
+    let user = gregs[libc::REG_EFL as usize] & USER_RFLAGS.bits();
+    gpr.rflags = gpr.rflags & !USER_RFLAGS.bits() | user;
+}
+
+/// Re-execute the `syscall` instruction of syscall `nr` on return
+fn restart(gpr: &mut GenPurposeRegs, nr: usize) {
+    gpr.rip -= 2;
+    gpr.rax = nr as _;
+}
 
 impl<'a> ProcessSyscallHandler for super::Handler<'a> {
     /// Do an arch_prctl() syscall
@@ -21,3 +101,276 @@ impl<'a> ProcessSyscallHandler for super::Handler<'a> {
         Ok(Default::default())
     }
 }
+
+impl<'a> super::Handler<'a> {
+    /// Fetch the signals the host received for the exec
+    pub(super) fn fetch_signals(&mut self) {
+        // The host hands out one signal at a time, but never more than exist
+        for _ in 0..NSIG {
+            let sig: usize = match unsafe { self.proxy(request!(SYS_ENARX_SIGNAL)) } {
+                Ok(ret) => ret[0].into(),
+                Err(_) => break,
+            };
+
+            if sig == 0 {
+                break;
+            }
+
+            SIGNALS.lock().raise(sig);
+        }
+    }
+
+    /// Deliver the next pending, unblocked signal on the return to the exec
+    ///
+    /// `nr` is the number of the syscall, which was interrupted by the host
+    /// to deliver signals. It is restarted, if the signals are ignored or
+    /// their handler asks for it.
+    pub(super) fn deliver_signal(&mut self, mut nr: Option<usize>) {
+        loop {
+            let next = SIGNALS.lock().next_pending();
+            let (sig, action) = match next {
+                Some(next) => next,
+                None => break,
+            };
+
+            match action.handler {
+                SIG_DFL if default_ignored(sig) => continue,
+                SIG_DFL => self.terminate(sig),
+                SIG_IGN => continue,
+                _ => {}
+            }
+
+            let restart = nr.take().filter(|_| action.flags & SA_RESTART != 0);
+            if self.push_signal_frame(sig, &action, restart).is_none() {
+                self.terminate(libc::SIGSEGV as _);
+            }
+
+            return;
+        }
+
+        if let Some(nr) = nr {
+            restart(&mut self.ssa.gpr, nr);
+        }
+    }
+
+    /// Return to the handler of `sig` with a signal frame on the stack of the exec
+    fn push_signal_frame(
+        &mut self,
+        sig: usize,
+        action: &SigAction,
+        restart_nr: Option<usize>,
+    ) -> Option<()> {
+        if action.flags & SA_RESTORER == 0 {
+            return None;
+        }
+
+        let mut saved = gregs(&self.ssa.gpr);
+        if let Some(nr) = restart_nr {
+            saved[libc::REG_RIP as usize] -= 2;
+            saved[libc::REG_RAX as usize] = nr as _;
+        }
+
+        let rsp = self.ssa.gpr.rsp;
+        let mut signals = SIGNALS.lock();
+        let altstack = signals.altstack(rsp);
+
+        let sp = if action.flags & SA_ONSTACK != 0 && altstack.flags == 0 {
+            let top = altstack.sp.checked_add(altstack.size)?;
+            let sp = Self::signal_frame_sp(top)?;
+            UntrustedRefMut::from(sp as *mut SigFrame).validate(self)?;
+            sp
+        } else {
+            // This handler runs below the space reserved for the frame.
+            let top = rsp.checked_sub(RED_ZONE)?;
+            let sp = Self::signal_frame_sp(top)?;
+
+            let (stack_start, stack_end) = unsafe {
+                (
+                    &ENARX_STACK_START as *const u8 as u64,
+                    &ENARX_STACK_END as *const u8 as u64,
+                )
+            };
+            if sp < stack_start || top > stack_end || top - sp > SIGNAL_RESERVE {
+                return None;
+            }
+            sp
+        };
+
+        let sigframe = unsafe { &mut *(sp as *mut SigFrame) };
+        *sigframe = SigFrame {
+            restorer: action.restorer,
+            uc: UContext::new(altstack, saved, 0, signals.mask()),
+            info: SigInfo::new(sig),
+            fpstate: self.fpstate(),
+        };
+        sigframe.uc.fpregs = &sigframe.fpstate as *const FpState as _;
+        signals.enter(sig, action);
+
+        let gpr = &mut self.ssa.gpr;
+        gpr.rip = action.handler;
+        gpr.rsp = sp;
+        gpr.rdi = sig as _;
+        gpr.rsi = &sigframe.info as *const SigInfo as _;
+        gpr.rdx = &sigframe.uc as *const UContext as _;
+        gpr.rax = 0;
+        gpr.rflags &= !(RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG).bits();
+
+        Some(())
+    }
+
+    /// The stack pointer for a signal frame below `top`
+    ///
+    /// Like after a `call`, the stack is aligned for the handler.
+    fn signal_frame_sp(top: u64) -> Option<u64> {
+        let sp = top.checked_sub(size_of::<SigFrame>() as u64)?;
+        (sp & !0xf).checked_sub(size_of::<u64>() as u64)
+    }
+
+    /// The x87 and SSE state of the exec, as saved in the SSA
+    fn fpstate(&self) -> FpState {
+        // The SSA starts with the `xsave` area, whose header follows the legacy region
+        let xsave = &*self.ssa as *const StateSaveArea as *const u8;
+        unsafe {
+            let legacy = &*(xsave as *const [u8; FPSTATE_SIZE]);
+            let xstate_bv = (xsave.add(FPSTATE_SIZE) as *const u64).read();
+            FpState::from_xsave(legacy, xstate_bv)
+        }
+    }
+
+    /// Replace the x87 and SSE state of the exec, which `eresume` restores
+    fn set_fpstate(&mut self, fpstate: &FpState) {
+        let xsave = &mut *self.ssa as *mut StateSaveArea as *mut u8;
+        unsafe {
+            (xsave as *mut [u8; FPSTATE_SIZE]).write(fpstate.0);
+            let xstate_bv = xsave.add(FPSTATE_SIZE) as *mut u64;
+            xstate_bv.write(xstate_bv.read() | XFEATURE_X87 | XFEATURE_SSE);
+        }
+    }
+
+    /// Return from a signal handler to the code it interrupted
+    fn rt_sigreturn(&mut self) {
+        self.trace("rt_sigreturn", 0);
+
+        // The handler returned to the restorer, which popped the return address
+        let uc = UntrustedRef::from(self.ssa.gpr.rsp as *const UContext);
+        let uc = match uc.validate(self) {
+            Some(uc) => *uc,
+            None => self.terminate(libc::SIGSEGV as _),
+        };
+
+        let rip = uc.gregs[libc::REG_RIP as usize] as usize;
+        if !self.is_exec_mem(rip, 1, false) {
+            self.terminate(libc::SIGSEGV as _);
+        }
+
+        // Without a saved state, the state is reset like on Linux
+        let mut fpstate = match uc.fpregs {
+            0 => FpState::new(),
+            fpregs => match UntrustedRef::from(fpregs as *const FpState).validate(self) {
+                Some(fpstate) => *fpstate,
+                None => self.terminate(libc::SIGSEGV as _),
+            },
+        };
+        fpstate.sanitize();
+
+        set_gregs(&mut self.ssa.gpr, &uc.gregs);
+        self.set_fpstate(&fpstate);
+        SIGNALS.lock().set_mask(uc.mask);
+    }
+
+    /// Terminate the exec like the default action of `sig`
+    ///
+    /// The exit status is the one a shell reports for a process killed by `sig`.
+    fn terminate(&mut self, sig: usize) -> ! {
+        let status = sig + 128;
+        loop {
+            let _ = unsafe { self.proxy(request!(libc::SYS_exit_group => status)) };
+        }
+    }
+
+    /// Do an `rt_sigaction()` syscall
+    pub(super) fn rt_sigaction(
+        &mut self,
+        sig: usize,
+        act: usize,
+        oldact: usize,
+        size: usize,
+    ) -> sallyport::Result {
+        self.trace("rt_sigaction", 4);
+
+        if size != SIGSET_SIZE {
+            return Err(libc::EINVAL);
+        }
+
+        let act = match act {
+            0 => None,
+            act => {
+                let act = UntrustedRef::from(act as *const SigAction);
+                Some(*act.validate(self).ok_or(libc::EFAULT)?)
+            }
+        };
+
+        let old = SIGNALS.lock().action(sig, act)?;
+
+        if oldact != 0 {
+            let oldact = UntrustedRefMut::from(oldact as *mut SigAction);
+            *oldact.validate(self).ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+
+    /// Do an `rt_sigprocmask()` syscall
+    pub(super) fn rt_sigprocmask(
+        &mut self,
+        how: libc::c_int,
+        set: usize,
+        oldset: usize,
+        size: usize,
+    ) -> sallyport::Result {
+        self.trace("rt_sigprocmask", 4);
+
+        if size != SIGSET_SIZE {
+            return Err(libc::EINVAL);
+        }
+
+        let set = match set {
+            0 => None,
+            set => {
+                let set = UntrustedRef::from(set as *const u64);
+                Some(*set.validate(self).ok_or(libc::EFAULT)?)
+            }
+        };
+
+        let old = SIGNALS.lock().procmask(how, set)?;
+
+        if oldset != 0 {
+            let oldset = UntrustedRefMut::from(oldset as *mut u64);
+            *oldset.validate(self).ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+
+    /// Do a `sigaltstack()` syscall
+    pub(super) fn sigaltstack(&mut self, ss: usize, old_ss: usize) -> sallyport::Result {
+        self.trace("sigaltstack", 2);
+
+        let ss = match ss {
+            0 => None,
+            ss => {
+                let ss = UntrustedRef::from(ss as *const SigStack);
+                Some(*ss.validate(self).ok_or(libc::EFAULT)?)
+            }
+        };
+
+        let old = SIGNALS.lock().set_altstack(ss, self.ssa.gpr.rsp)?;
+
+        if old_ss != 0 {
+            let old_ss = UntrustedRefMut::from(old_ss as *mut SigStack);
+            *old_ss.validate(self).ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+}
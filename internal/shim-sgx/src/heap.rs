@@ -2,6 +2,8 @@
 
 //! Allocate and deallocate memory on a Heap
 
+use crate::HEAP_PAGES;
+
 use core::num::NonZeroUsize;
 use core::ops::Range;
 
@@ -10,10 +12,10 @@ use primordial::Page;
 
 /// This section MUST be marked as RWX in the linker script
 #[link_section = ".enarx.heap"]
-static mut BLOCK: Block<32768> = Block::new();
+static mut BLOCK: Block<HEAP_PAGES> = Block::new();
 
 /// The keep heap
-pub static HEAP: spinning::RwLock<Heap<'_, 32768>> =
+pub static HEAP: spinning::RwLock<Heap<'_, HEAP_PAGES>> =
     spinning::RwLock::const_new(spinning::RawRwLock::const_new(), unsafe {
         Heap::new(&mut BLOCK)
     });
@@ -35,6 +37,11 @@ where
 {
     blk: &'a mut Block<N>,
     map: [u64; N * 64],
+    /// The pages the exec mapped writable
+    ///
+    /// Until EDMM, all heap pages are RWX, but the shim only writes to pages
+    /// on behalf of the exec, if the exec mapped them writable.
+    writable: [u64; N * 64],
     brk: Option<NonZeroUsize>,
 }
 
@@ -53,6 +60,7 @@ where
         Self {
             blk,
             map: bitmap,
+            writable: bitmap,
             brk,
         }
     }
@@ -72,6 +80,20 @@ where
         self.map[idx] & (1 << bit) != 0
     }
 
+    fn is_writable(&self, page: usize) -> bool {
+        let (idx, bit) = Self::idx_bit(page);
+        self.writable[idx] & (1 << bit) != 0
+    }
+
+    fn set_writable(&mut self, page: usize, writable: bool) {
+        let (idx, bit) = Self::idx_bit(page);
+        if writable {
+            self.writable[idx] |= 1 << bit;
+        } else {
+            self.writable[idx] &= !(1 << bit);
+        }
+    }
+
     fn is_allocated_range(&self, range: core::ops::Range<usize>) -> bool {
         for i in range {
             if self.is_allocated(i) {
@@ -90,6 +112,7 @@ where
     fn deallocate(&mut self, page: usize) {
         let (idx, bit) = Self::idx_bit(page);
         self.map[idx] &= !(1 << bit);
+        self.set_writable(page, false);
     }
 
     fn offset(&self, addr: usize) -> Option<usize> {
@@ -139,6 +162,7 @@ where
 
             for page in old..new {
                 self.allocate(page);
+                self.set_writable(page, true);
             }
         } else {
             for page in old..new {
@@ -163,8 +187,7 @@ where
         const RWX: libc::c_int = libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC;
         const PA: libc::c_int = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
 
-        let prot = prot & !RWX;
-        if addr != 0 || fd != -1 || offset != 0 || prot != 0 || flags != PA {
+        if addr != 0 || fd != -1 || offset != 0 || prot & !RWX != 0 || flags != PA {
             return Err(libc::EINVAL);
         }
 
@@ -186,6 +209,7 @@ where
             if !self.is_allocated_range(range.clone()) {
                 for page in range.clone() {
                     self.allocate(page);
+                    self.set_writable(page, prot & libc::PROT_WRITE != 0);
                 }
 
                 return Ok(self.blk.0[range].as_mut_ptr() as *mut T);
@@ -195,6 +219,45 @@ where
         Err(libc::ENOMEM)
     }
 
+    /// Whether `length` bytes at `addr` lie entirely in allocated heap pages,
+    /// which are writable, if `write` is set
+    pub fn contains(&self, addr: usize, length: usize, write: bool) -> bool {
+        let base = self.blk.0.as_ptr() as usize;
+        let ceil = base + self.blk.0.len() * Page::SIZE;
+
+        let end = match addr.checked_add(length) {
+            Some(end) if addr >= base && end <= ceil => end,
+            _ => return false,
+        };
+
+        let bot = (addr - base) / Page::SIZE;
+        let top = (end - base + Page::SIZE - 1) / Page::SIZE;
+        (bot..top).all(|page| self.is_allocated(page) && (!write || self.is_writable(page)))
+    }
+
+    /// Record the protection `prot` of the allocated heap pages of `length`
+    /// bytes at `addr`
+    pub fn protect(&mut self, addr: usize, length: usize, prot: libc::c_int) {
+        let bot = match self.offset_page_down(addr) {
+            Some(page) => page,
+            None => return,
+        };
+
+        let top = match addr
+            .checked_add(length)
+            .and_then(|end| self.offset_page_up(end))
+        {
+            Some(page) => page,
+            None => return,
+        };
+
+        for page in bot..top {
+            if self.is_allocated(page) {
+                self.set_writable(page, prot & libc::PROT_WRITE != 0);
+            }
+        }
+    }
+
     /// munmap memory from the heap
     pub fn munmap<T>(&mut self, addr: *const T, length: usize) -> Result<(), libc::c_int> {
         let addr = addr as usize;
@@ -313,4 +376,43 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn contains() {
+        let mut block = Block::<128>::new();
+        let mut heap = unsafe { Heap::new(&mut block) };
+        let base = heap.blk as *const _ as usize;
+
+        assert!(!heap.contains(base, 1, false));
+
+        let brk = base + 2 * Page::SIZE;
+        assert_eq!(heap.brk(brk), brk);
+        assert!(heap.contains(base, 2 * Page::SIZE, true));
+        assert!(!heap.contains(base, 2 * Page::SIZE + 1, false));
+
+        let addr = heap
+            .mmap::<c_void>(0, Page::SIZE, PROT, FLAGS, -1, 0)
+            .unwrap() as usize;
+        assert!(heap.contains(addr, Page::SIZE, false));
+        assert!(!heap.contains(addr, Page::SIZE, true));
+        assert!(!heap.contains(addr - 1, 2, false));
+        assert!(!heap.contains(addr + Page::SIZE, 1, false));
+        assert!(!heap.contains(addr, usize::MAX, false));
+        assert!(!heap.contains(base - 1, 1, false));
+
+        heap.protect(addr, Page::SIZE, libc::PROT_READ | libc::PROT_WRITE);
+        assert!(heap.contains(addr, Page::SIZE, true));
+        heap.protect(addr, Page::SIZE, libc::PROT_READ);
+        assert!(!heap.contains(addr, Page::SIZE, true));
+
+        heap.munmap::<c_void>(addr as *const c_void, Page::SIZE)
+            .unwrap();
+        assert!(!heap.contains(addr, 1, false));
+
+        let rw = libc::PROT_READ | libc::PROT_WRITE;
+        let addr = heap
+            .mmap::<c_void>(0, Page::SIZE, rw, FLAGS, -1, 0)
+            .unwrap() as usize;
+        assert!(heap.contains(addr, Page::SIZE, true));
+    }
 }
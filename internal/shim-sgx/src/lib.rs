@@ -18,14 +18,15 @@
 pub mod entry;
 pub mod handler;
 pub mod heap;
+pub mod signal;
 
 use sgx::parameters::{Attributes, Features, MiscSelect, Xfrm};
 
 const DEBUG: bool = cfg!(feature = "dbg");
 
-/// FIXME: doc
-pub const ENCL_SIZE_BITS: u8 = 31;
-/// FIXME: doc
+include!(concat!(env!("OUT_DIR"), "/size.rs"));
+
+/// The size of the enclave
 pub const ENCL_SIZE: usize = 1 << ENCL_SIZE_BITS;
 
 const XFRM: Xfrm = Xfrm::from_bits_truncate(Xfrm::X87.bits() | Xfrm::SSE.bits());
@@ -48,4 +49,8 @@ extern "C" {
     pub static ENARX_EXEC_START: u8;
     /// Extern
     pub static ENARX_EXEC_END: u8;
+    /// The start of the stack, shared by the shim and the exec
+    pub static ENARX_STACK_START: u8;
+    /// The end of the stack
+    pub static ENARX_STACK_END: u8;
 }
@@ -19,6 +19,7 @@ extern crate rcrt1;
 
 use core::arch::asm;
 
+use shim_sgx::signal::{RED_ZONE, SIGNAL_RESERVE};
 use shim_sgx::{entry, handler, ATTR, ENARX_EXEC_START, ENCL_SIZE, ENCL_SIZE_BITS, MISC};
 
 #[panic_handler]
@@ -225,7 +226,7 @@ pub unsafe extern "sysv64" fn _start() -> ! {
 
         // Find stack pointer for CSSA > 0
         "mov    r10,    [r10 + {RSPO}]      ",  // r10 = SSA[CSSA - 1].gpr.rsp
-        "sub    r10,    128                 ",  // Skip the red zone
+        "sub    r10,    {SKIP}              ",  // Skip the red zone and the signal frame
 
         // Setup the stack
         "3:                                 ",
@@ -256,6 +257,8 @@ pub unsafe extern "sysv64" fn _start() -> ! {
         // offset_of!(StateSaveArea, gpr.rsp)
         RSPO = const size_of::<StateSaveArea>() - size_of::<GenPurposeRegs>() + 32,
 
+        SKIP = const RED_ZONE + SIGNAL_RESERVE,
+
         // offset_of!(StateSaveArea, extra)
         EXTO = const size_of::<xsave::XSave>(),
 
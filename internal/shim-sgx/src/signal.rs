@@ -0,0 +1,26 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Signal handling of the exec
+//!
+//! The host forwards the signals it receives by failing a proxied syscall
+//! with `EINTR`. The shim then fetches them with
+//! [`SYS_ENARX_SIGNAL`](crate::handler::SYS_ENARX_SIGNAL) and delivers them
+//! on the return from that syscall. A workload busy computing without doing
+//! any syscalls therefore does not see its signals until its next syscall.
+
+pub use shim_common::signal::*;
+
+use core::mem::size_of;
+
+/// The stack space below the red zone, which the shim leaves free for a signal frame
+///
+/// The shim handles syscalls further down the stack of the exec, so the
+/// signal frame has to be written above the stack of the shim.
+pub const SIGNAL_RESERVE: u64 = 2048;
+
+// The signal frame and its alignment fit into the reserved stack space
+const _: () = assert!(size_of::<SigFrame>() + 24 <= SIGNAL_RESERVE as usize);
+
+/// The signal state of the exec
+pub static SIGNALS: spinning::Mutex<Signals> =
+    spinning::Mutex::const_new(spinning::RawMutex::const_new(), Signals::new());
@@ -1,10 +1,111 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use std::io::{ErrorKind, Read, Result};
+//! Resources bundled with a WebAssembly module
+//!
+//! `enarx bundle` packs a directory into the `.enarx.resources` custom
+//! section of a module, so the files are part of the module and its digest.
+//! The workload finds them in the read-only directory [`RESOURCES_PATH`].
+//!
+//! The section holds the magic `ENARXRES` and the format version as
+//! little-endian `u32`, followed by the entries:
+//!
+//! | Field  | Size          | Content                                        |
+//! |--------|---------------|------------------------------------------------|
+//! | kind   | 1             | 0 for a directory, 1 for a file                |
+//! | length | 4             | the length of the path as little-endian `u32`  |
+//! | path   | length        | relative, UTF-8, `/` separated                 |
+//! | size   | 8, files only | the size of the file as little-endian `u64`    |
+//! | data   | size          | the contents of the file                       |
+//!
+//! A directory comes before the entries inside of it. `enarx bundle` writes
+//! the section in `src/cli/bundle.rs`, and the `bundle` test in
+//! `tests/wasmldr_tests.rs` checks both sides agree.
+
+use super::memfs;
+
+use std::io::{Error, ErrorKind, Read, Result};
 use wasmparser::{Chunk, Parser, Payload::*};
 
 const RESOURCES_SECTION: &str = ".enarx.resources";
 
+/// Where the workload finds the bundled resources, like `RESOURCES_PATH` of the host
+pub const RESOURCES_PATH: &str = "/resources";
+
+/// The magic at the start of the section
+const MAGIC: &[u8; 8] = b"ENARXRES";
+
+/// The version of the format of the section
+const VERSION: u32 = 1;
+
+const KIND_DIR: u8 = 0;
+const KIND_FILE: u8 = 1;
+
+/// The resources bundled with `module`, if any
+pub fn resources(module: &[u8]) -> Result<Option<memfs::Dir>> {
+    let mut section = None;
+    parse(
+        module,
+        |data| {
+            if section.replace(data.to_vec()).is_some() {
+                return Err(invalid("more than one resources section"));
+            }
+            Ok(())
+        },
+        |_| Ok(()),
+    )?;
+
+    section.map(|data| unpack(&data)).transpose()
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid resources: {}", msg),
+    )
+}
+
+/// Take `n` bytes from the front of `data`
+fn take<'a>(data: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if data.len() < n {
+        return Err(invalid("truncated"));
+    }
+    let (head, rest) = data.split_at(n);
+    *data = rest;
+    Ok(head)
+}
+
+/// Build an in-memory directory from the contents of a resources section
+fn unpack(mut data: &[u8]) -> Result<memfs::Dir> {
+    if take(&mut data, MAGIC.len())? != MAGIC {
+        return Err(invalid("bad magic"));
+    }
+    let version = u32::from_le_bytes(take(&mut data, 4)?.try_into().unwrap());
+    if version != VERSION {
+        return Err(invalid("unsupported version"));
+    }
+
+    let dir = memfs::Dir::new();
+    while !data.is_empty() {
+        let kind = take(&mut data, 1)?[0];
+        let len = u32::from_le_bytes(take(&mut data, 4)?.try_into().unwrap());
+        let path = std::str::from_utf8(take(&mut data, len as usize)?)
+            .map_err(|_| invalid("path is not UTF-8"))?;
+
+        let added = match kind {
+            KIND_DIR => dir.add_dir(path),
+            KIND_FILE => {
+                let size = u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap());
+                let size = usize::try_from(size).map_err(|_| invalid("file too large"))?;
+                dir.add_file(path, take(&mut data, size)?.to_vec())
+            }
+            _ => return Err(invalid("unknown entry kind")),
+        };
+        added.map_err(|e| invalid(&format!("cannot add {:?}: {}", path, e)))?;
+    }
+
+    Ok(dir)
+}
+
 pub fn parse(
     mut input: impl Read,
     mut handle_custom: impl FnMut(&[u8]) -> Result<()>,
@@ -70,3 +171,70 @@ pub fn parse(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(kind: u8, path: &str, data: Option<&[u8]>) -> Vec<u8> {
+        let mut bytes = vec![kind];
+        bytes.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(path.as_bytes());
+        if let Some(data) = data {
+            bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn section(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        entries.iter().for_each(|e| data.extend_from_slice(e));
+        data
+    }
+
+    /// `module` with a custom section `name` holding `data` appended
+    fn with_section(mut module: Vec<u8>, name: &str, data: &[u8]) -> Vec<u8> {
+        // The sizes are below 128, so each fits into a single LEB128 byte
+        let size = 1 + name.len() + data.len();
+        assert!(size < 128);
+        module.extend_from_slice(&[0, size as u8, name.len() as u8]);
+        module.extend_from_slice(name.as_bytes());
+        module.extend_from_slice(data);
+        module
+    }
+
+    #[test]
+    fn bundled() {
+        let data = section(&[
+            entry(KIND_DIR, "etc", None),
+            entry(KIND_FILE, "etc/app.toml", Some(b"debug = true\n")),
+        ]);
+        let module = wat::parse_str("(module)").unwrap();
+
+        assert!(resources(&module).unwrap().is_none());
+        let other = with_section(module.clone(), "other", &data);
+        assert!(resources(&other).unwrap().is_none());
+
+        let bundled = with_section(module, RESOURCES_SECTION, &data);
+        assert!(resources(&bundled).unwrap().is_some());
+    }
+
+    #[test]
+    fn malformed() {
+        let file = entry(KIND_FILE, "a/b", Some(b"x"));
+        assert!(unpack(&section(&[file])).is_err());
+
+        let file = entry(KIND_FILE, "../b", Some(b"x"));
+        assert!(unpack(&section(&[file])).is_err());
+
+        let mut truncated = section(&[entry(KIND_FILE, "b", Some(b"xyz"))]);
+        truncated.pop();
+        assert!(unpack(&truncated).is_err());
+
+        assert!(unpack(b"ENARXRES\x02\0\0\0").is_err());
+        assert!(unpack(&section(&[entry(7, "b", None)])).is_err());
+        assert!(unpack(&section(&[])).is_ok());
+    }
+}
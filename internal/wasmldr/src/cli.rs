@@ -4,7 +4,11 @@
 
 use structopt::{clap::AppSettings, StructOpt};
 
+use super::config::{Config, Dir, Invoke, Stdio, StdioMode};
+
 use anyhow::{bail, Result};
+use std::fs::File;
+use std::os::unix::io::{IntoRawFd, RawFd};
 use std::path::PathBuf;
 
 // The main StructOpt for running `wasmldr` directly
@@ -21,11 +25,41 @@ pub struct RunOptions {
     )]
     pub envs: Vec<(String, String)>,
 
+    /// Where the program's stdin comes from: inherit or null
+    #[structopt(long, value_name = "MODE", default_value = "inherit")]
+    pub stdin: StdioMode,
+
+    /// Where the program's stdout goes: inherit or null
+    #[structopt(long, value_name = "MODE", default_value = "inherit")]
+    pub stdout: StdioMode,
+
+    /// Where the program's stderr goes: inherit or null
+    #[structopt(long, value_name = "MODE", default_value = "inherit")]
+    pub stderr: StdioMode,
+
+    /// Preopen a directory: the host directory HOST, or an in-memory one without it
+    ///
+    /// The directory is read-only with `:ro`.
+    #[structopt(
+        long = "dir",
+        number_of_values = 1,
+        value_name = "GUEST[=HOST][:ro]",
+        parse(try_from_str=parse_dir),
+    )]
+    pub dirs: Vec<(String, Option<PathBuf>, bool)>,
+
+    /// Call the exported function NAME with ARGS instead of `_start`
+    #[structopt(long, value_name = "NAME")]
+    pub invoke: Option<String>,
+
+    /// Where the results of the invoked function are written as JSON
+    #[structopt(long, value_name = "FD", default_value = "1")]
+    pub results_fd: RawFd,
+
     // TODO: --inherit-env
-    // TODO: --stdin, --stdout, --stderr
     /// Path of the WebAssembly module to run
     #[structopt(index = 1, value_name = "MODULE", parse(from_os_str))]
-    pub module: Option<PathBuf>,
+    pub module: PathBuf,
 
     // NOTE: this has to come last for TrailingVarArg
     /// Arguments to pass to the WebAssembly module
@@ -33,6 +67,39 @@ pub struct RunOptions {
     pub args: Vec<String>,
 }
 
+impl RunOptions {
+    /// Open the module and build a `Config` equivalent to the options
+    pub fn into_config(self) -> std::io::Result<Config> {
+        let module = File::open(&self.module)?;
+
+        let mut dirs = Vec::new();
+        for (path, host, writable) in self.dirs {
+            let fd = match host {
+                Some(host) => Some(File::open(host)?.into_raw_fd()),
+                None => None,
+            };
+            dirs.push(Dir { path, fd, writable });
+        }
+
+        Ok(Config {
+            module_fd: Some(module.into_raw_fd()),
+            args: self.args,
+            env: self.envs.into_iter().collect(),
+            stdio: Stdio {
+                stdin: self.stdin,
+                stdout: self.stdout,
+                stderr: self.stderr,
+            },
+            invoke: self.invoke.map(|function| Invoke {
+                function,
+                results_fd: self.results_fd,
+            }),
+            dirs,
+            ..Default::default()
+        })
+    }
+}
+
 fn parse_env_var(s: &str) -> Result<(String, String)> {
     let parts: Vec<&str> = s.splitn(2, '=').collect();
     if parts.len() != 2 {
@@ -40,3 +107,18 @@ fn parse_env_var(s: &str) -> Result<(String, String)> {
     }
     Ok((parts[0].to_owned(), parts[1].to_owned()))
 }
+
+fn parse_dir(s: &str) -> Result<(String, Option<PathBuf>, bool)> {
+    let (s, writable) = match s.strip_suffix(":ro") {
+        Some(s) => (s, false),
+        None => (s, true),
+    };
+    let (guest, host) = match s.split_once('=') {
+        Some((guest, host)) => (guest, Some(host.into())),
+        None => (s, None),
+    };
+    if guest.is_empty() {
+        bail!("must be of the form `GUEST[=HOST][:ro]`");
+    }
+    Ok((guest.to_owned(), host, writable))
+}
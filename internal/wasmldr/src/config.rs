@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The keep configuration, as delivered by the host.
+//!
+//! Inside a keep, `wasmldr` fetches its configuration with the
+//! `SYS_ENARX_KEEP_CONFIG` call, which the shim forwards to the host.
+//! Outside of a keep, the call fails with `ENOSYS` and the configuration
+//! is built from the command line instead.
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+/// Fetch the keep configuration from the host.
+///
+/// Arguments: a buffer, its length and the offset into the configuration.
+/// Returns the total size of the configuration if the length is zero,
+/// otherwise the number of bytes copied into the buffer.
+const SYS_ENARX_KEEP_CONFIG: libc::c_long = 0xEA10;
+
+/// Handling of a single standard I/O stream
+///
+/// Written as `"inherit"`, `"null"` or `"fd:NAME"`, like the `StdioMode` of
+/// the host configuration.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum StdioMode {
+    /// Use the corresponding stream of the `enarx` process
+    Inherit,
+    /// Reads return EOF, writes are discarded
+    Null,
+    /// The preopened file descriptor called `NAME`
+    Fd(String),
+}
+
+impl Default for StdioMode {
+    fn default() -> Self {
+        Self::Inherit
+    }
+}
+
+impl FromStr for StdioMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match mode {
+            "inherit" => Self::Inherit,
+            "null" => Self::Null,
+            _ => match mode.strip_prefix("fd:") {
+                Some(name) => Self::Fd(name.into()),
+                None => {
+                    return Err(format!(
+                        "invalid stdio mode {:?}, expected inherit, null or fd:NAME",
+                        mode
+                    ))
+                }
+            },
+        })
+    }
+}
+
+impl TryFrom<String> for StdioMode {
+    type Error = String;
+
+    fn try_from(mode: String) -> std::result::Result<Self, Self::Error> {
+        mode.parse()
+    }
+}
+
+/// Handling of the standard I/O streams
+///
+/// The host always routes all three streams explicitly.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Stdio {
+    pub stdin: StdioMode,
+    pub stdout: StdioMode,
+    pub stderr: StdioMode,
+}
+
+/// An exported function to call instead of the WASI entry point
+///
+/// The workload arguments are parsed according to the signature of the
+/// function, see [`workload::params`](crate::workload::params).
+#[derive(Deserialize, Debug, Clone)]
+pub struct Invoke {
+    /// The name of the exported function
+    pub function: String,
+    /// The file descriptor the results are written to as JSON
+    #[serde(default = "stdout_fd")]
+    pub results_fd: RawFd,
+}
+
+fn stdout_fd() -> RawFd {
+    libc::STDOUT_FILENO
+}
+
+/// The kind of a preopened file descriptor
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FdKind {
+    File,
+    Listen,
+    Stream,
+}
+
+/// A file descriptor opened by the host for the workload
+#[derive(Deserialize, Debug)]
+pub struct Fd {
+    pub name: String,
+    pub fd: RawFd,
+    pub kind: FdKind,
+    pub writable: bool,
+}
+
+/// A directory preopened for the workload
+#[derive(Deserialize, Debug, Clone)]
+pub struct Dir {
+    /// The path the workload knows the directory by
+    pub path: String,
+    /// The host directory, or an in-memory directory inside the keep if unset
+    #[serde(default)]
+    pub fd: Option<RawFd>,
+    /// Whether the workload may modify the directory
+    pub writable: bool,
+}
+
+/// The workload configuration
+#[derive(Deserialize, Debug, Default)]
+pub struct Config {
+    /// The file descriptor the WebAssembly module can be read from
+    #[serde(default)]
+    pub module_fd: Option<RawFd>,
+
+    /// Arguments passed to the workload
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Environment variables passed to the workload
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Handling of the workload's standard I/O
+    #[serde(default)]
+    pub stdio: Stdio,
+
+    /// The function to call, if not the WASI entry point
+    #[serde(default)]
+    pub invoke: Option<Invoke>,
+
+    /// Directories preopened for the workload, at file descriptors 3 and up
+    #[serde(default)]
+    pub dirs: Vec<Dir>,
+
+    /// File descriptors opened by the host for the workload, following the directories
+    #[serde(default)]
+    pub fds: Vec<Fd>,
+}
+
+fn keep_config(buf: *mut u8, len: usize, offset: usize) -> Result<usize> {
+    match unsafe { libc::syscall(SYS_ENARX_KEEP_CONFIG, buf, len, offset) } {
+        n if n < 0 => Err(Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+impl Config {
+    /// Fetch the configuration from the host.
+    ///
+    /// Returns `Ok(None)` if we're not running in a keep or the host
+    /// didn't supply a configuration.
+    pub fn fetch() -> Result<Option<Self>> {
+        let total = match keep_config(std::ptr::null_mut(), 0, 0) {
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::ENOENT)) => {
+                return Ok(None)
+            }
+            r => r?,
+        };
+
+        let mut buf = vec![0u8; total];
+        let mut offset = 0;
+        while offset < total {
+            let rest = &mut buf[offset..];
+            match keep_config(rest.as_mut_ptr(), rest.len(), offset)? {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                n if n > rest.len() => return Err(ErrorKind::InvalidData.into()),
+                n => offset += n,
+            }
+        }
+
+        let text = String::from_utf8(buf).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        toml::from_str(&text)
+            .map(Some)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
@@ -20,14 +20,25 @@
 //! [WARN  wasmldr] 🌭DEV-ONLY BUILD, NOT FOR PRODUCTION USE🌭
 //! [INFO  wasmldr] opts: RunOptions {
 //!         envs: [],
-//!         module: Some(
-//!             "return_1.wasm",
+//!         module: "return_1.wasm",
+//!         args: [],
+//!     }
+//! [INFO  wasmldr] config: Config {
+//!         module_fd: Some(
+//!             3,
 //!         ),
 //!         args: [],
+//!         env: {},
+//!         stdio: Stdio {
+//!             stdin: Inherit,
+//!             stdout: Inherit,
+//!             stderr: Inherit,
+//!         },
+//!         fds: [],
 //!     }
-//! [INFO  wasmldr] reading module from "return_1.wasm"
+//! [INFO  wasmldr] reading module from fd 3
 //! [INFO  wasmldr] running workload
-//! [WARN  wasmldr::workload] inheriting stdio from calling process
+//! [INFO  wasmldr::workload] stdio: Stdio { stdin: Inherit, stdout: Inherit, stderr: Inherit }
 //! [INFO  wasmldr] got result: Ok(
 //!         [
 //!             I32(
@@ -37,37 +48,45 @@
 //!     )
 //! ```
 //!
-//! If no filename is given, `wasmldr` expects to read the WebAssembly module
-//! from file descriptor 3, so this would be equivalent:
+//! With `--invoke NAME`, the exported function `NAME` is called with the
+//! arguments parsed according to its signature, and its results are written
+//! as JSON to the file descriptor given by `--results-fd` (stdout by
+//! default):
+//!
 //! ```console
-//! $ RUST_LOG=wasmldr=info RUST_BACKTRACE=1 cargo run -- 3< return_1.wasm
-//!  ```
+//! $ cargo run -- --invoke add add.wasm 1 2
+//! [{"type":"i32","value":3}]
+//! ```
+//!
+//! A workload calling WASI `proc_exit` makes `wasmldr` exit with that
+//! status. If the workload traps, the trap code and the wasm backtrace are
+//! printed to stderr.
+//!
+//! Inside a keep, `wasmldr` ignores its command line and fetches the keep
+//! configuration (module, arguments, environment, stdio handling and
+//! preopened files) from the host instead. See `enarx run --config`.
 //!
 #![deny(missing_docs)]
 #![deny(clippy::all)]
 #![warn(rust_2018_idioms)]
 
+mod bundle;
 mod cli;
+mod config;
+mod memfs;
 mod workload;
 
-use log::{debug, info, warn};
+use config::Config;
+
+use log::{debug, error, info, warn};
 use structopt::StructOpt;
 
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::mem::ManuallyDrop;
 use std::os::unix::io::FromRawFd;
 
-// v0.1.0 KEEP-CONFIG HACK
-// We don't yet have a well-defined way to pass runtime configuration from
-// the frontend/CLI into the keep, so the keep configuration is pre-defined:
-//   * the .wasm module is open on fd3 and gets no arguments or env vars
-//   * stdin, stdout, and stderr are enabled and should go to fd 0,1,2
-//   * logging should be turned on at "debug" level, output goes to stderr
-//
-
 fn main() {
-    // KEEP-CONFIG HACK: we've inherited stdio and the shim sets
-    // "RUST_LOG=debug", so this should make logging go to stderr.
     // FUTURE: we should have a keep-provided debug channel where we can
     // (safely, securely) send logs. Might need our own logger for that..
     env_logger::Builder::from_default_env().init();
@@ -76,37 +95,59 @@ fn main() {
 
     warn!("🌭DEV-ONLY BUILD, NOT FOR PRODUCTION USE🌭");
 
-    debug!("parsing argv");
-    let opts = cli::RunOptions::from_args();
-    info!("opts: {:#?}", opts);
-
-    let mut reader = if let Some(module) = opts.module {
-        info!("reading module from {:?}", &module);
-        File::open(&module).expect("Unable to open file")
-    } else {
-        // v0.1.0 KEEP-CONFIG HACK: just assume module is on FD3
-        info!("reading module from fd 3");
-        unsafe { File::from_raw_fd(3) }
+    debug!("fetching keep config");
+    let config = match Config::fetch() {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            debug!("no keep config, parsing argv");
+            let opts = cli::RunOptions::from_args();
+            info!("opts: {:#?}", opts);
+            opts.into_config().expect("Unable to open file")
+        }
+        Err(e) => {
+            error!("failed to fetch keep config: {}", e);
+            // EX_IOERR, as with any other I/O error below
+            std::process::exit(74);
+        }
     };
+    info!("config: {:#?}", config);
+
+    let module_fd = config.module_fd.expect("No module given");
+    info!("reading module from fd {}", module_fd);
+    let mut reader = unsafe { File::from_raw_fd(module_fd) };
 
     let mut bytes = Vec::new();
     reader
         .read_to_end(&mut bytes)
         .expect("Failed to load workload");
 
-    // TODO: split up / refactor workload::run() so we can configure things
-    // like WASI stdio or wasmtime features before executing the workload..
-
     info!("running workload");
-    let result = workload::run(bytes, opts.args, opts.envs);
+    let result = workload::run(bytes, &config);
     info!("got result: {:#?}", result);
 
     // FUTURE: produce attestation report here
-    // TODO: print the returned value(s) in some format (json?)
+
+    // The results of an invoked function are the output of the workload
+    if let (Some(invoke), Ok(results)) = (&config.invoke, &result) {
+        let json = workload::results_json(results);
+        debug!("writing results to fd {}", invoke.results_fd);
+        // Not ours to close
+        let mut out = ManuallyDrop::new(unsafe { File::from_raw_fd(invoke.results_fd) });
+        if let Err(e) = writeln!(out, "{}", json) {
+            error!("failed to write results: {}", e);
+            // EX_IOERR
+            std::process::exit(74);
+        }
+    }
 
     // Choose an appropriate exit code from our result
     std::process::exit(match result {
         Ok(_) => 0,
-        Err(e) => i32::from(e),
+        Err(e) => {
+            if let workload::Error::Trap(trap) = &e {
+                eprintln!("{}", workload::describe_trap(trap));
+            }
+            i32::from(e)
+        }
     });
 }
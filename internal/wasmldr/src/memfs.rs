@@ -0,0 +1,686 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! An in-memory filesystem inside the keep
+//!
+//! The contents of a [`Dir`] never leave the keep and are gone when the
+//! workload exits. There are no symlinks and no timestamps. Whether the
+//! workload may modify a directory is up to the capabilities it is
+//! preopened with.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::io::{IoSlice, IoSliceMut, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use wasi_common::dir::{ReaddirCursor, ReaddirEntity, WasiDir};
+use wasi_common::file::{Advice, FdFlags, FileType, Filestat, OFlags, WasiFile};
+use wasi_common::{Error, SystemTimeSpec};
+
+/// The next free inode number
+static INODE: AtomicU64 = AtomicU64::new(1);
+
+fn errno(code: libc::c_int) -> Error {
+    std::io::Error::from_raw_os_error(code).into()
+}
+
+/// The contents of a file
+#[derive(Debug)]
+struct FileNode {
+    inode: u64,
+    data: RwLock<Vec<u8>>,
+}
+
+/// The entries of a directory
+#[derive(Debug)]
+struct DirNode {
+    inode: u64,
+    entries: RwLock<BTreeMap<String, Node>>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File(Arc<FileNode>),
+    Dir(Arc<DirNode>),
+}
+
+impl Node {
+    fn file(data: Vec<u8>) -> Self {
+        Self::File(Arc::new(FileNode {
+            inode: INODE.fetch_add(1, Ordering::Relaxed),
+            data: RwLock::new(data),
+        }))
+    }
+
+    fn dir() -> Self {
+        Self::Dir(Arc::new(DirNode {
+            inode: INODE.fetch_add(1, Ordering::Relaxed),
+            entries: Default::default(),
+        }))
+    }
+
+    fn inode(&self) -> u64 {
+        match self {
+            Self::File(f) => f.inode,
+            Self::Dir(d) => d.inode,
+        }
+    }
+
+    fn filetype(&self) -> FileType {
+        match self {
+            Self::File(..) => FileType::RegularFile,
+            Self::Dir(..) => FileType::Directory,
+        }
+    }
+
+    fn stat(&self) -> Filestat {
+        let size = match self {
+            Self::File(f) => f.data.read().unwrap().len(),
+            Self::Dir(d) => d.entries.read().unwrap().len(),
+        };
+
+        Filestat {
+            device_id: 0,
+            inode: self.inode(),
+            filetype: self.filetype(),
+            nlink: 1,
+            size: size as u64,
+            atim: None,
+            mtim: None,
+            ctim: None,
+        }
+    }
+}
+
+/// The components of the relative `path`, with `.` and `..` resolved
+///
+/// `..` must not leave the directory the path is relative to.
+fn components(path: &str) -> Result<Vec<&str>, Error> {
+    if path.starts_with('/') {
+        return Err(errno(libc::EPERM));
+    }
+
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop().ok_or_else(|| errno(libc::EPERM))?;
+            }
+            part => parts.push(part),
+        }
+    }
+
+    Ok(parts)
+}
+
+/// The directory `parts` below `dir`
+fn walk(dir: &Arc<DirNode>, parts: &[&str]) -> Result<Arc<DirNode>, Error> {
+    let mut dir = dir.clone();
+
+    for part in parts {
+        let next = match dir.entries.read().unwrap().get(*part) {
+            Some(Node::Dir(d)) => d.clone(),
+            Some(Node::File(..)) => return Err(errno(libc::ENOTDIR)),
+            None => return Err(errno(libc::ENOENT)),
+        };
+        dir = next;
+    }
+
+    Ok(dir)
+}
+
+/// The directory containing `path` below `dir` and the last component of `path`
+fn parent<'a>(dir: &Arc<DirNode>, path: &'a str) -> Result<(Arc<DirNode>, &'a str), Error> {
+    let parts = components(path)?;
+    let (name, parents) = parts.split_last().ok_or_else(|| errno(libc::EINVAL))?;
+    Ok((walk(dir, parents)?, name))
+}
+
+/// Whether `other` is `dir` or below it
+fn contains(dir: &Arc<DirNode>, other: &Arc<DirNode>) -> bool {
+    Arc::ptr_eq(dir, other)
+        || dir.entries.read().unwrap().values().any(|node| match node {
+            Node::Dir(d) => contains(d, other),
+            Node::File(..) => false,
+        })
+}
+
+/// Add `node` at `path` below `dir`, whose parent must exist
+fn insert(dir: &Arc<DirNode>, path: &str, node: Node) -> Result<(), Error> {
+    let (dir, name) = parent(dir, path)?;
+    let mut entries = dir.entries.write().unwrap();
+
+    if entries.contains_key(name) {
+        return Err(errno(libc::EEXIST));
+    }
+    entries.insert(name.into(), node);
+    Ok(())
+}
+
+/// The node at `path` below `dir`
+fn lookup(dir: &Arc<DirNode>, path: &str) -> Result<Node, Error> {
+    let parts = components(path)?;
+    match parts.split_last() {
+        None => Ok(Node::Dir(dir.clone())),
+        Some((name, parents)) => walk(dir, parents)?
+            .entries
+            .read()
+            .unwrap()
+            .get(*name)
+            .cloned()
+            .ok_or_else(|| errno(libc::ENOENT)),
+    }
+}
+
+/// A directory of the in-memory filesystem
+#[derive(Debug, Clone)]
+pub struct Dir(Arc<DirNode>);
+
+impl Dir {
+    /// Create an empty filesystem
+    pub fn new() -> Self {
+        match Node::dir() {
+            Node::Dir(d) => Self(d),
+            Node::File(..) => unreachable!(),
+        }
+    }
+
+    /// Add the empty directory `path`, whose parent must exist
+    pub fn add_dir(&self, path: &str) -> Result<(), Error> {
+        insert(&self.0, path, Node::dir())
+    }
+
+    /// Add the file `path` with `data`, whose parent must exist
+    pub fn add_file(&self, path: &str, data: Vec<u8>) -> Result<(), Error> {
+        insert(&self.0, path, Node::file(data))
+    }
+}
+
+impl Default for Dir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiDir for Dir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        _symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        _read: bool,
+        _write: bool,
+        fdflags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        let (dir, name) = parent(&self.0, path)?;
+        let mut entries = dir.entries.write().unwrap();
+
+        let node = match entries.get(name) {
+            Some(..) if oflags.contains(OFlags::CREATE | OFlags::EXCLUSIVE) => {
+                return Err(errno(libc::EEXIST))
+            }
+            Some(Node::Dir(..)) => return Err(errno(libc::EISDIR)),
+            Some(Node::File(..)) if oflags.contains(OFlags::DIRECTORY) => {
+                return Err(errno(libc::ENOTDIR))
+            }
+            Some(Node::File(f)) => f.clone(),
+            None if oflags.contains(OFlags::CREATE) => match Node::file(Vec::new()) {
+                Node::File(f) => {
+                    entries.insert(name.into(), Node::File(f.clone()));
+                    f
+                }
+                Node::Dir(..) => unreachable!(),
+            },
+            None => return Err(errno(libc::ENOENT)),
+        };
+
+        if oflags.contains(OFlags::TRUNCATE) {
+            node.data.write().unwrap().clear();
+        }
+
+        Ok(Box::new(File {
+            node,
+            pos: 0,
+            append: fdflags.contains(FdFlags::APPEND),
+        }))
+    }
+
+    async fn open_dir(&self, _symlink_follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        match lookup(&self.0, path)? {
+            Node::Dir(d) => Ok(Box::new(Dir(d))),
+            Node::File(..) => Err(errno(libc::ENOTDIR)),
+        }
+    }
+
+    async fn create_dir(&self, path: &str) -> Result<(), Error> {
+        self.add_dir(path)
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        let inode = self.0.inode;
+        let dots = [
+            (".", FileType::Directory, inode),
+            ("..", FileType::Directory, inode),
+        ];
+
+        let entries = self.0.entries.read().unwrap();
+        let all = dots
+            .into_iter()
+            .map(|(name, filetype, inode)| (name.to_string(), filetype, inode))
+            .chain(
+                entries
+                    .iter()
+                    .map(|(name, node)| (name.clone(), node.filetype(), node.inode())),
+            );
+
+        let list: Vec<_> = all
+            .enumerate()
+            .skip(u64::from(cursor) as usize)
+            .map(|(i, (name, filetype, inode))| {
+                Ok(ReaddirEntity {
+                    next: ReaddirCursor::from(i as u64 + 1),
+                    inode,
+                    name,
+                    filetype,
+                })
+            })
+            .collect();
+
+        Ok(Box::new(list.into_iter()))
+    }
+
+    async fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<(), Error> {
+        Err(errno(libc::ENOTSUP))
+    }
+
+    async fn remove_dir(&self, path: &str) -> Result<(), Error> {
+        let (dir, name) = parent(&self.0, path)?;
+        let mut entries = dir.entries.write().unwrap();
+
+        match entries.get(name) {
+            Some(Node::Dir(d)) if d.entries.read().unwrap().is_empty() => {}
+            Some(Node::Dir(..)) => return Err(errno(libc::ENOTEMPTY)),
+            Some(Node::File(..)) => return Err(errno(libc::ENOTDIR)),
+            None => return Err(errno(libc::ENOENT)),
+        }
+        entries.remove(name);
+        Ok(())
+    }
+
+    async fn unlink_file(&self, path: &str) -> Result<(), Error> {
+        let (dir, name) = parent(&self.0, path)?;
+        let mut entries = dir.entries.write().unwrap();
+
+        match entries.get(name) {
+            Some(Node::File(..)) => {}
+            Some(Node::Dir(..)) => return Err(errno(libc::EISDIR)),
+            None => return Err(errno(libc::ENOENT)),
+        }
+        entries.remove(name);
+        Ok(())
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        // Nothing is a symlink
+        lookup(&self.0, path)?;
+        Err(errno(libc::EINVAL))
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(Node::Dir(self.0.clone()).stat())
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        _follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        Ok(lookup(&self.0, path)?.stat())
+    }
+
+    async fn rename(
+        &self,
+        path: &str,
+        dest_dir: &dyn WasiDir,
+        dest_path: &str,
+    ) -> Result<(), Error> {
+        let dest = dest_dir
+            .as_any()
+            .downcast_ref::<Dir>()
+            .ok_or_else(|| errno(libc::EXDEV))?;
+
+        let (src, name) = parent(&self.0, path)?;
+        let (dst, dest_name) = parent(&dest.0, dest_path)?;
+
+        let node = lookup(&src, name)?;
+        if Arc::ptr_eq(&src, &dst) && name == dest_name {
+            return Ok(());
+        }
+        if let Node::Dir(d) = &node {
+            // A directory must not become its own descendant
+            if contains(d, &dst) {
+                return Err(errno(libc::EINVAL));
+            }
+        }
+
+        match (&node, dst.entries.read().unwrap().get(dest_name)) {
+            (_, None) | (Node::File(..), Some(Node::File(..))) => {}
+            (Node::Dir(..), Some(Node::Dir(d))) if d.entries.read().unwrap().is_empty() => {}
+            (Node::Dir(..), Some(Node::Dir(..))) => return Err(errno(libc::ENOTEMPTY)),
+            (Node::Dir(..), Some(Node::File(..))) => return Err(errno(libc::ENOTDIR)),
+            (Node::File(..), Some(Node::Dir(..))) => return Err(errno(libc::EISDIR)),
+        }
+
+        // The parents may be the same directory, so lock them one at a time
+        src.entries.write().unwrap().remove(name);
+        dst.entries.write().unwrap().insert(dest_name.into(), node);
+        Ok(())
+    }
+
+    async fn hard_link(
+        &self,
+        path: &str,
+        target_dir: &dyn WasiDir,
+        target_path: &str,
+    ) -> Result<(), Error> {
+        let target = target_dir
+            .as_any()
+            .downcast_ref::<Dir>()
+            .ok_or_else(|| errno(libc::EXDEV))?;
+
+        let node = match lookup(&self.0, path)? {
+            Node::File(f) => Node::File(f),
+            Node::Dir(..) => return Err(errno(libc::EPERM)),
+        };
+
+        insert(&target.0, target_path, node)
+    }
+
+    async fn set_times(
+        &self,
+        path: &str,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+        _follow_symlinks: bool,
+    ) -> Result<(), Error> {
+        // No timestamps are kept
+        lookup(&self.0, path).map(|_| ())
+    }
+}
+
+/// An open file of the in-memory filesystem
+#[derive(Debug)]
+pub struct File {
+    node: Arc<FileNode>,
+    pos: u64,
+    append: bool,
+}
+
+impl File {
+    fn read_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> u64 {
+        let data = self.node.data.read().unwrap();
+        let mut pos = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        let mut total = 0;
+
+        for buf in bufs {
+            let n = buf.len().min(data.len() - pos);
+            buf[..n].copy_from_slice(&data[pos..pos + n]);
+            pos += n;
+            total += n;
+        }
+
+        total as u64
+    }
+
+    fn write_at(&self, bufs: &[IoSlice<'_>], offset: u64) -> Result<u64, Error> {
+        let mut data = self.node.data.write().unwrap();
+        let start = usize::try_from(offset).map_err(|_| errno(libc::EFBIG))?;
+        let len: usize = bufs.iter().map(|b| b.len()).sum();
+        let end = start.checked_add(len).ok_or_else(|| errno(libc::EFBIG))?;
+
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+
+        let mut pos = start;
+        for buf in bufs {
+            data[pos..pos + buf.len()].copy_from_slice(buf);
+            pos += buf.len();
+        }
+
+        Ok(len as u64)
+    }
+
+    fn len(&self) -> u64 {
+        self.node.data.read().unwrap().len() as u64
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for File {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn datasync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::RegularFile)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        Ok(match self.append {
+            true => FdFlags::APPEND,
+            false => FdFlags::empty(),
+        })
+    }
+
+    async fn set_fdflags(&mut self, fdflags: FdFlags) -> Result<(), Error> {
+        self.append = fdflags.contains(FdFlags::APPEND);
+        Ok(())
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        Ok(Node::File(self.node.clone()).stat())
+    }
+
+    async fn set_filestat_size(&mut self, size: u64) -> Result<(), Error> {
+        let size = usize::try_from(size).map_err(|_| errno(libc::EFBIG))?;
+        self.node.data.write().unwrap().resize(size, 0);
+        Ok(())
+    }
+
+    async fn advise(&mut self, _offset: u64, _len: u64, _advice: Advice) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn allocate(&mut self, offset: u64, len: u64) -> Result<(), Error> {
+        let end = offset.checked_add(len).ok_or_else(|| errno(libc::EFBIG))?;
+        if self.len() < end {
+            self.set_filestat_size(end).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_times(
+        &mut self,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let n = self.read_at(bufs, self.pos);
+        self.pos += n;
+        Ok(n)
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        Ok(self.read_at(bufs, offset))
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        if self.append {
+            self.pos = self.len();
+        }
+        let n = self.write_at(bufs, self.pos)?;
+        self.pos += n;
+        Ok(n)
+    }
+
+    async fn write_vectored_at<'a>(
+        &mut self,
+        bufs: &[IoSlice<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.write_at(bufs, offset)
+    }
+
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => offset_by(self.pos, offset),
+            SeekFrom::End(offset) => offset_by(self.len(), offset),
+        };
+        self.pos = pos.ok_or_else(|| errno(libc::EINVAL))?;
+        Ok(self.pos)
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        Ok(self.read_at(&mut [IoSliceMut::new(buf)], self.pos))
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(self.len().saturating_sub(self.pos))
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// `base` moved by `offset`, if it stays within `u64`
+fn offset_by(base: u64, offset: i64) -> Option<u64> {
+    match offset {
+        o if o < 0 => base.checked_sub(o.unsigned_abs()),
+        o => base.checked_add(o as u64),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    /// Run `f`, which never waits, as the filesystem does not block
+    fn run<F: Future>(f: F) -> F::Output {
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(
+            |_| RawWaker::new(std::ptr::null(), &VTABLE),
+            |_| {},
+            |_| {},
+            |_| {},
+        );
+
+        let waker = unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) };
+        let mut f = Box::pin(f);
+        match Pin::new(&mut f).poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the filesystem blocked"),
+        }
+    }
+
+    fn open(dir: &Dir, path: &str, oflags: OFlags) -> Result<Box<dyn WasiFile>, Error> {
+        run(dir.open_file(false, path, oflags, true, true, FdFlags::empty()))
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(components("a/./b//c/../d").unwrap(), ["a", "b", "d"]);
+        assert_eq!(components("").unwrap(), Vec::<&str>::new());
+        assert!(components("a/../..").is_err());
+        assert!(components("/etc").is_err());
+    }
+
+    #[test]
+    fn files() {
+        let dir = Dir::new();
+        run(dir.create_dir("sub")).unwrap();
+        assert!(run(dir.create_dir("sub")).is_err());
+        assert!(open(&dir, "sub/file", OFlags::empty()).is_err());
+
+        let mut file = open(&dir, "sub/file", OFlags::CREATE).unwrap();
+        let n = run(file.write_vectored(&[IoSlice::new(b"Hello, "), IoSlice::new(b"world!")]));
+        assert_eq!(n.unwrap(), 13);
+        assert_eq!(run(file.seek(SeekFrom::Start(7))).unwrap(), 7);
+
+        let mut buf = [0u8; 16];
+        let n = run(file.read_vectored(&mut [IoSliceMut::new(&mut buf)])).unwrap();
+        assert_eq!(&buf[..n as usize], b"world!");
+
+        let mut file = open(&dir, "sub/./file", OFlags::TRUNCATE).unwrap();
+        assert_eq!(run(file.get_filestat()).unwrap().size, 0);
+        assert!(open(&dir, "sub/file", OFlags::CREATE | OFlags::EXCLUSIVE).is_err());
+
+        run(dir.rename("sub/file", &dir, "moved")).unwrap();
+        assert!(run(dir.remove_dir("sub")).is_ok());
+        assert!(run(dir.unlink_file("moved")).is_ok());
+        assert!(run(dir.get_path_filestat("moved", false)).is_err());
+    }
+
+    #[test]
+    fn readdir() {
+        let dir = Dir::new();
+        run(dir.create_dir("b")).unwrap();
+        open(&dir, "a", OFlags::CREATE).unwrap();
+
+        let names: Vec<_> = run(dir.readdir(ReaddirCursor::from(0)))
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect();
+        assert_eq!(names, [".", "..", "a", "b"]);
+
+        let rest: Vec<_> = run(dir.readdir(ReaddirCursor::from(3)))
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect();
+        assert_eq!(rest, ["b"]);
+    }
+
+    #[test]
+    fn no_escape() {
+        let dir = Dir::new();
+        run(dir.create_dir("a")).unwrap();
+        assert!(run(dir.rename("a", &dir, "a/b")).is_err());
+        assert!(open(&dir, "../x", OFlags::CREATE).is_err());
+    }
+}
@@ -1,8 +1,29 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use log::{debug, info};
+use super::bundle::{self, RESOURCES_PATH};
+use super::config::{Config, FdKind, StdioMode};
+use super::memfs;
+
+use std::fs::File;
+use std::mem::MaybeUninit;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use log::{debug, error, info};
+use wasi_common::dir::DirCaps;
+use wasi_common::file::FileCaps;
+use wasi_common::pipe::{ReadPipe, WritePipe};
+use wasi_common::{WasiDir, WasiFile};
+use wasmtime::{InterruptHandle, Trap, Val, ValType};
 use wasmtime_wasi::sync::WasiCtxBuilder;
 
+/// The interrupt handle of the running workload, for [`on_sigterm`]
+static INTERRUPT: AtomicPtr<InterruptHandle> = AtomicPtr::new(null_mut());
+
+/// Whether the workload was asked to stop
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
 /// The error codes of workload execution.
 // clippy doesn't like how "ConfigurationError" ends with "Error", so..
 #[allow(clippy::enum_variant_names)]
@@ -16,14 +37,22 @@ pub enum Error {
     ExportNotFound,
     /// module instantiation failed
     InstantiationFailed,
+    /// arguments not matching the signature of the invoked function
+    InvalidArguments,
     /// call failed
     CallFailed,
+    /// the workload trapped
+    Trap(Trap),
+    /// the workload exited with WASI `proc_exit`
+    Exit(i32),
     /// I/O error
     IoError(std::io::Error),
     /// WASI error
     WASIError(wasmtime_wasi::Error),
     /// Arguments or environment too large
     StringTableError,
+    /// stopped by `SIGTERM`
+    Terminated,
 }
 
 impl From<std::io::Error> for Error {
@@ -53,12 +82,22 @@ impl From<Error> for i32 {
             InstantiationFailed => 65,
             ExportNotFound => 65,
             CallFailed => 65,
+            Trap(_) => 65,
+
+            // Bad arguments for the invoked function -> EX_USAGE
+            InvalidArguments => 64,
 
             // Internal WASI errors -> EX_SOFTWARE
             WASIError(_) => 70,
 
             // General IO errors -> EX_IOERR
             IoError(_) => 74,
+
+            // The status the workload asked for
+            Exit(status) => status,
+
+            // Like the default action of the signal
+            Terminated => 128 + libc::SIGTERM,
         }
     }
 }
@@ -66,87 +105,361 @@ impl From<Error> for i32 {
 /// Result type used throughout the library.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Wrap the host file descriptor `fd` for WASI
+fn wasi_file(fd: RawFd) -> Box<dyn WasiFile> {
+    let file = unsafe { File::from_raw_fd(fd) };
+    let file = cap_std::fs::File::from_std(file, cap_std::ambient_authority());
+    Box::new(wasmtime_wasi::sync::file::File::from_cap_std(file))
+}
+
+/// A duplicate of the preopened file descriptor `name`, for use as stdio
+fn preopened(config: &Config, name: &str) -> Result<Box<dyn WasiFile>> {
+    let preopen = config
+        .fds
+        .iter()
+        .find(|fd| fd.name == name)
+        .ok_or(Error::ConfigurationError)?;
+
+    // The preopened file descriptor is handed to the workload, too
+    match unsafe { libc::dup(preopen.fd) } {
+        fd if fd < 0 => Err(std::io::Error::last_os_error().into()),
+        fd => Ok(wasi_file(fd)),
+    }
+}
+
+/// Parse `args` as the parameters of a function of type `ty`
+///
+/// Only numbers are supported: `i32` also takes unsigned values, floats
+/// anything Rust parses, e.g. `1.5`, `-inf` or `NaN`.
+pub fn params(ty: &wasmtime::FuncType, args: &[String]) -> Result<Vec<Val>> {
+    if ty.params().len() != args.len() {
+        return Err(Error::InvalidArguments);
+    }
+
+    ty.params()
+        .zip(args)
+        .map(|(ty, arg)| {
+            let val = match ty {
+                ValType::I32 => arg
+                    .parse::<i32>()
+                    .or_else(|_| arg.parse::<u32>().map(|v| v as i32))
+                    .map(Val::I32)
+                    .ok(),
+                ValType::I64 => arg
+                    .parse::<i64>()
+                    .or_else(|_| arg.parse::<u64>().map(|v| v as i64))
+                    .map(Val::I64)
+                    .ok(),
+                ValType::F32 => arg.parse::<f32>().map(|v| Val::F32(v.to_bits())).ok(),
+                ValType::F64 => arg.parse::<f64>().map(|v| Val::F64(v.to_bits())).ok(),
+                _ => None,
+            };
+            val.ok_or(Error::InvalidArguments)
+        })
+        .collect()
+}
+
+/// Format the results of a function call as a JSON array
+///
+/// Each value is an object with its `type` and `value`. Floats, which are
+/// not finite, are written as the strings `"NaN"`, `"inf"` and `"-inf"`.
+pub fn results_json(results: &[Val]) -> String {
+    fn float(v: impl std::fmt::Debug + std::fmt::Display, finite: bool) -> String {
+        match finite {
+            true => format!("{:?}", v),
+            false => format!("\"{}\"", v),
+        }
+    }
+
+    let values: Vec<_> = results
+        .iter()
+        .map(|val| {
+            let (ty, value) = match val {
+                Val::I32(v) => ("i32", v.to_string()),
+                Val::I64(v) => ("i64", v.to_string()),
+                Val::F32(v) => {
+                    let v = f32::from_bits(*v);
+                    ("f32", float(v, v.is_finite()))
+                }
+                Val::F64(v) => {
+                    let v = f64::from_bits(*v);
+                    ("f64", float(v, v.is_finite()))
+                }
+                Val::V128(_) => ("v128", "null".into()),
+                Val::FuncRef(_) => ("funcref", "null".into()),
+                Val::ExternRef(_) => ("externref", "null".into()),
+            };
+            format!("{{\"type\":\"{}\",\"value\":{}}}", ty, value)
+        })
+        .collect();
+
+    format!("[{}]", values.join(","))
+}
+
+/// Describe `trap` with its code and wasm backtrace
+///
+/// The functions are named after the name section of the module, if any.
+pub fn describe_trap(trap: &Trap) -> String {
+    let mut text = match trap.trap_code() {
+        Some(code) => format!("the workload trapped: {:?}\n", code),
+        None => "the workload trapped\n".to_string(),
+    };
+
+    text.push_str("wasm backtrace:");
+    for (i, frame) in trap.trace().iter().enumerate() {
+        let module = frame.module_name().unwrap_or("<unknown>");
+        let func = match frame.func_name() {
+            Some(name) => name.to_string(),
+            None => format!("<wasm function {}>", frame.func_index()),
+        };
+        text.push_str(&format!(
+            "\n  {:>3}: {:#8x} - {}!{}",
+            i,
+            frame.module_offset(),
+            module,
+            func
+        ));
+    }
+
+    text
+}
+
+/// Stop the workload gracefully on `SIGTERM`
+///
+/// The WebAssembly code traps on its next function call or loop iteration,
+/// while the syscall it might be blocked in fails with `EINTR`.
+fn stop_on_sigterm(handle: InterruptHandle) -> Result<()> {
+    extern "C" fn on_sigterm(_: libc::c_int) {
+        TERMINATED.store(true, Ordering::SeqCst);
+
+        let handle = INTERRUPT.load(Ordering::SeqCst);
+        if !handle.is_null() {
+            unsafe { (*handle).interrupt() };
+        }
+    }
+
+    // The handle is needed until the process exits
+    let handle = Box::into_raw(Box::new(handle));
+    let old = INTERRUPT.swap(handle, Ordering::SeqCst);
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+
+    // Without `SA_RESTART`, so blocking syscalls return
+    let mut act: libc::sigaction = unsafe { MaybeUninit::zeroed().assume_init() };
+    act.sa_sigaction = on_sigterm as usize;
+    unsafe { libc::sigemptyset(&mut act.sa_mask) };
+
+    if unsafe { libc::sigaction(libc::SIGTERM, &act, null_mut()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    Ok(())
+}
+
 /// Runs a WebAssembly workload.
-// TODO: refactor this into multiple steps
-// Since we're not bundling the launch/deployment config into `bytes`, the
-// naive solution would just be to add new arguments for those things, like
-// WasmFeatures, stdio handling, etc - but that gets messy quick.
-// Instead we should probably refactor this into distinct steps, each with
-// its own config options (and error variants - see above).
-pub fn run<T: AsRef<str>, U: AsRef<str>>(
-    bytes: impl AsRef<[u8]>,
-    args: impl IntoIterator<Item = T>,
-    envs: impl IntoIterator<Item = (U, U)>,
-) -> Result<Vec<wasmtime::Val>> {
+// TODO: refactor this into multiple steps, each with its own config
+// options (and error variants - see above).
+pub fn run(bytes: impl AsRef<[u8]>, config: &Config) -> Result<Vec<wasmtime::Val>> {
     debug!("configuring wasmtime engine");
-    let mut config = wasmtime::Config::new();
+    let mut engine_config = wasmtime::Config::new();
     // Support module-linking (https://github.com/webassembly/module-linking)
-    config.wasm_module_linking(true);
+    engine_config.wasm_module_linking(true);
     // module-linking requires multi-memory
-    config.wasm_multi_memory(true);
+    engine_config.wasm_multi_memory(true);
 
     // Prefer dynamic memory allocation style over static memory
-    config.static_memory_maximum_size(0);
-    config.static_memory_guard_size(0);
-    config.dynamic_memory_guard_size(0);
-    config.dynamic_memory_reserved_for_growth(16 * 1024 * 1024);
+    engine_config.static_memory_maximum_size(0);
+    engine_config.static_memory_guard_size(0);
+    engine_config.dynamic_memory_guard_size(0);
+    engine_config.dynamic_memory_reserved_for_growth(16 * 1024 * 1024);
 
-    let engine = wasmtime::Engine::new(&config).or(Err(Error::ConfigurationError))?;
+    // Allow to stop the workload on SIGTERM
+    engine_config.interruptable(true);
+
+    let engine = wasmtime::Engine::new(&engine_config).or(Err(Error::ConfigurationError))?;
+
+    debug!("instantiating module from bytes");
+    let module = wasmtime::Module::from_binary(&engine, bytes.as_ref())?;
+
+    debug!("unpacking bundled resources");
+    let resources = bundle::resources(bytes.as_ref()).map_err(|e| {
+        error!("failed to unpack the bundled resources: {}", e);
+        Error::InstantiationFailed
+    })?;
+    let resources_path = resources.as_ref().map(|_| RESOURCES_PATH);
 
     debug!("instantiating wasmtime linker");
     let mut linker = wasmtime::Linker::new(&engine);
 
-    // TODO: read config, set up filehandles & sockets, etc etc
-
     debug!("adding WASI to linker");
     wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
 
     debug!("creating WASI context");
     let mut wasi = WasiCtxBuilder::new();
-    for arg in args {
-        wasi = wasi.arg(arg.as_ref()).or(Err(Error::StringTableError))?;
+    for arg in &config.args {
+        wasi = wasi.arg(arg).or(Err(Error::StringTableError))?;
+    }
+    for (k, v) in &config.env {
+        wasi = wasi.env(k, v).or(Err(Error::StringTableError))?;
     }
-    for kv in envs {
+
+    // Tell the workload which file descriptors it got, much like systemd's
+    // `LISTEN_FDNAMES`.
+    if !config.dirs.is_empty() || resources.is_some() || !config.fds.is_empty() {
+        let names = ["stdin", "stdout", "stderr"]
+            .into_iter()
+            .chain(config.dirs.iter().map(|dir| dir.path.as_str()))
+            .chain(resources_path)
+            .chain(config.fds.iter().map(|fd| fd.name.as_str()))
+            .collect::<Vec<_>>();
+        wasi = wasi
+            .env("FD_COUNT", &names.len().to_string())
+            .or(Err(Error::StringTableError))?;
         wasi = wasi
-            .env(kv.0.as_ref(), kv.1.as_ref())
+            .env("FD_NAMES", &names.join(":"))
             .or(Err(Error::StringTableError))?;
     }
 
-    // v0.1.0 KEEP-CONFIG HACK: let wasmtime/wasi inherit our stdio.
-    // FIXME: this isn't a safe default if you don't trust the host!
-    info!("inheriting stdio from calling process");
-    wasi = wasi.inherit_stdio();
+    // The host routes stdio explicitly in the keep configuration
+    info!("stdio: {:?}", config.stdio);
+    wasi = match &config.stdio.stdin {
+        StdioMode::Inherit => wasi.inherit_stdin(),
+        StdioMode::Null => wasi.stdin(Box::new(ReadPipe::from(Vec::new()))),
+        StdioMode::Fd(name) => wasi.stdin(preopened(config, name)?),
+    };
+    wasi = match &config.stdio.stdout {
+        StdioMode::Inherit => wasi.inherit_stdout(),
+        StdioMode::Null => wasi.stdout(Box::new(WritePipe::new(std::io::sink()))),
+        StdioMode::Fd(name) => wasi.stdout(preopened(config, name)?),
+    };
+    wasi = match &config.stdio.stderr {
+        StdioMode::Inherit => wasi.inherit_stderr(),
+        StdioMode::Null => wasi.stderr(Box::new(WritePipe::new(std::io::sink()))),
+        StdioMode::Fd(name) => wasi.stderr(preopened(config, name)?),
+    };
+
+    let mut ctx = wasi.build();
+
+    // The preopened directories follow stdio, as the workload looks for
+    // them up to the first file descriptor, which is not a directory.
+    for (fd, preopen) in (3..).zip(config.dirs.iter()) {
+        debug!("inserting {:?} as fd {}", preopen, fd);
+
+        let dir: Box<dyn WasiDir> = match preopen.fd {
+            Some(host) => {
+                let dir = unsafe { cap_std::fs::Dir::from_raw_fd(host) };
+                Box::new(wasmtime_wasi::sync::dir::Dir::from_cap_std(dir))
+            }
+            None => Box::new(memfs::Dir::new()),
+        };
+
+        let (dir_caps, file_caps) = match preopen.writable {
+            true => (DirCaps::all(), FileCaps::all()),
+            false => (READ_ONLY_DIR, READ_ONLY_FILE),
+        };
+
+        ctx.insert_dir(fd, dir, dir_caps, file_caps, preopen.path.clone().into());
+    }
+
+    // The bundled resources follow the configured directories
+    let mut first = 3 + config.dirs.len() as u32;
+    if let Some(resources) = resources {
+        debug!("inserting the bundled resources as fd {}", first);
+        let path = RESOURCES_PATH.into();
+        ctx.insert_dir(
+            first,
+            Box::new(resources),
+            READ_ONLY_DIR,
+            READ_ONLY_FILE,
+            path,
+        );
+        first += 1;
+    }
+
+    // The preopened file descriptors follow the directories
+    for (fd, preopen) in (first..).zip(config.fds.iter()) {
+        debug!("inserting {:?} as fd {}", preopen, fd);
+
+        let file = wasi_file(preopen.fd);
+
+        // FUTURE: sockets should get socket capabilities (sock_accept et al.)
+        // once our wasmtime supports them.
+        let caps = match (preopen.kind, preopen.writable) {
+            (FdKind::File, false) => READ_ONLY_FILE,
+            _ => FileCaps::all(),
+        };
+
+        ctx.insert_file(fd, file, caps);
+    }
 
     debug!("creating wasmtime Store");
-    let mut store = wasmtime::Store::new(&engine, wasi.build());
+    let mut store = wasmtime::Store::new(&engine, ctx);
 
-    debug!("instantiating module from bytes");
-    let module = wasmtime::Module::from_binary(&engine, bytes.as_ref())?;
+    debug!("stopping the workload on SIGTERM");
+    let handle = store
+        .interrupt_handle()
+        .or(Err(Error::ConfigurationError))?;
+    stop_on_sigterm(handle)?;
 
     debug!("adding module to store");
     linker
         .module(&mut store, "", &module)
         .or(Err(Error::InstantiationFailed))?;
 
-    // TODO: use the --invoke FUNCTION name, if any
-    debug!("getting module's default function");
-    let func = linker
-        .get_default(&mut store, "")
-        .or(Err(Error::ExportNotFound))?;
+    let (func, params) = match &config.invoke {
+        Some(invoke) => {
+            debug!("getting exported function {:?}", invoke.function);
+            let func = linker
+                .get(&mut store, "", Some(&invoke.function))
+                .and_then(|export| export.into_func())
+                .ok_or(Error::ExportNotFound)?;
+            let params = params(&func.ty(&store), &config.args)?;
+            (func, params)
+        }
+        None => {
+            debug!("getting module's default function");
+            let func = linker
+                .get_default(&mut store, "")
+                .or(Err(Error::ExportNotFound))?;
+            (func, Vec::new())
+        }
+    };
 
     debug!("calling function");
     let mut results = vec![wasmtime::Val::null(); func.ty(&store).results().len()];
 
-    func.call(store, Default::default(), &mut results)
-        .or(Err(Error::CallFailed))?;
+    if TERMINATED.load(Ordering::SeqCst) {
+        return Err(Error::Terminated);
+    }
+
+    func.call(store, &params, &mut results).map_err(|e| {
+        if TERMINATED.load(Ordering::SeqCst) {
+            return Error::Terminated;
+        }
+
+        match e.downcast::<Trap>() {
+            Ok(trap) => match trap.i32_exit_status() {
+                Some(status) => Error::Exit(status),
+                None => Error::Trap(trap),
+            },
+            Err(_) => Error::CallFailed,
+        }
+    })?;
 
     Ok(results)
 }
 
 #[cfg(test)]
 pub(crate) mod test {
+    use crate::config::{Config, Fd, FdKind, Invoke, Stdio, StdioMode};
     use crate::workload;
-    use std::iter::empty;
+
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    use wasmtime::Val;
 
     const NO_EXPORT_WAT: &str = r#"(module
       (memory (export "") 1)
@@ -156,6 +469,27 @@ pub(crate) mod test {
       (func (export "") (result i32) i32.const 1)
     )"#;
 
+    const ADD_WAT: &str = r#"(module
+      (func (export "add") (param i32 i32) (result i32)
+        (i32.add (local.get 0) (local.get 1)))
+      (func (export "half") (param f64) (result f64 i64)
+        (f64.div (local.get 0) (f64.const 2))
+        (i64.const -1))
+    )"#;
+
+    const EXIT_3_WAT: &str = r#"(module
+      (import "wasi_snapshot_preview1" "proc_exit"
+        (func $__wasi_proc_exit (param i32)))
+      (func (export "_start")
+        (call $__wasi_proc_exit (i32.const 3)))
+      (memory (export "memory") 1)
+    )"#;
+
+    const TRAP_WAT: &str = r#"(module $trap
+      (func $boom unreachable)
+      (func (export "_start") (call $boom))
+    )"#;
+
     const WASI_COUNT_ARGS_WAT: &str = r#"(module
       (import "wasi_snapshot_preview1" "args_sizes_get"
         (func $__wasi_args_sizes_get (param i32 i32) (result i32)))
@@ -200,12 +534,11 @@ pub(crate) mod test {
     fn workload_run_return_1() {
         let bytes = wat::parse_str(RETURN_1_WAT).expect("error parsing wat");
 
-        let results: Vec<i32> =
-            workload::run(&bytes, empty::<String>(), empty::<(String, String)>())
-                .unwrap()
-                .iter()
-                .map(|v| v.unwrap_i32())
-                .collect();
+        let results: Vec<i32> = workload::run(&bytes, &Config::default())
+            .unwrap()
+            .iter()
+            .map(|v| v.unwrap_i32())
+            .collect();
 
         assert_eq!(results, vec![1]);
     }
@@ -214,7 +547,7 @@ pub(crate) mod test {
     fn workload_run_no_export() {
         let bytes = wat::parse_str(NO_EXPORT_WAT).expect("error parsing wat");
 
-        match workload::run(&bytes, empty::<String>(), empty::<(String, String)>()) {
+        match workload::run(&bytes, &Config::default()) {
             Err(workload::Error::ExportNotFound) => {}
             _ => panic!("unexpected error"),
         };
@@ -224,15 +557,17 @@ pub(crate) mod test {
     fn workload_run_wasi_count_args() {
         let bytes = wat::parse_str(WASI_COUNT_ARGS_WAT).expect("error parsing wat");
 
-        let results: Vec<i32> = workload::run(
-            &bytes,
-            vec!["a".to_string(), "b".to_string(), "c".to_string()],
-            vec![("k", "v")],
-        )
-        .unwrap()
-        .iter()
-        .map(|v| v.unwrap_i32())
-        .collect();
+        let config = Config {
+            args: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            env: [("k".to_string(), "v".to_string())].into_iter().collect(),
+            ..Default::default()
+        };
+
+        let results: Vec<i32> = workload::run(&bytes, &config)
+            .unwrap()
+            .iter()
+            .map(|v| v.unwrap_i32())
+            .collect();
 
         assert_eq!(results, vec![3]);
     }
@@ -240,14 +575,135 @@ pub(crate) mod test {
     #[test]
     fn workload_run_hello_wasi() {
         let bytes = wat::parse_str(HELLO_WASI_WAT).expect("error parsing wat");
-        let args: Vec<String> = vec![];
-        let envs: Vec<(String, String)> = vec![];
-
-        let results = workload::run(&bytes, args, envs).unwrap();
 
+        // Capture stdout with a pipe, preopened as a file, which the
+        // workload closes when it is done
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        let config = Config {
+            stdio: Stdio {
+                stdout: StdioMode::Fd("out".into()),
+                ..Default::default()
+            },
+            fds: vec![Fd {
+                name: "out".into(),
+                fd: fds[1],
+                kind: FdKind::File,
+                writable: true,
+            }],
+            ..Default::default()
+        };
+        let results = workload::run(&bytes, &config).unwrap();
         assert_eq!(results.len(), 0);
 
-        // TODO/FIXME: we need a way to configure WASI stdout so we can capture
-        // and check it here...
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"Hello, world!\n");
+    }
+
+    #[test]
+    fn workload_run_unknown_stdio_fd() {
+        let bytes = wat::parse_str(HELLO_WASI_WAT).expect("error parsing wat");
+
+        let config = Config {
+            stdio: Stdio {
+                stdout: StdioMode::Fd("nope".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        match workload::run(&bytes, &config) {
+            Err(workload::Error::ConfigurationError) => {}
+            _ => panic!("unexpected result"),
+        };
+    }
+
+    fn invoke(function: &str, args: &[&str]) -> Config {
+        Config {
+            args: args.iter().map(|a| a.to_string()).collect(),
+            invoke: Some(Invoke {
+                function: function.into(),
+                results_fd: 1,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn workload_invoke() {
+        let bytes = wat::parse_str(ADD_WAT).expect("error parsing wat");
+
+        let results = workload::run(&bytes, &invoke("add", &["1", "4294967295"])).unwrap();
+        assert_eq!(
+            workload::results_json(&results),
+            r#"[{"type":"i32","value":0}]"#
+        );
+
+        let results = workload::run(&bytes, &invoke("half", &["3"])).unwrap();
+        assert_eq!(
+            workload::results_json(&results),
+            r#"[{"type":"f64","value":1.5},{"type":"i64","value":-1}]"#
+        );
+    }
+
+    #[test]
+    fn workload_invoke_invalid() {
+        let bytes = wat::parse_str(ADD_WAT).expect("error parsing wat");
+
+        for (function, args) in [
+            ("add", &["1"][..]),
+            ("add", &["1", "x"]),
+            ("half", &["1", "2"]),
+        ] {
+            match workload::run(&bytes, &invoke(function, args)) {
+                Err(workload::Error::InvalidArguments) => {}
+                _ => panic!("unexpected result"),
+            }
+        }
+
+        match workload::run(&bytes, &invoke("sub", &[])) {
+            Err(workload::Error::ExportNotFound) => {}
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    #[test]
+    fn results_json() {
+        let results = [
+            Val::F32(f32::NAN.to_bits()),
+            Val::F64(f64::NEG_INFINITY.to_bits()),
+            Val::F32(0.25f32.to_bits()),
+        ];
+        assert_eq!(
+            workload::results_json(&results),
+            r#"[{"type":"f32","value":"NaN"},{"type":"f64","value":"-inf"},{"type":"f32","value":0.25}]"#
+        );
+    }
+
+    #[test]
+    fn workload_run_exit() {
+        let bytes = wat::parse_str(EXIT_3_WAT).expect("error parsing wat");
+
+        match workload::run(&bytes, &Config::default()) {
+            Err(e @ workload::Error::Exit(3)) => assert_eq!(i32::from(e), 3),
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    #[test]
+    fn workload_run_trap() {
+        let bytes = wat::parse_str(TRAP_WAT).expect("error parsing wat");
+
+        match workload::run(&bytes, &Config::default()) {
+            Err(workload::Error::Trap(trap)) => {
+                let text = workload::describe_trap(&trap);
+                assert!(text.contains("UnreachableCodeReached"), "{}", text);
+                assert!(text.contains("trap!boom"), "{}", text);
+            }
+            _ => panic!("unexpected result"),
+        }
     }
 }
@@ -4,7 +4,7 @@ use super::{Config, Loader, Mapper};
 
 use std::convert::TryInto;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use goblin::elf::{header::*, note::NoteIterator, program_header::*, Elf};
 use mmarinus::{perms, Kind, Map};
 use primordial::Page;
@@ -133,6 +133,66 @@ impl<'a> Binary<'a> {
 
         None
     }
+
+    /// Check that the shim supports the sallyport version of `enarx`
+    fn check_sallyport(&self) -> Result<()> {
+        let reqs: Vec<_> = self
+            .notes(elf::note::NAME, elf::note::REQUIRES)
+            .map(|n| String::from_utf8_lossy(n).into_owned())
+            .collect();
+
+        if reqs.is_empty() {
+            bail!("The shim is missing the sallyport version requirement note!");
+        }
+
+        let version = semver::Version::parse(sallyport::VERSION).unwrap();
+        let supported = reqs
+            .iter()
+            .filter_map(|n| semver::VersionReq::parse(n).ok())
+            .any(|req| req.matches(&version));
+        if !supported {
+            bail!(
+                "Unable to satisfy sallyport version requirement! The shim requires {}, enarx uses {}",
+                reqs.join(" or "),
+                version
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Check that `shim` can be loaded as a shim
+pub fn check_shim(shim: &[u8]) -> Result<()> {
+    Binary::new(shim)?.check_sallyport()
+}
+
+/// Check that `exec` can be loaded as an executable
+pub fn check_exec(exec: &[u8]) -> Result<()> {
+    Binary::new(exec).map(|_| ())
+}
+
+/// The number of bytes of memory `shim` and `exec` occupy, once loaded
+pub fn memory_size(shim: &[u8], exec: &[u8]) -> Result<usize> {
+    let sbin = Binary::new(shim)?;
+    let ebin = Binary::new(exec)?;
+
+    Ok(sbin
+        .segments(0)
+        .chain(ebin.segments(0))
+        .map(|seg| seg.range.end - seg.range.start)
+        .sum())
+}
+
+/// The sallyport version requirements noted in a shim
+pub fn sallyport_requires(shim: &[u8]) -> Result<Vec<String>> {
+    let sbin = Binary::new(shim)?;
+
+    Ok(sbin
+        .notes(elf::note::NAME, elf::note::REQUIRES)
+        .filter_map(|n| std::str::from_utf8(n).ok())
+        .map(String::from)
+        .collect())
 }
 
 impl<T: Mapper> Loader for T {
@@ -155,15 +215,7 @@ impl<T: Mapper> Loader for T {
         }
 
         // Check sallyport compatibility
-        let version = semver::Version::parse(sallyport::VERSION).unwrap();
-        let supported = sbin
-            .notes(elf::note::NAME, elf::note::REQUIRES)
-            .filter_map(|n| std::str::from_utf8(n).ok())
-            .filter_map(|n| semver::VersionReq::parse(n).ok())
-            .any(|req| req.matches(&version));
-        if !supported {
-            return Err(anyhow!("Unable to satisfy sallyport version requirement!"));
-        }
+        sbin.check_sallyport()?;
 
         // Parse the config and create a builder.
         let mut loader: Self = Self::Config::new(&sbin, &ebin)?.try_into()?;
@@ -41,7 +41,7 @@ impl TryFrom<super::config::Config> for Builder {
 
 impl super::super::Mapper for Builder {
     type Config = super::config::Config;
-    type Output = Arc<dyn super::super::Keep>;
+    type Output = Arc<RwLock<super::Keep<KvmKeepPersonality>>>;
 
     fn map(
         &mut self,
@@ -98,7 +98,7 @@ pub fn kvm_builder_map(
     Ok(mem_region)
 }
 
-impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
+impl TryFrom<Builder> for Arc<RwLock<super::Keep<KvmKeepPersonality>>> {
     type Error = Error;
 
     fn try_from(mut builder: Builder) -> Result<Self> {
@@ -110,6 +110,7 @@ impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
             vm_fd: builder.vm_fd,
             cpu_fds: vec![vcpu_fd],
             regions: builder.regions,
+            memory_max: None,
             sallyports: builder.sallyports,
             sallyport_start: sallyport_block_start,
             personality: KvmKeepPersonality(()),
@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::backend::Datum;
+use crate::backend::{Capability, Datum};
 
 use kvm_ioctls::Kvm;
 
@@ -29,3 +29,21 @@ pub fn kvm_version() -> Datum {
         mesg: None,
     }
 }
+
+pub fn memslots() -> Capability {
+    let slots = Kvm::new().map(|kvm| kvm.get_nr_memslots()).ok();
+
+    Capability {
+        name: "memslots",
+        value: slots.into(),
+    }
+}
+
+pub fn max_vcpus() -> Capability {
+    let vcpus = Kvm::new().map(|kvm| kvm.get_max_vcpus()).ok();
+
+    Capability {
+        name: "max_vcpus",
+        value: vcpus.into(),
+    }
+}
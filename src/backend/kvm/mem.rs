@@ -19,7 +19,14 @@ impl Region {
         }
     }
 
-    #[allow(dead_code)]
+    pub fn as_kvm(&self) -> &KvmUserspaceMemoryRegion {
+        &self.kvm_region
+    }
+
+    pub fn slot(&self) -> u32 {
+        self.kvm_region.slot
+    }
+
     pub fn as_guest(&self) -> Span<PhysAddr, u64> {
         Span {
             start: PhysAddr::new(self.kvm_region.guest_phys_addr),
@@ -3,12 +3,12 @@
 pub use kvm_bindings::kvm_userspace_memory_region as KvmUserspaceMemoryRegion;
 
 use super::Loader;
-use data::{dev_kvm, kvm_version};
+use data::{dev_kvm, kvm_version, max_vcpus, memslots};
 use mem::Region;
 
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use kvm_bindings::bindings::kvm_userspace_memory_region;
 use kvm_ioctls::Kvm;
 use kvm_ioctls::{VcpuFd, VmFd};
@@ -25,6 +25,10 @@ pub trait KeepPersonality {
     fn map(_vm_fd: &mut VmFd, _region: &Region) -> std::io::Result<()> {
         Ok(())
     }
+
+    fn unmap(_vm_fd: &mut VmFd, _region: &Region) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 struct KvmKeepPersonality(());
@@ -39,13 +43,44 @@ pub struct Keep<P: KeepPersonality> {
     pub sallyport_start: VirtAddr,
     pub sallyports: Vec<Option<VirtAddr>>,
     pub regions: Vec<Region>,
+    /// The maximum size of all regions together, if limited
+    pub memory_max: Option<usize>,
     pub personality: P,
 }
 
 impl<P: KeepPersonality> Keep<P> {
+    /// The size of all memory regions of the keep
+    pub fn memory_size(&self) -> usize {
+        self.regions
+            .iter()
+            .map(|r| r.as_guest().count as usize)
+            .sum()
+    }
+
+    /// Limit the memory of the keep to `max` bytes
+    pub fn set_memory_max(&mut self, max: Option<usize>) -> Result<()> {
+        if let Some(max) = max {
+            if self.memory_size() > max {
+                bail!(
+                    "the keep needs {} bytes of memory to start, more than the limit of {} bytes",
+                    self.memory_size(),
+                    max
+                );
+            }
+        }
+
+        self.memory_max = max;
+        Ok(())
+    }
+
     pub fn map(&mut self, pages: Map<perms::ReadWrite>, to: usize) -> std::io::Result<&mut Region> {
+        // Slots of removed regions are reused
+        let slot = (0..)
+            .find(|slot| !self.regions.iter().any(|r| r.slot() == *slot))
+            .unwrap();
+
         let kvm_region = kvm_userspace_memory_region {
-            slot: self.regions.len() as u32,
+            slot,
             flags: 0,
             guest_phys_addr: to as u64,
             memory_size: pages.len() as u64,
@@ -59,9 +94,39 @@ impl<P: KeepPersonality> Keep<P> {
         P::map(&mut self.vm_fd, &region)?;
 
         self.regions.push(region);
+        crate::metrics::memory(self.memory_size());
 
         Ok(self.regions.last_mut().unwrap())
     }
+
+    /// Remove the region of `size` bytes at guest physical address `from` and release its memory
+    pub fn unmap(&mut self, from: usize, size: usize) -> std::io::Result<()> {
+        let index = self
+            .regions
+            .iter()
+            .position(|r| {
+                let guest = r.as_guest();
+                guest.start.as_u64() == from as u64 && guest.count == size as u64
+            })
+            .ok_or_else(|| std::io::Error::from_raw_os_error(libc::EINVAL))?;
+
+        let region = &self.regions[index];
+
+        // A memory size of zero deletes the slot
+        let kvm_region = kvm_userspace_memory_region {
+            memory_size: 0,
+            ..*region.as_kvm()
+        };
+        unsafe { self.vm_fd.set_user_memory_region(kvm_region)? };
+
+        P::unmap(&mut self.vm_fd, region)?;
+
+        // Only now the guest cannot access the memory anymore
+        self.regions.remove(index);
+        crate::metrics::memory(self.memory_size());
+
+        Ok(())
+    }
 }
 
 pub struct Backend;
@@ -77,6 +142,11 @@ impl crate::backend::Backend for Backend {
         include_bytes!(concat!(env!("OUT_DIR"), "/bin/shim-sev"))
     }
 
+    #[inline]
+    fn shim_version(&self) -> &'static str {
+        env!("ENARX_SHIM_SEV_VERSION")
+    }
+
     #[inline]
     fn have(&self) -> bool {
         data::dev_kvm().pass
@@ -86,9 +156,19 @@ impl crate::backend::Backend for Backend {
         vec![dev_kvm(), kvm_version()]
     }
 
-    #[inline]
-    fn keep(&self, shim: &[u8], exec: &[u8]) -> Result<Arc<dyn super::Keep>> {
-        builder::Builder::load(shim, exec)
+    fn capabilities(&self) -> Vec<super::Capability> {
+        vec![memslots(), max_vcpus()]
+    }
+
+    fn keep(
+        &self,
+        shim: &[u8],
+        exec: &[u8],
+        memory: Option<usize>,
+    ) -> Result<Arc<dyn super::Keep>> {
+        let keep = builder::Builder::load(shim, exec)?;
+        keep.write().unwrap().set_memory_max(memory)?;
+        Ok(keep)
     }
 
     #[inline]
@@ -2,18 +2,28 @@
 
 use super::super::Command;
 use super::KeepPersonality;
+use crate::replay::{self, Kind};
+use crate::{faults, metrics};
 
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 use anyhow::{anyhow, Result};
 use kvm_ioctls::{VcpuExit, VcpuFd};
-use mmarinus::{perms, Kind, Map};
+use mmarinus::{perms, Kind as MapKind, Map};
 use primordial::{Address, Register};
 use sallyport::syscall::enarx::MemInfo;
 use sallyport::syscall::{SYS_ENARX_BALLOON_MEMORY, SYS_ENARX_MEM_INFO};
 use sallyport::Block;
 use sallyport::{Request, KVM_SYSCALL_TRIGGER_PORT};
 
+/// Return a ballooned memory region to the host
+///
+/// Arguments: the guest physical address and the size of a region, exactly
+/// as it was added by `SYS_ENARX_BALLOON_MEMORY`. The shim must not access
+/// the region afterwards.
+pub const SYS_ENARX_MEM_DEFLATE: i64 = 0xEA14;
+
 pub struct Thread<P: KeepPersonality> {
     keep: Arc<RwLock<super::Keep<P>>>,
     vcpu_fd: Option<VcpuFd>,
@@ -61,15 +71,25 @@ impl<P: KeepPersonality> Thread<P> {
             return Err(libc::EINVAL);
         }
 
+        let mut keep = self.keep.write().unwrap();
+
+        // Enforce the memory limit of the keep
+        if let Some(max) = keep.memory_max {
+            let total = size
+                .checked_mul(npgs)
+                .and_then(|bytes| bytes.checked_add(keep.memory_size()));
+            if total.map_or(true, |total| total > max) {
+                return Err(libc::ENOMEM);
+            }
+        }
+
         // Allocate the new memory
         let pages = Map::map(size * npgs)
             .anywhere()
             .anonymously()
-            .known::<perms::ReadWrite>(Kind::Private)
+            .known::<perms::ReadWrite>(MapKind::Private)
             .map_err(|e| e.err.raw_os_error().unwrap_or(libc::ENOTSUP))?;
 
-        let mut keep = self.keep.write().unwrap();
-
         // Map the memory into the VM
         let vaddr = keep
             .map(pages, addr)
@@ -77,10 +97,27 @@ impl<P: KeepPersonality> Thread<P> {
             .as_virt()
             .start;
 
+        metrics::balloon(size * npgs);
         Ok([vaddr.as_u64().into(), 0.into()])
     }
 
+    pub fn deflate(&mut self, req: &Request) -> Result<[Register<usize>; 2], i32> {
+        let addr: usize = req.arg[0].into(); // Guest Physical Address
+        let size: usize = req.arg[1].into(); // Size in bytes
+
+        self.keep
+            .write()
+            .unwrap()
+            .unmap(addr, size)
+            .map_err(|e| e.raw_os_error().unwrap_or(libc::ENOTSUP))?;
+
+        metrics::deflate(size);
+        Ok([0.into(), 0.into()])
+    }
+
     pub fn meminfo(&self, block: &mut Block) -> Result<[Register<usize>; 2], i32> {
+        metrics::meminfo();
+
         let keep = self.keep.read().unwrap();
 
         // The maximum number of memory slots possible for a virtual machine
@@ -92,10 +129,11 @@ impl<P: KeepPersonality> Thread<P> {
         // is implemented.
         let virt_start = Address::from(keep.sallyport_start.as_mut_ptr());
 
-        let mem_info: MemInfo = MemInfo {
+        let mut mem_info: MemInfo = MemInfo {
             virt_start,
             mem_slots,
         };
+        faults::meminfo(&mut mem_info);
 
         let c = block.cursor();
         c.write(&mem_info).map_err(|_| libc::ENOBUFS)?;
@@ -107,7 +145,14 @@ impl<P: KeepPersonality> Thread<P> {
 impl<P: KeepPersonality> super::super::Thread for Thread<P> {
     fn enter(&mut self) -> Result<Command<'_>> {
         let vcpu_fd = self.vcpu_fd.as_mut().unwrap();
-        match vcpu_fd.run()? {
+        let exit = match vcpu_fd.run() {
+            // A signal for the keep interrupted the vCPU, the shim fetches
+            // it on its next syscall.
+            Err(e) if e.errno() == libc::EINTR => return Ok(Command::Continue),
+            exit => exit?,
+        };
+
+        match exit {
             VcpuExit::IoOut(KVM_SYSCALL_TRIGGER_PORT, data) => {
                 debug_assert_eq!(data.len(), 2);
                 let block_nr = data[0] as usize + ((data[1] as usize) << 8);
@@ -121,18 +166,26 @@ impl<P: KeepPersonality> super::super::Thread for Thread<P> {
 
                 // To avoid clashing of rep and req in the union, clone the request
                 let req = unsafe { block.msg.req };
-
-                let ret = match i64::from(req.num) {
-                    SYS_ENARX_BALLOON_MEMORY => {
-                        let rep = self.balloon(&req).into();
-                        block.msg.rep = rep;
-                        Ok(Command::Continue)
-                    }
-
-                    SYS_ENARX_MEM_INFO => {
-                        block.msg.rep = self.meminfo(block).into();
-                        Ok(Command::Continue)
-                    }
+                let nr = i64::from(req.num);
+                let start = Instant::now();
+
+                let ret = match nr {
+                    SYS_ENARX_BALLOON_MEMORY => replay::hostcall(Kind::Backend, block, |block| {
+                        let mut rep = self.balloon(&req);
+                        faults::balloon(&mut rep);
+                        block.msg.rep = rep.into()
+                    })
+                    .map(|()| Command::Continue),
+
+                    SYS_ENARX_MEM_DEFLATE => replay::hostcall(Kind::Backend, block, |block| {
+                        block.msg.rep = self.deflate(&req).into()
+                    })
+                    .map(|()| Command::Continue),
+
+                    SYS_ENARX_MEM_INFO => replay::hostcall(Kind::Backend, block, |block| {
+                        block.msg.rep = self.meminfo(block).into()
+                    })
+                    .map(|()| Command::Continue),
 
                     #[cfg(feature = "gdb")]
                     sallyport::syscall::SYS_ENARX_GDB_START
@@ -142,9 +195,14 @@ impl<P: KeepPersonality> super::super::Thread for Thread<P> {
                         Ok(Command::Gdb(block, &mut self.gdb_fd))
                     }
 
-                    _ => Ok(Command::SysCall(block)),
+                    _ => Ok(Command::exit(&req).unwrap_or(Command::SysCall(block))),
                 };
 
+                // Requests proxied by the caller are recorded there
+                if let Ok(Command::Continue) = ret {
+                    metrics::hostcall(nr, start.elapsed());
+                }
+
                 // In case of gdb, this is unsafe, but we know the block is not misused in the main loop
                 self.keep.write().unwrap().sallyports[block_nr].replace(block_virt);
                 ret
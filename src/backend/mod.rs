@@ -9,9 +9,14 @@ pub mod sev;
 #[cfg(feature = "backend-sgx")]
 pub mod sgx;
 
+#[cfg(feature = "backend-nil")]
+pub mod nil;
+
 mod binary;
 mod probe;
 
+pub use binary::{check_exec, check_shim, memory_size, sallyport_requires};
+
 use binary::Binary;
 
 use std::convert::TryFrom;
@@ -22,6 +27,11 @@ use mmarinus::{perms, Map};
 use sallyport::Block;
 use spinning::Lazy;
 
+/// The sallyport call used by a shim to terminate the keep after a failure
+///
+/// Arguments: the exit status.
+pub const SYS_ENARX_SHIM_EXIT: i64 = 0xEA12;
+
 trait Config: Sized {
     type Flags;
 
@@ -52,11 +62,21 @@ pub trait Backend: Sync + Send {
     /// The builtin shim
     fn shim(&self) -> &'static [u8];
 
+    /// The version of the builtin shim
+    fn shim_version(&self) -> &'static str;
+
     /// The tests that show platform support for the backend
     fn data(&self) -> Vec<Datum>;
 
-    /// Create a keep instance
-    fn keep(&self, shim: &[u8], exec: &[u8]) -> Result<Arc<dyn Keep>>;
+    /// Machine-readable properties of the platform relevant to the backend
+    fn capabilities(&self) -> Vec<Capability> {
+        Vec::new()
+    }
+
+    /// Create a keep instance with up to `memory` bytes of memory
+    ///
+    /// Without a memory limit, the keep may use as much memory as the host grants.
+    fn keep(&self, shim: &[u8], exec: &[u8], memory: Option<usize>) -> Result<Arc<dyn Keep>>;
 
     /// Hash the inputs
     fn hash(&self, shim: &[u8], exec: &[u8]) -> Result<Vec<u8>>;
@@ -81,6 +101,15 @@ pub struct Datum {
     pub mesg: Option<String>,
 }
 
+/// A platform property, as reported by `enarx info --format json`
+pub struct Capability {
+    /// The name of the property
+    pub name: &'static str,
+
+    /// The value, `null` if it could not be determined
+    pub value: serde_json::Value,
+}
+
 pub trait Keep {
     /// Creates a new thread in the keep.
     fn spawn(self: Arc<Self>) -> Result<Option<Box<dyn Thread>>>;
@@ -104,6 +133,29 @@ pub enum Command<'a> {
 
     #[allow(dead_code)]
     Continue,
+
+    /// The calling thread of the workload exited with a status code
+    ThreadExit(i32),
+
+    /// The workload exited with a status code
+    Exit(i32),
+
+    /// The shim terminated the keep with a status code
+    ShimExit(i32),
+}
+
+impl Command<'_> {
+    /// Turn an exit request from the keep into the corresponding command
+    fn exit(req: &sallyport::Request) -> Option<Self> {
+        let status = usize::from(req.arg[0]) as i32;
+
+        match i64::from(req.num) {
+            libc::SYS_exit => Some(Self::ThreadExit(status)),
+            libc::SYS_exit_group => Some(Self::Exit(status)),
+            SYS_ENARX_SHIM_EXIT => Some(Self::ShimExit(status)),
+            _ => None,
+        }
+    }
 }
 
 pub static BACKENDS: Lazy<Vec<Box<dyn Backend>>> = Lazy::new(|| {
@@ -114,6 +166,8 @@ pub static BACKENDS: Lazy<Vec<Box<dyn Backend>>> = Lazy::new(|| {
         Box::new(sev::Backend),
         #[cfg(feature = "backend-kvm")]
         Box::new(kvm::Backend),
+        #[cfg(feature = "backend-nil")]
+        Box::new(nil::Backend),
     ]
 });
 
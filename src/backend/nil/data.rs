@@ -0,0 +1,30 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::backend::Datum;
+
+pub fn ptrace_scope() -> Datum {
+    let scope = std::fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+        .ok()
+        .map(|s| s.trim().to_string());
+
+    Datum {
+        name: "ptrace".into(),
+        // Yama's most restrictive mode forbids ptrace altogether
+        pass: scope.as_deref() != Some("3"),
+        info: scope.map(|s| format!("yama ptrace_scope {}", s)),
+        mesg: None,
+    }
+}
+
+pub fn isolation() -> Datum {
+    Datum {
+        name: "Isolation".into(),
+        pass: true,
+        info: Some("none".into()),
+        mesg: Some(
+            "The nil backend runs the workload as a normal process without any \
+             hardware isolation. Use it for testing only."
+                .into(),
+        ),
+    }
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A backend without any hardware isolation
+//!
+//! The executable runs as a normal child process under `ptrace`. Its
+//! syscalls are trapped and turned into sallyport requests, like a shim
+//! would do it, so the whole host side of `enarx` can be exercised on
+//! machines without KVM or a TEE. The few syscalls the process runs itself
+//! are confined by a seccomp filter. Still, the workload is not protected
+//! from the host in any way, so this backend is only meant for testing and
+//! has to be selected explicitly.
+
+use data::{isolation, ptrace_scope};
+use thread::Thread;
+
+use std::fs::File;
+use std::io::{Error, Write};
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+mod data;
+mod seccomp;
+mod thread;
+
+pub struct Backend;
+
+impl super::Backend for Backend {
+    #[inline]
+    fn name(&self) -> &'static str {
+        "nil"
+    }
+
+    #[inline]
+    fn shim(&self) -> &'static [u8] {
+        &[]
+    }
+
+    #[inline]
+    fn shim_version(&self) -> &'static str {
+        "none"
+    }
+
+    #[inline]
+    fn have(&self) -> bool {
+        ptrace_scope().pass
+    }
+
+    fn data(&self) -> Vec<super::Datum> {
+        vec![ptrace_scope(), isolation()]
+    }
+
+    fn keep(
+        &self,
+        _shim: &[u8],
+        exec: &[u8],
+        memory: Option<usize>,
+    ) -> Result<Arc<dyn super::Keep>> {
+        let fd = unsafe { libc::memfd_create(b"enarx-nil\0".as_ptr() as _, libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(Error::last_os_error()).context("failed to create a memfd");
+        }
+
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(exec)?;
+
+        Ok(Arc::new(Keep {
+            exec: file,
+            memory,
+            spawned: AtomicBool::new(false),
+        }))
+    }
+
+    #[inline]
+    fn hash(&self, _shim: &[u8], _exec: &[u8]) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+struct Keep {
+    /// The executable to run
+    exec: File,
+    /// The address space limit of the process
+    memory: Option<usize>,
+    /// Whether the process was started already
+    spawned: AtomicBool,
+}
+
+impl super::Keep for Keep {
+    fn spawn(self: Arc<Self>) -> Result<Option<Box<dyn super::Thread>>> {
+        if self.spawned.swap(true, Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        Ok(Some(Box::new(Thread::new(&self.exec, self.memory)?)))
+    }
+}
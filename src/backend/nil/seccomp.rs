@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The seccomp filter of the keep process
+//!
+//! The tracer skips every syscall it proxies or rejects, so only the native
+//! syscalls reach the kernel. The filter enforces that on its own, in case
+//! a syscall slips past the tracer, and narrows the native syscalls down to
+//! the process itself. Since Linux 4.8, the filter sees a syscall after the
+//! tracer.
+
+use libc::{c_int, c_long, sock_filter};
+
+// BPF instruction classes and modes, see `linux/bpf_common.h`
+const BPF_LD: u16 = 0x00;
+const BPF_W: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_JMP: u16 = 0x05;
+const BPF_JEQ: u16 = 0x10;
+const BPF_K: u16 = 0x00;
+const BPF_RET: u16 = 0x06;
+
+// Filter return values, see `linux/seccomp.h`
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+/// `AUDIT_ARCH_X86_64` of `linux/audit.h`
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+
+// Offsets into `struct seccomp_data`
+const NR: u32 = 0;
+const ARCH: u32 = 4;
+const ARG0: u32 = 16;
+
+fn load(offset: u32) -> sock_filter {
+    sock_filter {
+        code: BPF_LD | BPF_W | BPF_ABS,
+        jt: 0,
+        jf: 0,
+        k: offset,
+    }
+}
+
+fn jeq(k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: BPF_JMP | BPF_JEQ | BPF_K,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn ret(k: u32) -> sock_filter {
+    sock_filter {
+        code: BPF_RET | BPF_K,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+/// Build the filter for a process, which may run `native` syscalls and
+/// execute the file `exec` once
+///
+/// The syscall number and the first argument are compared as 32-bit values,
+/// like the kernel uses them.
+pub fn filter(native: &[c_long], exec: c_int) -> Vec<sock_filter> {
+    let mut filter = vec![
+        load(ARCH),
+        jeq(AUDIT_ARCH_X86_64, 1, 0),
+        ret(SECCOMP_RET_KILL_PROCESS),
+        load(NR),
+    ];
+
+    // Skipped by the tracer, which sets the return value itself
+    let skipped = -1;
+
+    // The tracer ends the keep on these
+    let exits = [libc::SYS_exit, libc::SYS_exit_group];
+
+    let allowed = native
+        .iter()
+        .filter(|nr| **nr != libc::SYS_prlimit64)
+        .chain(&exits)
+        .chain(&[skipped]);
+
+    for nr in allowed {
+        filter.push(jeq(*nr as u32, 0, 1));
+        filter.push(ret(SECCOMP_RET_ALLOW));
+    }
+
+    // Resource limits only of the process itself, so it cannot lift the
+    // limits of other processes of the user
+    if native.contains(&libc::SYS_prlimit64) {
+        filter.extend([
+            jeq(libc::SYS_prlimit64 as u32, 0, 4),
+            load(ARG0),
+            jeq(0, 0, 1),
+            ret(SECCOMP_RET_ALLOW),
+            ret(SECCOMP_RET_ERRNO | libc::EPERM as u32),
+        ]);
+    }
+
+    // Only the executable, which is closed on execution
+    filter.extend([
+        jeq(libc::SYS_execveat as u32, 0, 4),
+        load(ARG0),
+        jeq(exec as u32, 0, 1),
+        ret(SECCOMP_RET_ALLOW),
+        ret(SECCOMP_RET_KILL_PROCESS),
+    ]);
+
+    filter.push(ret(SECCOMP_RET_KILL_PROCESS));
+    filter
+}
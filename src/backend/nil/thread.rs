@@ -0,0 +1,860 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::Command;
+use super::seccomp;
+use crate::config::{EXEC_ARGS_MAX, SYS_ENARX_EXEC_ARGS, SYS_ENARX_KEEP_CONFIG};
+use crate::{limits, signal};
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Error;
+use std::iter::once;
+use std::mem::{size_of, MaybeUninit};
+use std::os::unix::io::AsRawFd;
+use std::ptr::{null, null_mut};
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+use sallyport::syscall::SYS_ENARX_GETATT;
+use sallyport::{request, Block, Request};
+
+/// `TeeTech::None`: there is nothing to attest
+const TEE_TECH_NONE: u64 = 0;
+
+/// The syscalls the process executes itself, as they only affect the process
+const NATIVE: &[libc::c_long] = &[
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_arch_prctl,
+    libc::SYS_set_tid_address,
+    libc::SYS_set_robust_list,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_uname,
+    libc::SYS_getrandom,
+    libc::SYS_prlimit64,
+];
+
+/// The syscalls proxied to the host, which only take integer arguments
+const PROXIED_INT: &[libc::c_long] = &[
+    libc::SYS_close,
+    libc::SYS_dup,
+    libc::SYS_dup2,
+    libc::SYS_dup3,
+    libc::SYS_eventfd2,
+    libc::SYS_epoll_create1,
+    libc::SYS_socket,
+    libc::SYS_listen,
+];
+
+/// The maximum number of `iovec`s, as in the kernel
+const IOV_MAX: usize = 1024;
+
+const SOCKADDR_MAX: usize = size_of::<libc::sockaddr_storage>();
+
+/// The data buffer of the block, which follows the message
+fn buffer(block: &mut Block) -> &mut [u8] {
+    let len = Block::buf_capacity();
+    let end = block as *mut Block as usize + size_of::<Block>();
+    unsafe { std::slice::from_raw_parts_mut((end - len) as *mut u8, len) }
+}
+
+/// Copy memory of process `pid` at `addr` into `buf`
+fn read_mem(pid: libc::pid_t, addr: usize, buf: &mut [u8]) -> Result<(), i32> {
+    let local = libc::iovec {
+        iov_base: buf.as_mut_ptr() as _,
+        iov_len: buf.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: addr as _,
+        iov_len: buf.len(),
+    };
+
+    match unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) } {
+        n if n >= 0 && n as usize == buf.len() => Ok(()),
+        _ => Err(libc::EFAULT),
+    }
+}
+
+/// Copy `buf` into the memory of process `pid` at `addr`
+fn write_mem(pid: libc::pid_t, addr: usize, buf: &[u8]) -> Result<(), i32> {
+    let local = libc::iovec {
+        iov_base: buf.as_ptr() as _,
+        iov_len: buf.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: addr as _,
+        iov_len: buf.len(),
+    };
+
+    match unsafe { libc::process_vm_writev(pid, &local, 1, &remote, 1, 0) } {
+        n if n >= 0 && n as usize == buf.len() => Ok(()),
+        _ => Err(libc::EFAULT),
+    }
+}
+
+/// A buffer to copy back into the process after a proxied syscall
+struct Output {
+    /// The offset of the data in the block buffer
+    offset: usize,
+    /// The address in the process
+    addr: usize,
+    len: usize,
+    /// If set, only the bytes the syscall reported in its return value
+    /// (counted in units of this size) are copied, in order of the outputs
+    unit: Option<usize>,
+}
+
+/// A syscall proxied to the host
+struct Pending {
+    /// The reply register returned to the process
+    ret: usize,
+    outputs: Vec<Output>,
+}
+
+/// Copies the syscall arguments of the process into the block buffer
+struct Marshal<'a> {
+    pid: libc::pid_t,
+    buf: &'a mut [u8],
+    used: usize,
+    outputs: Vec<Output>,
+}
+
+impl<'a> Marshal<'a> {
+    fn new(pid: libc::pid_t, buf: &'a mut [u8]) -> Self {
+        Self {
+            pid,
+            buf,
+            used: 0,
+            outputs: Vec::new(),
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.used
+    }
+
+    /// The host address of `offset` in the block buffer
+    fn host(&self, offset: usize) -> usize {
+        self.buf.as_ptr() as usize + offset
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<usize, i32> {
+        let offset = self.used;
+        let end = offset
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or(libc::EMSGSIZE)?;
+
+        // Keep the following data aligned for structures
+        self.used = ((end + 7) & !7).min(self.buf.len());
+        Ok(offset)
+    }
+
+    /// Copy `len` bytes at `addr` of the process into the block
+    fn input(&mut self, addr: usize, len: usize) -> Result<usize, i32> {
+        if addr == 0 {
+            return Ok(0);
+        }
+
+        let offset = self.reserve(len)?;
+        read_mem(self.pid, addr, &mut self.buf[offset..][..len])?;
+        Ok(self.host(offset))
+    }
+
+    /// Like [`Marshal::input`], but copy the data back after the syscall
+    fn inout(&mut self, addr: usize, len: usize) -> Result<usize, i32> {
+        let host = self.input(addr, len)?;
+        if addr != 0 {
+            self.outputs.push(Output {
+                offset: host - self.host(0),
+                addr,
+                len,
+                unit: None,
+            });
+        }
+        Ok(host)
+    }
+
+    /// Reserve `len` bytes for data the syscall returns in units of `unit`
+    fn output(&mut self, addr: usize, len: usize, unit: usize) -> Result<usize, i32> {
+        if addr == 0 {
+            return Ok(0);
+        }
+
+        let offset = self.reserve(len)?;
+        self.outputs.push(Output {
+            offset,
+            addr,
+            len,
+            unit: Some(unit),
+        });
+        Ok(self.host(offset))
+    }
+
+    /// Handle a socket address output with its length at `lenp`
+    fn sockaddr_out(&mut self, addr: usize, lenp: usize) -> Result<(usize, usize), i32> {
+        if lenp == 0 {
+            return Ok((0, 0));
+        }
+
+        let mut len = [0u8; 4];
+        read_mem(self.pid, lenp, &mut len)?;
+        let len = (u32::from_ne_bytes(len) as usize).min(SOCKADDR_MAX);
+
+        let lenp = self.inout(lenp, size_of::<libc::socklen_t>())?;
+        let addr = self.inout(addr, len)?;
+        Ok((addr, lenp))
+    }
+
+    /// Handle the `iovec`s of `readv` or `writev`
+    fn iovecs(&mut self, addr: usize, count: usize, write: bool) -> Result<usize, i32> {
+        if count > IOV_MAX {
+            return Err(libc::EINVAL);
+        }
+
+        let size = size_of::<libc::iovec>();
+        let mut iovs = vec![0u8; count * size];
+        read_mem(self.pid, addr, &mut iovs)?;
+
+        let array = self.reserve(iovs.len())?;
+        for (i, iov) in iovs.chunks_exact(size).enumerate() {
+            let iov = unsafe { (iov.as_ptr() as *const libc::iovec).read_unaligned() };
+            let len = iov.iov_len.min(self.remaining());
+
+            let base = if write {
+                self.input(iov.iov_base as usize, len)?
+            } else {
+                self.output(iov.iov_base as usize, len, 1)?
+            };
+
+            let host = libc::iovec {
+                iov_base: base as _,
+                iov_len: len,
+            };
+            let ptr = self.buf[array + i * size..].as_mut_ptr() as *mut libc::iovec;
+            unsafe { ptr.write_unaligned(host) };
+        }
+
+        Ok(self.host(array))
+    }
+}
+
+/// What is needed to start the process
+struct Launch {
+    /// The executable to run
+    exec: File,
+    /// The address space limit of the process
+    memory: Option<usize>,
+    /// The arguments and environment of the exec fetched from the host so far
+    args: Vec<u8>,
+    /// Whether a request for more of them is waiting for its reply
+    fetching: bool,
+}
+
+impl Launch {
+    /// Take the reply to a `SYS_ENARX_EXEC_ARGS` request
+    ///
+    /// Returns whether all arguments and the environment were fetched.
+    fn fetched(&mut self, block: &mut Block) -> Result<bool> {
+        let rep: sallyport::Result = unsafe { block.msg.rep }.into();
+        let rep = rep.map_err(Error::from_raw_os_error)?;
+        let (n, total): (usize, usize) = (rep[0].into(), rep[1].into());
+
+        if total > EXEC_ARGS_MAX || n > Block::buf_capacity() || (n == 0 && self.args.len() < total)
+        {
+            bail!("invalid reply");
+        }
+
+        self.args.extend_from_slice(&buffer(block)[..n]);
+        Ok(self.args.len() >= total)
+    }
+
+    /// Split the arguments and environment into C strings
+    fn strings(&self) -> Result<(Vec<CString>, Vec<CString>)> {
+        let argc = self
+            .args
+            .get(..8)
+            .and_then(|argc| argc.try_into().ok())
+            .map(u64::from_le_bytes)
+            .context("missing argument count")?;
+
+        let strings = self.args[8..]
+            .strip_suffix(b"\0")
+            .context("unterminated string")?;
+
+        // The strings are split at the NUL bytes, so there are none inside
+        let mut args: Vec<_> = strings
+            .split(|b| *b == 0)
+            .map(|s| CString::new(s).unwrap())
+            .collect();
+
+        if argc == 0 || argc > args.len() as u64 {
+            bail!("invalid argument count {}", argc);
+        }
+
+        let env = args.split_off(argc as usize);
+        Ok((args, env))
+    }
+}
+
+pub struct Thread {
+    /// What is needed to start the process, until it is started
+    launch: Option<Launch>,
+    pid: libc::pid_t,
+    block: Block,
+    /// Whether the process is inside a syscall, i.e. the next syscall stop is its exit
+    in_syscall: bool,
+    /// A proxied syscall waiting for its reply
+    pending: Option<Pending>,
+    /// `rax` (and `rdx`) to set when the process reaches the exit of the skipped syscall
+    result: Option<(u64, Option<u64>)>,
+    /// The signal to deliver when the process is resumed
+    signal: libc::c_int,
+    /// Whether the process is gone
+    exited: bool,
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        if self.pid > 0 && !self.exited {
+            let mut status = 0;
+            unsafe {
+                libc::kill(self.pid, libc::SIGKILL);
+                libc::waitpid(self.pid, &mut status, libc::__WALL);
+            }
+        }
+    }
+}
+
+/// Start `exec` in a traced child process, which only has the executable open
+///
+/// The process is confined by the seccomp `filter`.
+///
+/// # Safety
+///
+/// Only async-signal-safe functions may be called after `fork()`.
+unsafe fn child(
+    exec: libc::c_int,
+    max_fd: libc::c_int,
+    argv: &[*const libc::c_char],
+    envp: &[*const libc::c_char],
+    limit: Option<&libc::rlimit>,
+    filter: &libc::sock_fprog,
+) -> ! {
+    let close = |lo: libc::c_int, hi: libc::c_int| {
+        if lo <= hi && libc::syscall(libc::SYS_close_range, lo, hi, 0) != 0 {
+            for fd in lo..=hi.min(max_fd) {
+                libc::close(fd);
+            }
+        }
+    };
+
+    close(0, exec - 1);
+    close(exec + 1, libc::c_int::MAX);
+
+    let mut set = MaybeUninit::<libc::sigset_t>::zeroed();
+    libc::sigemptyset(set.as_mut_ptr());
+    libc::sigprocmask(libc::SIG_SETMASK, set.as_ptr(), null_mut());
+
+    if let Some(limit) = limit {
+        if libc::setrlimit(libc::RLIMIT_AS, limit) != 0 {
+            libc::_exit(127);
+        }
+    }
+
+    libc::ptrace(
+        libc::PTRACE_TRACEME,
+        0,
+        null_mut::<libc::c_void>(),
+        null_mut::<libc::c_void>(),
+    );
+    libc::raise(libc::SIGSTOP);
+
+    if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+        || libc::prctl(
+            libc::PR_SET_SECCOMP,
+            libc::SECCOMP_MODE_FILTER,
+            filter as *const _,
+        ) != 0
+    {
+        libc::_exit(127);
+    }
+
+    libc::syscall(
+        libc::SYS_execveat,
+        exec,
+        b"\0".as_ptr(),
+        argv.as_ptr(),
+        envp.as_ptr(),
+        libc::AT_EMPTY_PATH,
+    );
+
+    libc::_exit(127)
+}
+
+impl Thread {
+    /// Prepare to run `exec` with at most `memory` bytes of address space
+    ///
+    /// Like a shim, the thread fetches the arguments and environment of the
+    /// exec from the host first, and then starts the process.
+    pub fn new(exec: &File, memory: Option<usize>) -> Result<Self> {
+        let launch = Launch {
+            exec: exec.try_clone()?,
+            memory,
+            args: Vec::new(),
+            fetching: false,
+        };
+
+        Ok(Self {
+            launch: Some(launch),
+            pid: 0,
+            block: Block::default(),
+            in_syscall: true,
+            pending: None,
+            result: None,
+            signal: 0,
+            exited: false,
+        })
+    }
+
+    /// Start the process as a traced child process
+    fn start(&mut self, launch: Launch) -> Result<()> {
+        let (args, env) = launch
+            .strings()
+            .context("invalid arguments and environment of the exec")?;
+
+        // Everything the child needs is prepared before forking
+        let argv: Vec<_> = args
+            .iter()
+            .map(|s| s.as_ptr())
+            .chain(once(null()))
+            .collect();
+        let envp: Vec<_> = env.iter().map(|s| s.as_ptr()).chain(once(null())).collect();
+        let exec = launch.exec.as_raw_fd();
+        let max_fd = unsafe { libc::sysconf(libc::_SC_OPEN_MAX) }.clamp(1024, 65536) as _;
+        let limit = launch.memory.map(|memory| libc::rlimit {
+            rlim_cur: memory as _,
+            rlim_max: memory as _,
+        });
+        let program = seccomp::filter(NATIVE, exec);
+        let filter = libc::sock_fprog {
+            len: program.len() as _,
+            filter: program.as_ptr() as *mut _,
+        };
+
+        self.pid = match unsafe { libc::fork() } {
+            -1 => return Err(Error::last_os_error()).context("failed to fork"),
+            0 => unsafe { child(exec, max_fd, &argv, &envp, limit.as_ref(), &filter) },
+            pid => pid,
+        };
+        limits::register_child(self.pid);
+
+        let status = self.wait()?;
+        if !libc::WIFSTOPPED(status) || libc::WSTOPSIG(status) != libc::SIGSTOP {
+            bail!("failed to start the keep process");
+        }
+
+        let options =
+            libc::PTRACE_O_TRACESYSGOOD | libc::PTRACE_O_EXITKILL | libc::PTRACE_O_TRACEEXEC;
+        self.ptrace(libc::PTRACE_SETOPTIONS, options as _)?;
+        self.ptrace(libc::PTRACE_CONT, 0)?;
+
+        // The process stops again right after `execveat()` succeeded,
+        // still inside the syscall.
+        let status = self.wait()?;
+        if status >> 8 != libc::SIGTRAP | (libc::PTRACE_EVENT_EXEC << 8) {
+            bail!("failed to execute the keep binary");
+        }
+
+        Ok(())
+    }
+
+    fn ptrace(&self, request: libc::c_uint, data: usize) -> Result<()> {
+        let ret = unsafe {
+            libc::ptrace(
+                request,
+                self.pid,
+                null_mut::<libc::c_void>(),
+                data as *mut libc::c_void,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error()).context("ptrace failed");
+        }
+        Ok(())
+    }
+
+    fn wait(&mut self) -> Result<libc::c_int> {
+        let mut status = 0;
+        loop {
+            match unsafe { libc::waitpid(self.pid, &mut status, libc::__WALL) } {
+                -1 if Error::last_os_error().raw_os_error() == Some(libc::EINTR) => {
+                    self.forward_signals();
+                    continue;
+                }
+                -1 => return Err(Error::last_os_error()).context("waitpid failed"),
+                _ => break,
+            }
+        }
+
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            self.exited = true;
+        }
+
+        Ok(status)
+    }
+
+    /// Send the signals `enarx` received for the keep to the process
+    ///
+    /// The kernel delivers them natively, as the process runs on the host.
+    fn forward_signals(&self) {
+        loop {
+            match signal::take() {
+                0 => break,
+                sig => unsafe {
+                    libc::kill(self.pid, sig as _);
+                },
+            }
+        }
+    }
+
+    fn regs(&self) -> Result<libc::user_regs_struct> {
+        let mut regs = MaybeUninit::<libc::user_regs_struct>::uninit();
+        self.ptrace(libc::PTRACE_GETREGS, regs.as_mut_ptr() as usize)?;
+        Ok(unsafe { regs.assume_init() })
+    }
+
+    fn set_regs(&self, regs: &libc::user_regs_struct) -> Result<()> {
+        self.ptrace(libc::PTRACE_SETREGS, regs as *const _ as usize)
+    }
+
+    /// Skip the syscall the process stopped at and return `rax` (and `rdx`) instead
+    fn skip(&mut self, mut regs: libc::user_regs_struct, rax: u64, rdx: Option<u64>) -> Result<()> {
+        regs.orig_rax = u64::MAX;
+        self.set_regs(&regs)?;
+        self.result = Some((rax, rdx));
+        Ok(())
+    }
+
+    /// Copy the outputs of a proxied syscall back into the process
+    fn complete(&mut self, pending: Pending, rep: sallyport::Result) -> Result<usize, i32> {
+        let rep = rep?;
+        let mut left: usize = rep[0].into();
+
+        let pid = self.pid;
+        let buf = buffer(&mut self.block);
+        for out in pending.outputs {
+            let len = match out.unit {
+                None => out.len,
+                Some(unit) => {
+                    let len = out.len.min(left.saturating_mul(unit));
+                    left -= len / unit;
+                    len
+                }
+            };
+
+            write_mem(pid, out.addr, &buf[out.offset..][..len])?;
+        }
+
+        Ok(rep[pending.ret].into())
+    }
+
+    /// Turn the syscall `nr` with `args` into a request in the block
+    fn marshal(&mut self, nr: libc::c_long, a: [usize; 6]) -> Result<(Request, Pending), i32> {
+        let mut m = Marshal::new(self.pid, buffer(&mut self.block));
+        let mut ret = 0;
+
+        let req = match nr {
+            n if PROXIED_INT.contains(&n) => request!(nr => a[0], a[1], a[2], a[3]),
+
+            libc::SYS_read => {
+                let count = a[2].min(m.remaining());
+                let buf = m.output(a[1], count, 1)?;
+                request!(nr => a[0], buf, count)
+            }
+
+            libc::SYS_write => {
+                let count = a[2].min(m.remaining());
+                let buf = m.input(a[1], count)?;
+                request!(nr => a[0], buf, count)
+            }
+
+            libc::SYS_readv | libc::SYS_writev => {
+                let iov = m.iovecs(a[1], a[2], nr == libc::SYS_writev)?;
+                request!(nr => a[0], iov, a[2])
+            }
+
+            libc::SYS_fstat => {
+                let stat = m.inout(a[1], size_of::<libc::stat>())?;
+                request!(nr => a[0], stat)
+            }
+
+            libc::SYS_fcntl => match a[1] as libc::c_int {
+                libc::F_GETFD
+                | libc::F_SETFD
+                | libc::F_GETFL
+                | libc::F_SETFL
+                | libc::F_DUPFD
+                | libc::F_DUPFD_CLOEXEC => request!(nr => a[0], a[1], a[2]),
+                _ => return Err(libc::EINVAL),
+            },
+
+            libc::SYS_ioctl => {
+                let cmd = a[1] as libc::c_ulong;
+                let arg = if cmd == libc::FIONBIO as libc::c_ulong {
+                    m.input(a[2], size_of::<libc::c_int>())?
+                } else if cmd == libc::TIOCGWINSZ as libc::c_ulong {
+                    m.inout(a[2], size_of::<libc::winsize>())?
+                } else if cmd == libc::TCGETS as libc::c_ulong {
+                    m.inout(a[2], size_of::<libc::termios>())?
+                } else if cmd == libc::FIOCLEX as libc::c_ulong
+                    || cmd == libc::FIONCLEX as libc::c_ulong
+                {
+                    0
+                } else {
+                    return Err(libc::ENOTTY);
+                };
+                request!(nr => a[0], a[1], arg)
+            }
+
+            libc::SYS_poll => {
+                let len = a[1]
+                    .checked_mul(size_of::<libc::pollfd>())
+                    .ok_or(libc::EINVAL)?;
+                let fds = m.inout(a[0], len)?;
+                request!(nr => fds, a[1], a[2])
+            }
+
+            libc::SYS_epoll_ctl => {
+                let event = m.input(a[3], size_of::<libc::epoll_event>())?;
+                request!(nr => a[0], a[1], a[2], event)
+            }
+
+            libc::SYS_epoll_wait | libc::SYS_epoll_pwait => {
+                let sigmask = match nr {
+                    libc::SYS_epoll_pwait if a[5] <= size_of::<libc::sigset_t>() => {
+                        m.input(a[4], a[5])?
+                    }
+                    libc::SYS_epoll_pwait => return Err(libc::EINVAL),
+                    _ => 0,
+                };
+
+                let size = size_of::<libc::epoll_event>();
+                if a[2] as libc::c_int <= 0 {
+                    return Err(libc::EINVAL);
+                }
+                let max = (a[2] as libc::c_int as usize).min(m.remaining() / size);
+                let events = m.output(a[1], max * size, size)?;
+                request!(nr => a[0], events, max, a[3], sigmask, a[5])
+            }
+
+            libc::SYS_bind | libc::SYS_connect => {
+                let len = a[2] as libc::socklen_t as usize;
+                if len > SOCKADDR_MAX {
+                    return Err(libc::EINVAL);
+                }
+                let addr = m.input(a[1], len)?;
+                request!(nr => a[0], addr, len)
+            }
+
+            libc::SYS_accept | libc::SYS_accept4 | libc::SYS_getsockname => {
+                let (addr, lenp) = m.sockaddr_out(a[1], a[2])?;
+                request!(nr => a[0], addr, lenp, a[3])
+            }
+
+            libc::SYS_recvfrom => {
+                let (addr, lenp) = m.sockaddr_out(a[4], a[5])?;
+                let count = a[2].min(m.remaining());
+                let buf = m.output(a[1], count, 1)?;
+                request!(nr => a[0], buf, count, a[3], addr, lenp)
+            }
+
+            libc::SYS_sendto => {
+                let len = a[5] as libc::socklen_t as usize;
+                if len > SOCKADDR_MAX {
+                    return Err(libc::EINVAL);
+                }
+                let addr = m.input(a[4], len)?;
+                let count = a[2].min(m.remaining());
+                let buf = m.input(a[1], count)?;
+                request!(nr => a[0], buf, count, a[3], addr, len)
+            }
+
+            libc::SYS_setsockopt => {
+                let len = a[4] as libc::socklen_t as usize;
+                let optval = m.input(a[3], len)?;
+                request!(nr => a[0], a[1], a[2], optval, len)
+            }
+
+            libc::SYS_clock_gettime => {
+                let tp = m.inout(a[1], size_of::<libc::timespec>())?;
+                request!(nr => a[0], tp)
+            }
+
+            libc::SYS_nanosleep => {
+                let req = m.input(a[0], size_of::<libc::timespec>())?;
+                let rem = m.inout(a[1], size_of::<libc::timespec>())?;
+                request!(nr => req, rem)
+            }
+
+            // The host copies the configuration to the start of the block buffer
+            SYS_ENARX_KEEP_CONFIG if a[1] == 0 => {
+                ret = 1;
+                request!(nr => 0, 0)
+            }
+
+            SYS_ENARX_KEEP_CONFIG => {
+                let count = a[1].min(m.remaining());
+                m.output(a[0], count, 1)?;
+                request!(nr => count, a[2])
+            }
+
+            _ => return Err(libc::ENOSYS),
+        };
+
+        let outputs = m.outputs;
+        Ok((req, Pending { ret, outputs }))
+    }
+}
+
+impl super::super::Thread for Thread {
+    fn enter(&mut self) -> Result<Command<'_>> {
+        if let Some(mut launch) = self.launch.take() {
+            let done = launch.fetching
+                && launch
+                    .fetched(&mut self.block)
+                    .context("failed to fetch the arguments and environment of the exec")?;
+
+            if !done {
+                let (len, offset) = (Block::buf_capacity(), launch.args.len());
+                self.block.msg.req = request!(SYS_ENARX_EXEC_ARGS => len, offset);
+                launch.fetching = true;
+                self.launch = Some(launch);
+                return Ok(Command::SysCall(&mut self.block));
+            }
+
+            self.start(launch)?;
+        }
+
+        if let Some(pending) = self.pending.take() {
+            let rep = unsafe { self.block.msg.rep }.into();
+            self.result = Some(match self.complete(pending, rep) {
+                Ok(ret) => (ret as u64, None),
+                Err(e) => (-e as u64, None),
+            });
+        }
+
+        loop {
+            self.forward_signals();
+
+            let signal = std::mem::take(&mut self.signal);
+            self.ptrace(libc::PTRACE_SYSCALL, signal as _)?;
+
+            let status = self.wait()?;
+            if libc::WIFEXITED(status) {
+                return Ok(Command::Exit(libc::WEXITSTATUS(status)));
+            }
+            // Like a shell, report the death by a signal as exit status
+            if libc::WIFSIGNALED(status) {
+                return Ok(Command::Exit(128 + libc::WTERMSIG(status)));
+            }
+
+            if libc::WSTOPSIG(status) != libc::SIGTRAP | 0x80 {
+                // Deliver signals, but not the stops of ptrace events
+                if status >> 16 == 0 {
+                    self.signal = libc::WSTOPSIG(status);
+                }
+                continue;
+            }
+
+            self.in_syscall = !self.in_syscall;
+
+            let mut regs = self.regs()?;
+
+            if !self.in_syscall {
+                if let Some((rax, rdx)) = self.result.take() {
+                    regs.rax = rax;
+                    regs.rdx = rdx.unwrap_or(regs.rdx);
+                    self.set_regs(&regs)?;
+                }
+                continue;
+            }
+
+            let nr = regs.orig_rax as libc::c_long;
+            let args =
+                [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9].map(|r| r as usize);
+
+            match nr {
+                _ if NATIVE.contains(&nr) => (),
+
+                libc::SYS_exit | libc::SYS_exit_group => {
+                    return Ok(Command::exit(&request!(nr => args[0])).unwrap())
+                }
+
+                SYS_ENARX_GETATT => self.skip(regs, 0, Some(TEE_TECH_NONE))?,
+
+                _ => match self.marshal(nr, args) {
+                    Ok((req, pending)) => {
+                        self.skip(regs, 0, None)?;
+                        self.block.msg.req = req;
+                        self.pending = Some(pending);
+                        return Ok(Command::SysCall(&mut self.block));
+                    }
+
+                    Err(libc::ENOSYS) => {
+                        warn!("nil: unsupported syscall {}", nr);
+                        self.skip(regs, -libc::ENOSYS as u64, None)?;
+                    }
+
+                    Err(e) => self.skip(regs, -e as u64, None)?,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn launch(args: &[u8]) -> Launch {
+        Launch {
+            exec: File::open("/dev/null").unwrap(),
+            memory: None,
+            args: args.to_vec(),
+            fetching: false,
+        }
+    }
+
+    #[test]
+    fn strings() {
+        let args = ["/init".to_string(), "".into()];
+        let env = ["LANG=C".to_string()];
+        let bytes = crate::config::exec_args(&args, &env).unwrap();
+
+        let (args, env) = launch(&bytes).strings().unwrap();
+        assert_eq!(args, [CString::new("/init").unwrap(), CString::default()]);
+        assert_eq!(env, [CString::new("LANG=C").unwrap()]);
+
+        assert!(launch(b"\x01\0\0\0").strings().is_err());
+        assert!(launch(b"\0\0\0\0\0\0\0\0LANG=C\0").strings().is_err());
+        assert!(launch(b"\x02\0\0\0\0\0\0\0/init\0").strings().is_err());
+        assert!(launch(b"\x01\0\0\0\0\0\0\0/init").strings().is_err());
+    }
+}
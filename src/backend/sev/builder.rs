@@ -59,7 +59,7 @@ impl TryFrom<super::super::kvm::config::Config> for Builder {
 
 impl super::super::Mapper for Builder {
     type Config = super::super::kvm::config::Config;
-    type Output = Arc<dyn super::super::Keep>;
+    type Output = Arc<RwLock<super::Keep<SnpKeepPersonality>>>;
 
     fn map(
         &mut self,
@@ -141,7 +141,7 @@ impl super::super::Mapper for Builder {
     }
 }
 
-impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
+impl TryFrom<Builder> for Arc<RwLock<super::Keep<SnpKeepPersonality>>> {
     type Error = Error;
 
     fn try_from(mut builder: Builder) -> Result<Self> {
@@ -160,6 +160,7 @@ impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
             vm_fd,
             cpu_fds: vec![vcpu_fd],
             regions: builder.regions,
+            memory_max: None,
             sallyports: builder.sallyports,
             sallyport_start: sallyport_block_start,
             personality: SnpKeepPersonality { _sev_fd: sev_fd },
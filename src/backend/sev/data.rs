@@ -1,15 +1,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
-pub use crate::backend::kvm::data::{dev_kvm, kvm_version};
+pub use crate::backend::kvm::data::{dev_kvm, kvm_version, max_vcpus, memslots};
 
 use crate::backend::probe::x86_64::{CpuId, Vendor};
-use crate::backend::Datum;
+use crate::backend::sev::snp::firmware::TcbVersion;
+use crate::backend::sev::Firmware;
+use crate::backend::{Capability, Datum};
 
 use std::arch::x86_64::__cpuid_count;
 use std::fs::OpenOptions;
 use std::mem::{transmute, MaybeUninit};
 use std::str::from_utf8;
 
+use serde_json::{json, Value};
+
 pub fn has_reasonable_memlock_rlimit() -> Datum {
     let mut rlimits = MaybeUninit::uninit();
     let res = unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, rlimits.as_mut_ptr()) };
@@ -106,6 +110,46 @@ pub fn dev_sev_writable() -> Datum {
     }
 }
 
+fn tcb(version: &TcbVersion) -> Value {
+    json!({
+        "bootloader": version.bootloader,
+        "tee": version.tee,
+        "snp": version.snp,
+        "microcode": version.microcode,
+    })
+}
+
+/// The SNP firmware build and TCB versions
+pub fn snp_platform() -> Vec<Capability> {
+    let status = Firmware::open()
+        .ok()
+        .and_then(|mut sev| sev.platform_status().ok());
+
+    vec![
+        Capability {
+            name: "snp_firmware_build",
+            value: match &status {
+                Some(status) => json!({
+                    "major": status.build.version.major,
+                    "minor": status.build.version.minor,
+                    "build": status.build.build,
+                }),
+                None => Value::Null,
+            },
+        },
+        Capability {
+            name: "snp_tcb",
+            value: match &status {
+                Some(status) => json!({
+                    "platform": tcb(&status.tcb.platform_version),
+                    "reported": tcb(&status.tcb.reported_version),
+                }),
+                None => Value::Null,
+            },
+        },
+    ]
+}
+
 pub const CPUIDS: &[CpuId] = &[
     CpuId {
         name: "CPU Manufacturer",
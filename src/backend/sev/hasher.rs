@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Calculate the SEV-SNP launch digest without SNP hardware.
+//!
+//! This replays the `SNP_LAUNCH_UPDATE` calls of `super::builder::Builder`
+//! page by page, as the firmware does (SNP firmware ABI specification,
+//! chapter 8.17.2, `PAGE_INFO`).
+//!
+//! On `SNP_LAUNCH_FINISH`, KVM adds the VMSA page of the single vCPU, which
+//! holds the vCPU state after reset, see `vmsa()`.
+
+use super::snp::launch::{PageType, VmplPerms};
+
+use std::convert::TryFrom;
+
+use anyhow::{Error, Result};
+use mmarinus::{perms, Map};
+use openssl::sha::{sha384, Sha384};
+use primordial::Page;
+use sallyport::elf::pf::snp::{CPUID, SECRETS};
+
+/// The size of a SHA-384 digest
+const DIGEST_SIZE: usize = 48;
+
+/// The size of the `PAGE_INFO` structure
+const PAGE_INFO_SIZE: u16 = 0x70;
+
+/// The guest physical address the firmware measures VMSA pages at
+const VMSA_GPA: u64 = 0xFFFF_FFFF_F000;
+
+/// The processor signature in RDX after reset
+///
+/// KVM resets the vCPU before the CPUID is set, so it falls back to its
+/// default family, model and stepping.
+const VCPU_SIGNATURE: u64 = 0x600;
+
+/// The `SNPActive` bit of the `SEV_FEATURES` KVM enables for SNP guests
+const SEV_FEATURES: u64 = 1;
+
+/// The VMSA of a vCPU after reset, starting at the shim's reset vector
+///
+/// The layout is the `VMSA` of the AMD64 Architecture Programmer's Manual,
+/// volume 2, table B-4. The values are the ones of KVM's `init_vmcb()`.
+fn vmsa() -> Vec<u8> {
+    fn put(vmsa: &mut [u8], offset: usize, bytes: &[u8]) {
+        vmsa[offset..][..bytes.len()].copy_from_slice(bytes);
+    }
+
+    // selector, attributes, limit and base of a segment register
+    fn seg(vmsa: &mut [u8], offset: usize, selector: u16, attrib: u16, base: u64) {
+        put(vmsa, offset, &selector.to_le_bytes());
+        put(vmsa, offset + 2, &attrib.to_le_bytes());
+        put(vmsa, offset + 4, &0xffffu32.to_le_bytes());
+        put(vmsa, offset + 8, &base.to_le_bytes());
+    }
+
+    let mut vmsa = vec![0u8; Page::SIZE];
+
+    for es_ss_ds_fs_gs in [0x00, 0x20, 0x30, 0x40, 0x50] {
+        seg(&mut vmsa, es_ss_ds_fs_gs, 0, 0x93, 0);
+    }
+    seg(&mut vmsa, 0x10, 0xf000, 0x9b, 0xffff_0000); // cs
+    seg(&mut vmsa, 0x60, 0, 0, 0); // gdtr
+    seg(&mut vmsa, 0x70, 0, 0x82, 0); // ldtr
+    seg(&mut vmsa, 0x80, 0, 0, 0); // idtr
+    seg(&mut vmsa, 0x90, 0, 0x83, 0); // tr
+
+    put(&mut vmsa, 0xd0, &0x1000u64.to_le_bytes()); // efer: SVME
+    put(&mut vmsa, 0x148, &0x40u64.to_le_bytes()); // cr4: MCE
+    put(&mut vmsa, 0x158, &0x10u64.to_le_bytes()); // cr0: ET
+    put(&mut vmsa, 0x160, &0x400u64.to_le_bytes()); // dr7
+    put(&mut vmsa, 0x168, &0xffff_0ff0u64.to_le_bytes()); // dr6
+    put(&mut vmsa, 0x170, &0x2u64.to_le_bytes()); // rflags
+    put(&mut vmsa, 0x178, &0xfff0u64.to_le_bytes()); // rip
+    put(&mut vmsa, 0x268, &0x0007_0406_0007_0406u64.to_le_bytes()); // g_pat
+    put(&mut vmsa, 0x310, &VCPU_SIGNATURE.to_le_bytes()); // rdx
+    put(&mut vmsa, 0x3e8, &SEV_FEATURES.to_le_bytes());
+    put(&mut vmsa, 0x420, &0x1u64.to_le_bytes()); // xcr0: x87
+    put(&mut vmsa, 0x440, &0x1f80u32.to_le_bytes()); // mxcsr
+    put(&mut vmsa, 0x448, &0x37fu16.to_le_bytes()); // x87 fcw
+
+    vmsa
+}
+
+pub struct Hasher([u8; DIGEST_SIZE]);
+
+impl Hasher {
+    /// Extend the digest by a single page, like `SNP_LAUNCH_UPDATE` does
+    fn update(
+        &mut self,
+        gpa: u64,
+        page: &[u8],
+        page_type: PageType,
+        perms: (VmplPerms, VmplPerms, VmplPerms),
+    ) {
+        // Only the contents of normal and VMSA pages are measured.
+        let contents = match page_type {
+            PageType::Normal | PageType::Vmsa => sha384(page),
+            _ => [0u8; DIGEST_SIZE],
+        };
+
+        let mut page_info = Sha384::new();
+        page_info.update(&self.0);
+        page_info.update(&contents);
+        page_info.update(&PAGE_INFO_SIZE.to_le_bytes());
+        page_info.update(&[
+            page_type as u8,
+            0, // IMI_PAGE
+            perms.2.bits(),
+            perms.1.bits(),
+            perms.0.bits(),
+            0, // reserved
+        ]);
+        page_info.update(&gpa.to_le_bytes());
+
+        self.0 = page_info.finish();
+    }
+}
+
+impl TryFrom<super::super::kvm::config::Config> for Hasher {
+    type Error = Error;
+
+    #[inline]
+    fn try_from(_config: super::super::kvm::config::Config) -> Result<Self> {
+        Ok(Self([0u8; DIGEST_SIZE]))
+    }
+}
+
+impl super::super::Mapper for Hasher {
+    type Config = super::super::kvm::config::Config;
+    type Output = Vec<u8>;
+
+    fn map(&mut self, pages: Map<perms::ReadWrite>, to: usize, with: u32) -> Result<()> {
+        let dp = VmplPerms::empty();
+
+        let page_type = if with & CPUID != 0 {
+            assert_eq!(pages.len(), Page::SIZE);
+            PageType::Cpuid
+        } else if with & SECRETS != 0 {
+            assert_eq!(pages.len(), Page::SIZE);
+            PageType::Secrets
+        } else {
+            PageType::Normal
+        };
+
+        for (i, page) in pages.chunks(Page::SIZE).enumerate() {
+            let gpa = (to + i * Page::SIZE) as u64;
+            self.update(gpa, page, page_type, (dp, dp, dp));
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<Hasher> for Vec<u8> {
+    type Error = Error;
+
+    #[inline]
+    fn try_from(mut hasher: Hasher) -> Result<Self> {
+        let dp = VmplPerms::empty();
+        hasher.update(VMSA_GPA, &vmsa(), PageType::Vmsa, (dp, dp, dp));
+        Ok(hasher.0.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::Mapper;
+
+    use mmarinus::Kind;
+
+    fn pages(fill: u8) -> Map<perms::ReadWrite> {
+        let mut pages = Map::map(Page::SIZE)
+            .anywhere()
+            .anonymously()
+            .known::<perms::ReadWrite>(Kind::Private)
+            .unwrap();
+        pages.fill(fill);
+        pages
+    }
+
+    #[test]
+    fn digest() {
+        let mut hasher = Hasher([0u8; DIGEST_SIZE]);
+        hasher.map(pages(0x00), 0x1000, 0).unwrap();
+
+        // The contents of the CPUID page are not measured.
+        hasher.map(pages(0xff), 0x2000, CPUID).unwrap();
+
+        // The VMSA page is measured last.
+        let digest: Vec<u8> = Vec::try_from(hasher).unwrap();
+
+        // Computed with the `Gctx` update of sev-snp-measure, independently
+        // of this code, for the same pages and the KVM VMSA at 0xFFFF_FFF0.
+        assert_eq!(
+            digest,
+            [
+                0xe5, 0xc5, 0xa5, 0x5c, 0x99, 0xca, 0xf7, 0x20, 0x2b, 0x38, 0x1e, 0xd2, 0x82, 0x48,
+                0x60, 0xd1, 0x32, 0xd9, 0x62, 0xca, 0x46, 0x5b, 0x87, 0x1e, 0xb0, 0xf1, 0x41, 0xa1,
+                0xed, 0xfa, 0x7f, 0x02, 0xdf, 0xd5, 0x29, 0xad, 0x78, 0x37, 0xb6, 0xdb, 0x2c, 0xde,
+                0x9d, 0x13, 0xba, 0x33, 0xaf, 0xdb,
+            ]
+        );
+    }
+}
@@ -12,7 +12,7 @@ use std::sync::Arc;
 use anyhow::Result;
 use data::{
     dev_kvm, dev_sev, dev_sev_readable, dev_sev_writable, has_reasonable_memlock_rlimit,
-    kvm_version, sev_enabled_in_kernel, CPUIDS,
+    kvm_version, max_vcpus, memslots, sev_enabled_in_kernel, snp_platform, CPUIDS,
 };
 use kvm_ioctls::VmFd;
 use snp::launch::linux::KvmEncRegion;
@@ -20,6 +20,7 @@ use snp::launch::linux::KvmEncRegion;
 mod builder;
 mod cpuid_page;
 mod data;
+mod hasher;
 mod snp;
 
 struct SnpKeepPersonality {
@@ -32,6 +33,11 @@ impl KeepPersonality for SnpKeepPersonality {
         KvmEncRegion::new(region.backing()).register(vm_fd)?;
         Ok(())
     }
+
+    fn unmap(vm_fd: &mut VmFd, region: &Region) -> std::io::Result<()> {
+        KvmEncRegion::new(region.backing()).unregister(vm_fd)?;
+        Ok(())
+    }
 }
 
 pub struct Backend;
@@ -47,6 +53,11 @@ impl super::Backend for Backend {
         include_bytes!(concat!(env!("OUT_DIR"), "/bin/shim-sev"))
     }
 
+    #[inline]
+    fn shim_version(&self) -> &'static str {
+        env!("ENARX_SHIM_SEV_VERSION")
+    }
+
     #[inline]
     fn have(&self) -> bool {
         data::dev_sev_writable().pass
@@ -66,13 +77,26 @@ impl super::Backend for Backend {
         data
     }
 
-    #[inline]
-    fn keep(&self, shim: &[u8], exec: &[u8]) -> Result<Arc<dyn super::Keep>> {
-        builder::Builder::load(shim, exec)
+    fn capabilities(&self) -> Vec<super::Capability> {
+        let mut caps = snp_platform();
+        caps.push(memslots());
+        caps.push(max_vcpus());
+        caps
+    }
+
+    fn keep(
+        &self,
+        shim: &[u8],
+        exec: &[u8],
+        memory: Option<usize>,
+    ) -> Result<Arc<dyn super::Keep>> {
+        let keep = builder::Builder::load(shim, exec)?;
+        keep.write().unwrap().set_memory_max(memory)?;
+        Ok(keep)
     }
 
     #[inline]
-    fn hash(&self, _shim: &[u8], _exec: &[u8]) -> Result<Vec<u8>> {
-        Ok(Vec::new())
+    fn hash(&self, shim: &[u8], exec: &[u8]) -> Result<Vec<u8>> {
+        hasher::Hasher::load(shim, exec)
     }
 }
@@ -43,6 +43,10 @@ const ENC_OP: Ioctl<WriteRead, &c_ulong> = unsafe { KVM.write_read(0xBA) };
 pub const ENC_REG_REGION: Ioctl<Write, &KvmEncRegion<'_>> =
     unsafe { KVM.read::<KvmEncRegion<'_>>(0xBB).lie() };
 
+/// Corresponds to the `KVM_MEMORY_ENCRYPT_UNREG_REGION` ioctl
+pub const ENC_UNREG_REGION: Ioctl<Write, &KvmEncRegion<'_>> =
+    unsafe { KVM.read::<KvmEncRegion<'_>>(0xBC).lie() };
+
 /// Initialize the SEV-SNP platform in KVM.
 pub const SNP_INIT: Ioctl<WriteRead, &Command<'_, Init>> = unsafe { ENC_OP.lie() };
 
@@ -83,6 +87,14 @@ impl<'a> KvmEncRegion<'a> {
     pub fn register(&mut self, vm_fd: &mut impl AsRawFd) -> std::io::Result<std::os::raw::c_uint> {
         ENC_REG_REGION.ioctl(vm_fd, self)
     }
+
+    /// Unregister the encrypted memory region from a virtual machine
+    pub fn unregister(
+        &mut self,
+        vm_fd: &mut impl AsRawFd,
+    ) -> std::io::Result<std::os::raw::c_uint> {
+        ENC_UNREG_REGION.ioctl(vm_fd, self)
+    }
 }
 
 /// A generic SEV command
@@ -0,0 +1,299 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A client for the Intel Architectural Enclave Service Manager (`aesmd`)
+//!
+//! The quoting enclave is reached through `aesmd` over a Unix socket. Every
+//! message is a protobuf `Request` or `Response` (see
+//! `src/protobuf/aesm-proto.proto`), preceded by its length as a
+//! little-endian `u32`. `aesmd` handles a single request per connection.
+
+use crate::protobuf::aesm_proto::{
+    Request, Request_GetQuoteExRequest, Request_GetQuoteSizeExRequest, Request_InitQuoteExRequest,
+    Request_SelectAttKeyIDRequest, Response,
+};
+
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use protobuf::Message;
+
+/// The default path of the `aesmd` socket
+pub const AESM_SOCKET: &str = "/var/run/aesmd/aesm.socket";
+
+/// The environment variable to override the path of the `aesmd` socket
+pub const AESM_SOCKET_ENV: &str = "ENARX_AESM_SOCKET";
+
+/// The timeout passed to `aesmd` for each request (in milliseconds)
+const AESM_TIMEOUT: u32 = 1_000_000;
+
+/// The size of `sgx_target_info_t`
+pub const TARGET_INFO_SIZE: usize = 512;
+
+/// The size of `sgx_report_t`
+pub const REPORT_SIZE: usize = 432;
+
+fn aesm_error(request: &str, code: u32) -> Error {
+    Error::new(
+        ErrorKind::Other,
+        format!("aesmd: {} failed with error code {:#x}", request, code),
+    )
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("aesmd: {}", msg))
+}
+
+/// A connection to `aesmd`
+#[derive(Debug, Clone)]
+pub struct AesmClient {
+    path: PathBuf,
+}
+
+impl Default for AesmClient {
+    fn default() -> Self {
+        match std::env::var_os(AESM_SOCKET_ENV) {
+            Some(path) => Self::new(path),
+            None => Self::new(AESM_SOCKET),
+        }
+    }
+}
+
+impl AesmClient {
+    /// Use the `aesmd` socket at `path`
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn transact(&self, request: Request) -> Result<Response> {
+        let bytes = request.write_to_bytes().map_err(Error::from)?;
+        let len = u32::try_from(bytes.len()).map_err(|_| invalid_data("request too large"))?;
+
+        let mut stream = UnixStream::connect(&self.path)?;
+        stream.write_all(&len.to_le_bytes())?;
+        stream.write_all(&bytes)?;
+
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len)?;
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        stream.read_exact(&mut bytes)?;
+
+        Response::parse_from_bytes(&bytes).map_err(Error::from)
+    }
+
+    /// Get the ID of the attestation key `aesmd` uses by default
+    fn att_key_id(&self) -> Result<Vec<u8>> {
+        let mut req = Request_SelectAttKeyIDRequest::new();
+        req.set_timeout(AESM_TIMEOUT);
+
+        let mut request = Request::new();
+        request.set_selectAttKeyIDReq(req);
+
+        let response = self.transact(request)?;
+        let rep = response.get_selectAttKeyIDRes();
+        match rep.get_errorCode() {
+            0 => Ok(rep.get_selected_att_key_id().to_vec()),
+            e => Err(aesm_error("SelectAttKeyID", e)),
+        }
+    }
+
+    /// Get the `TargetInfo` of the quoting enclave
+    ///
+    /// The enclave uses it to create a report the quoting enclave can verify.
+    /// Returns `Ok(None)`, if `aesmd` is not running.
+    pub fn target_info(&self) -> Result<Option<Vec<u8>>> {
+        let akid = match self.att_key_id() {
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+                return Ok(None)
+            }
+            r => r?,
+        };
+
+        let mut req = Request_InitQuoteExRequest::new();
+        req.set_att_key_id(akid);
+        req.set_b_pub_key_id(false);
+        req.set_timeout(AESM_TIMEOUT);
+
+        let mut request = Request::new();
+        request.set_initQuoteExReq(req);
+
+        let response = self.transact(request)?;
+        let rep = response.get_initQuoteExRes();
+        match rep.get_errorCode() {
+            0 if rep.get_target_info().len() == TARGET_INFO_SIZE => {
+                Ok(Some(rep.get_target_info().to_vec()))
+            }
+            0 => Err(invalid_data("invalid TargetInfo size")),
+            e => Err(aesm_error("InitQuoteEx", e)),
+        }
+    }
+
+    /// Get a quote for the enclave `report`
+    pub fn quote(&self, report: &[u8]) -> Result<Vec<u8>> {
+        if report.len() != REPORT_SIZE {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid report size"));
+        }
+
+        let akid = self.att_key_id()?;
+
+        let mut req = Request_GetQuoteSizeExRequest::new();
+        req.set_att_key_id(akid.clone());
+        req.set_timeout(AESM_TIMEOUT);
+
+        let mut request = Request::new();
+        request.set_getQuoteSizeExReq(req);
+
+        let response = self.transact(request)?;
+        let rep = response.get_getQuoteSizeExRes();
+        let size = match rep.get_errorCode() {
+            0 => rep.get_quote_size(),
+            e => return Err(aesm_error("GetQuoteSizeEx", e)),
+        };
+
+        let mut req = Request_GetQuoteExRequest::new();
+        req.set_report(report.to_vec());
+        req.set_att_key_id(akid);
+        req.set_buf_size(size);
+        req.set_timeout(AESM_TIMEOUT);
+
+        let mut request = Request::new();
+        request.set_getQuoteExReq(req);
+
+        let response = self.transact(request)?;
+        let rep = response.get_getQuoteExRes();
+        match rep.get_errorCode() {
+            0 if rep.get_quote().len() <= size as usize => Ok(rep.get_quote().to_vec()),
+            0 => Err(invalid_data("quote exceeds the announced size")),
+            e => Err(aesm_error("GetQuoteEx", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protobuf::aesm_proto::{
+        Response_GetQuoteExResponse, Response_GetQuoteSizeExResponse, Response_InitQuoteExResponse,
+        Response_SelectAttKeyIDResponse,
+    };
+
+    use std::os::unix::net::UnixListener;
+    use std::thread::JoinHandle;
+
+    /// Replay `responses` to the next connections, one per connection,
+    /// and return the requests received
+    fn fake_aesmd(path: &Path, responses: Vec<Response>) -> JoinHandle<Vec<Request>> {
+        let listener = UnixListener::bind(path).unwrap();
+
+        std::thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+
+                let mut len = [0u8; 4];
+                stream.read_exact(&mut len).unwrap();
+                let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+                stream.read_exact(&mut bytes).unwrap();
+                requests.push(Request::parse_from_bytes(&bytes).unwrap());
+
+                let bytes = response.write_to_bytes().unwrap();
+                stream
+                    .write_all(&(bytes.len() as u32).to_le_bytes())
+                    .unwrap();
+                stream.write_all(&bytes).unwrap();
+            }
+
+            requests
+        })
+    }
+
+    fn select_att_key_id() -> Response {
+        let mut rep = Response_SelectAttKeyIDResponse::new();
+        rep.set_errorCode(0);
+        rep.set_selected_att_key_id(vec![7u8; 256]);
+
+        let mut response = Response::new();
+        response.set_selectAttKeyIDRes(rep);
+        response
+    }
+
+    #[test]
+    fn target_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+
+        let mut rep = Response_InitQuoteExResponse::new();
+        rep.set_errorCode(0);
+        rep.set_target_info(vec![1u8; TARGET_INFO_SIZE]);
+        let mut response = Response::new();
+        response.set_initQuoteExRes(rep);
+
+        let aesmd = fake_aesmd(&path, vec![select_att_key_id(), response]);
+
+        let ti = AesmClient::new(&path).target_info().unwrap();
+        assert_eq!(ti, Some(vec![1u8; TARGET_INFO_SIZE]));
+
+        let requests = aesmd.join().unwrap();
+        assert!(requests[0].has_selectAttKeyIDReq());
+        assert_eq!(
+            requests[1].get_initQuoteExReq().get_att_key_id(),
+            &[7u8; 256][..]
+        );
+    }
+
+    #[test]
+    fn quote() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+
+        let mut rep = Response_GetQuoteSizeExResponse::new();
+        rep.set_errorCode(0);
+        rep.set_quote_size(4598);
+        let mut size = Response::new();
+        size.set_getQuoteSizeExRes(rep);
+
+        let mut rep = Response_GetQuoteExResponse::new();
+        rep.set_errorCode(0);
+        rep.set_quote(vec![3u8; 4598]);
+        let mut quote = Response::new();
+        quote.set_getQuoteExRes(rep);
+
+        let aesmd = fake_aesmd(&path, vec![select_att_key_id(), size, quote]);
+
+        let report = [2u8; REPORT_SIZE];
+        let q = AesmClient::new(&path).quote(&report).unwrap();
+        assert_eq!(q, vec![3u8; 4598]);
+
+        let requests = aesmd.join().unwrap();
+        let req = requests[2].get_getQuoteExReq();
+        assert_eq!(req.get_report(), &report[..]);
+        assert_eq!(req.get_buf_size(), 4598);
+    }
+
+    #[test]
+    fn error_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aesm.socket");
+
+        let mut rep = Response_InitQuoteExResponse::new();
+        rep.set_errorCode(42);
+        let mut response = Response::new();
+        response.set_initQuoteExRes(rep);
+
+        let aesmd = fake_aesmd(&path, vec![select_att_key_id(), response]);
+
+        assert!(AesmClient::new(&path).target_info().is_err());
+        aesmd.join().unwrap();
+    }
+
+    #[test]
+    fn unavailable() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = AesmClient::new(dir.path().join("aesm.socket"));
+        assert_eq!(client.target_info().unwrap(), None);
+    }
+}
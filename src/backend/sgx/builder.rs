@@ -62,6 +62,7 @@ impl TryFrom<super::config::Config> for Builder {
         trace!("creating enclave: {:?}", secs);
         let create = Create::new(&secs);
         ENCLAVE_CREATE.ioctl(&mut file, &create)?;
+        crate::metrics::memory(map.size());
 
         Ok(Builder {
             hash: Hasher::new(config.size, config.ssap),
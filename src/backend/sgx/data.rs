@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::backend::probe::x86_64::{CpuId, Vendor};
-use crate::backend::Datum;
+use crate::backend::{Capability, Datum};
 
 use sgx::parameters::{Features, MiscSelect, Xfrm};
 
@@ -128,24 +128,33 @@ pub const CPUIDS: &[CpuId] = &[
     },
 ];
 
-pub fn epc_size(max: u32) -> Datum {
-    let mut pass = false;
-    let mut info = None;
-
-    if max >= 0x00000012 {
-        let mut size = 0;
+/// The total size of all EPC sections in bytes
+fn epc_bytes(max: u32) -> Option<u64> {
+    if max < 0x00000012 {
+        return None;
+    }
 
-        for i in 2.. {
-            let result = unsafe { __cpuid_count(0x00000012, i) };
-            if result.eax & 0xf != 1 {
-                break;
-            }
+    let mut size = 0;
 
-            let low = result.ecx as u64 & 0xfffff000;
-            let high = result.edx as u64 & 0x000fffff;
-            size += high << 12 | low;
+    for i in 2.. {
+        let result = unsafe { __cpuid_count(0x00000012, i) };
+        if result.eax & 0xf != 1 {
+            break;
         }
 
+        let low = result.ecx as u64 & 0xfffff000;
+        let high = result.edx as u64 & 0x000fffff;
+        size += high << 12 | low;
+    }
+
+    Some(size)
+}
+
+pub fn epc_size(max: u32) -> Datum {
+    let mut pass = false;
+    let mut info = None;
+
+    if let Some(size) = epc_bytes(max) {
         let (n, s) = humanize(size as f64);
         info = Some(format!("{:.0} {}", n, s));
         pass = true;
@@ -159,6 +168,13 @@ pub fn epc_size(max: u32) -> Datum {
     }
 }
 
+pub fn epc_capability(max: u32) -> Capability {
+    Capability {
+        name: "epc_size",
+        value: epc_bytes(max).into(),
+    }
+}
+
 pub fn dev_sgx_enclave() -> Datum {
     let mut pass = false;
 
@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
+mod attestation;
 mod builder;
 mod config;
 mod data;
@@ -9,7 +10,7 @@ mod thread;
 
 use super::Loader;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use mmarinus::{perms, Map};
 
 use std::arch::x86_64::__cpuid_count;
@@ -35,6 +36,11 @@ impl crate::backend::Backend for Backend {
         include_bytes!(concat!(env!("OUT_DIR"), "/bin/shim-sgx"))
     }
 
+    #[inline]
+    fn shim_version(&self) -> &'static str {
+        env!("ENARX_SHIM_SGX_VERSION")
+    }
+
     #[inline]
     fn have(&self) -> bool {
         data::dev_sgx_enclave().pass
@@ -51,8 +57,30 @@ impl crate::backend::Backend for Backend {
         data
     }
 
-    #[inline]
-    fn keep(&self, shim: &[u8], exec: &[u8]) -> Result<Arc<dyn super::Keep>> {
+    fn capabilities(&self) -> Vec<super::Capability> {
+        let max = unsafe { __cpuid_count(0x00000000, 0x00000000) }.eax;
+        vec![data::epc_capability(max)]
+    }
+
+    fn keep(
+        &self,
+        shim: &[u8],
+        exec: &[u8],
+        memory: Option<usize>,
+    ) -> Result<Arc<dyn super::Keep>> {
+        // An enclave cannot grow, so all of its memory is committed upfront
+        if let Some(max) = memory {
+            let size = super::memory_size(shim, exec)?;
+            if size > max {
+                bail!(
+                    "The sgx keep needs {} bytes of memory, more than the limit of {} bytes; \
+                     use a shim built with a smaller ENARX_SGX_HEAP_PAGES",
+                    size,
+                    max
+                );
+            }
+        }
+
         builder::Builder::load(shim, exec)
     }
 
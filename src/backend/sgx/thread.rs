@@ -1,15 +1,22 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::Command;
+use super::attestation::{AesmClient, REPORT_SIZE};
+use crate::metrics;
+use crate::replay::{self, Kind};
 
 use std::arch::asm;
 use std::mem::MaybeUninit;
 #[cfg(feature = "gdb")]
 use std::net::TcpStream;
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::Result;
-use sallyport::{syscall::SYS_ENARX_CPUID, Block};
+use log::warn;
+use primordial::Register;
+use sallyport::syscall::{SYS_ENARX_CPUID, SYS_ENARX_GETATT};
+use sallyport::Block;
 use sgx::enclu::{EENTER, EEXIT, ERESUME};
 use sgx::ssa::Vector;
 use vdso::Symbol;
@@ -19,6 +26,7 @@ pub struct Thread {
     vdso: &'static Symbol,
     tcs: *const super::Tcs,
     block: Block,
+    quote: Vec<u8>,
     cssa: usize,
     how: usize,
     #[cfg(feature = "gdb")]
@@ -48,6 +56,7 @@ impl super::super::Keep for super::Keep {
             vdso,
             tcs,
             block: Block::default(),
+            quote: Vec::new(),
             cssa: usize::default(),
             how: EENTER,
             #[cfg(feature = "gdb")]
@@ -56,6 +65,78 @@ impl super::super::Keep for super::Keep {
     }
 }
 
+/// Handle a `SYS_ENARX_GETATT` request from the shim
+///
+/// Arguments: the size of the enclave report at the start of the block
+/// buffer, the number of bytes the shim can receive and an offset.
+///
+/// Without a report and at offset zero, the `TargetInfo` of the quoting
+/// enclave is written to the start of the block buffer. An empty
+/// `TargetInfo` tells the shim that `aesmd` is not available.
+///
+/// With a report, the quote for it is stored in `quote`. Its bytes from the
+/// offset on are then returned like for `SYS_ENARX_KEEP_CONFIG`: the reply
+/// is the number of bytes copied to the block buffer and the size of the
+/// quote.
+fn attest(block: &mut Block, quote: &mut Vec<u8>) -> Result<[Register<usize>; 2], i32> {
+    let req = unsafe { block.msg.req };
+    let report_len: usize = req.arg[0].into();
+    let buf_len: usize = req.arg[1].into();
+    let offset: usize = req.arg[2].into();
+
+    let aesm = AesmClient::default();
+
+    match (report_len, offset) {
+        (0, 0) => {
+            let ti = match aesm.target_info() {
+                Ok(Some(ti)) => ti,
+                Ok(None) => return Ok([0.into(), 0.into()]),
+                Err(e) => return Err(attestation_failed(e)),
+            };
+
+            if ti.len() > buf_len {
+                return Err(libc::EMSGSIZE);
+            }
+
+            block
+                .cursor()
+                .copy_from_slice(&ti)
+                .map_err(|_| libc::EMSGSIZE)?;
+
+            return Ok([ti.len().into(), 0.into()]);
+        }
+
+        (REPORT_SIZE, 0) => {
+            let mut report = [0u8; REPORT_SIZE];
+            unsafe {
+                block
+                    .cursor()
+                    .copy_into_slice(REPORT_SIZE, &mut report[..])
+                    .map_err(|_| libc::EMSGSIZE)?;
+            }
+            *quote = aesm.quote(&report).map_err(attestation_failed)?;
+        }
+
+        (0, _) => (),
+        _ => return Err(libc::EINVAL),
+    }
+
+    let rest = quote.get(offset..).ok_or(libc::EINVAL)?;
+    let n = rest.len().min(buf_len).min(Block::buf_capacity());
+
+    block
+        .cursor()
+        .copy_from_slice(&rest[..n])
+        .map_err(|_| libc::EMSGSIZE)?;
+
+    Ok([n.into(), quote.len().into()])
+}
+
+fn attestation_failed(e: std::io::Error) -> i32 {
+    warn!("SGX attestation failed: {}", e);
+    e.raw_os_error().unwrap_or(libc::EIO)
+}
+
 impl super::super::Thread for Thread {
     fn enter(&mut self) -> Result<Command<'_>> {
         let mut run: Run = unsafe { MaybeUninit::zeroed().assume_init() };
@@ -128,9 +209,20 @@ impl super::super::Thread for Thread {
         // remove this logic.
         if self.cssa > 0 {
             if let (EENTER, ERESUME) = (how, self.how) {
-                match unsafe { self.block.msg.req }.num.into() {
+                let req = unsafe { self.block.msg.req };
+                match req.num.into() {
                     SYS_ENARX_CPUID => return Ok(Command::CpuId(&mut self.block)),
 
+                    // The quote is replayed, so a replay needs no `aesmd`
+                    SYS_ENARX_GETATT => {
+                        let start = Instant::now();
+                        replay::hostcall(Kind::Proxied, &mut self.block, |block| {
+                            block.msg.rep = attest(block, &mut self.quote).into()
+                        })?;
+                        metrics::hostcall(SYS_ENARX_GETATT, start.elapsed());
+                        return Ok(Command::Continue);
+                    }
+
                     #[cfg(feature = "gdb")]
                     sallyport::syscall::SYS_ENARX_GDB_START
                     | sallyport::syscall::SYS_ENARX_GDB_PEEK
@@ -139,7 +231,10 @@ impl super::super::Thread for Thread {
                         return Ok(Command::Gdb(&mut self.block, &mut self.gdb_fd))
                     }
 
-                    _ => return Ok(Command::SysCall(&mut self.block)),
+                    _ => {
+                        let syscall = Command::SysCall(&mut self.block);
+                        return Ok(Command::exit(&req).unwrap_or(syscall));
+                    }
                 }
             }
         }
@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::StructOpt;
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+/// The custom section holding the resources
+///
+/// This and the format of the section must match `internal/wasmldr/src/bundle.rs`,
+/// which the `bundle` test in `tests/wasmldr_tests.rs` checks.
+const RESOURCES_SECTION: &str = ".enarx.resources";
+
+/// The magic and format version at the start of the section
+const MAGIC: &[u8; 8] = b"ENARXRES";
+const VERSION: u32 = 1;
+
+const KIND_DIR: u8 = 0;
+const KIND_FILE: u8 = 1;
+
+/// Pack a directory into the resources of a WebAssembly module.
+///
+/// The workload finds the files in the read-only directory `/resources`.
+/// As they are part of the module, they are covered by its digest. Resources
+/// bundled before are replaced.
+#[derive(StructOpt, Debug)]
+pub struct Options {
+    /// The directory to pack
+    #[structopt(value_name = "DIR", parse(from_os_str))]
+    pub dir: PathBuf,
+
+    /// Path of the WebAssembly module
+    #[structopt(value_name = "MODULE", parse(from_os_str))]
+    pub module: PathBuf,
+
+    /// Write the bundled module to OUTPUT instead of replacing MODULE
+    #[structopt(short, long, value_name = "OUTPUT", parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn run(self) -> Result<()> {
+        let module =
+            fs::read(&self.module).with_context(|| format!("failed to read {:?}", self.module))?;
+
+        let mut resources = MAGIC.to_vec();
+        resources.extend_from_slice(&VERSION.to_le_bytes());
+        pack(&self.dir, "", &mut Vec::new(), &mut resources)?;
+
+        let bundled = bundle(&module, &resources)
+            .with_context(|| format!("failed to bundle {:?}", self.module))?;
+
+        let output = self.output.as_ref().unwrap_or(&self.module);
+        fs::write(output, bundled).with_context(|| format!("failed to write {:?}", output))
+    }
+}
+
+/// Append an entry for `path` to `out`, see `internal/wasmldr/src/bundle.rs`
+fn entry(out: &mut Vec<u8>, kind: u8, path: &str, data: Option<&[u8]>) -> Result<()> {
+    let len = u32::try_from(path.len()).context("path too long")?;
+
+    out.push(kind);
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(path.as_bytes());
+    if let Some(data) = data {
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        out.extend_from_slice(data);
+    }
+
+    Ok(())
+}
+
+/// Append the entries of the directory `dir`, found at `prefix` in the resources, to `out`
+///
+/// `parents` are the device and inode numbers of the directories `dir` is in,
+/// so a symlink to one of them is not followed forever.
+fn pack(dir: &Path, prefix: &str, parents: &mut Vec<(u64, u64)>, out: &mut Vec<u8>) -> Result<()> {
+    let meta = fs::metadata(dir).with_context(|| format!("failed to stat {:?}", dir))?;
+    let id = (meta.dev(), meta.ino());
+    if parents.contains(&id) {
+        bail!("{:?} is a symlink to a directory it is in", dir);
+    }
+
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory {:?}", dir))?
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("failed to read directory {:?}", dir))?;
+
+    // The same directory always gives the same module
+    entries.sort_by_key(|e| e.file_name());
+
+    for e in entries {
+        let name = e.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => bail!("{:?} is not valid UTF-8", e.path()),
+        };
+        let path = format!("{}{}", prefix, name);
+
+        // Symlinks are followed, so their targets end up in the bundle
+        let meta =
+            fs::metadata(e.path()).with_context(|| format!("failed to stat {:?}", e.path()))?;
+        if meta.is_dir() {
+            entry(out, KIND_DIR, &path, None)?;
+            parents.push(id);
+            pack(&e.path(), &format!("{}/", path), parents, out)?;
+            parents.pop();
+        } else if meta.is_file() {
+            let data =
+                fs::read(e.path()).with_context(|| format!("failed to read {:?}", e.path()))?;
+            entry(out, KIND_FILE, &path, Some(&data))?;
+        } else {
+            bail!("{:?} is neither a file nor a directory", e.path());
+        }
+    }
+
+    Ok(())
+}
+
+/// Read an unsigned LEB128 number from the front of `data`
+fn leb128(data: &mut &[u8]) -> Result<usize> {
+    let mut value = 0usize;
+
+    for shift in (0..35).step_by(7) {
+        let (&byte, rest) = match data.split_first() {
+            Some(split) => split,
+            None => bail!("truncated module"),
+        };
+        *data = rest;
+
+        value |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    bail!("invalid LEB128 number")
+}
+
+/// Append `value` as unsigned LEB128 number to `out`
+fn write_leb128(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// `module` with its resources section, if any, replaced by one holding `resources`
+fn bundle(module: &[u8], resources: &[u8]) -> Result<Vec<u8>> {
+    if module.len() < 8 || &module[..4] != b"\0asm" {
+        bail!("not a WebAssembly module");
+    }
+
+    let mut out = module[..8].to_vec();
+    let mut data = &module[8..];
+
+    while !data.is_empty() {
+        let start = data;
+        let id = data[0];
+        data = &data[1..];
+        let size = leb128(&mut data)?;
+        if data.len() < size {
+            bail!("truncated module");
+        }
+        let (mut payload, rest) = data.split_at(size);
+        data = rest;
+
+        let section = &start[..start.len() - data.len()];
+        if id == 0 {
+            let len = leb128(&mut payload)?;
+            if payload.get(..len) == Some(RESOURCES_SECTION.as_bytes()) {
+                continue;
+            }
+        }
+        out.extend_from_slice(section);
+    }
+
+    let mut section = Vec::new();
+    write_leb128(&mut section, RESOURCES_SECTION.len());
+    section.extend_from_slice(RESOURCES_SECTION.as_bytes());
+    section.extend_from_slice(resources);
+
+    out.push(0);
+    write_leb128(&mut out, section.len());
+    out.extend_from_slice(&section);
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn leb128_roundtrip() {
+        for value in [0, 1, 127, 128, 300, 1 << 20, u32::MAX as usize] {
+            let mut bytes = Vec::new();
+            write_leb128(&mut bytes, value);
+            let mut data = &bytes[..];
+            assert_eq!(leb128(&mut data).unwrap(), value);
+            assert!(data.is_empty());
+        }
+
+        assert!(leb128(&mut &[0x80][..]).is_err());
+    }
+
+    #[test]
+    fn replace() {
+        let module = wat::parse_str("(module (func (export \"_start\")))").unwrap();
+
+        let once = bundle(&module, b"one").unwrap();
+        assert!(once.starts_with(&module));
+        assert!(once.ends_with(b".enarx.resourcesone"));
+
+        let twice = bundle(&once, b"two").unwrap();
+        assert_eq!(twice.len(), once.len());
+        assert!(twice.ends_with(b"two"));
+
+        assert!(bundle(b"not wasm", b"").is_err());
+        assert!(bundle(&once[..once.len() - 1], b"").is_err());
+    }
+
+    #[test]
+    fn pack_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("etc")).unwrap();
+        fs::write(dir.path().join("etc/app.toml"), b"x").unwrap();
+        fs::write(dir.path().join("a"), b"").unwrap();
+
+        let mut out = Vec::new();
+        pack(dir.path(), "", &mut Vec::new(), &mut out).unwrap();
+
+        let mut expected = Vec::new();
+        entry(&mut expected, KIND_FILE, "a", Some(b"")).unwrap();
+        entry(&mut expected, KIND_DIR, "etc", None).unwrap();
+        entry(&mut expected, KIND_FILE, "etc/app.toml", Some(b"x")).unwrap();
+        assert_eq!(out, expected);
+
+        // A symlink to another directory is followed, one to a parent is not
+        std::os::unix::fs::symlink("etc", dir.path().join("link")).unwrap();
+        assert!(pack(dir.path(), "", &mut Vec::new(), &mut Vec::new()).is_ok());
+
+        std::os::unix::fs::symlink("..", dir.path().join("etc/loop")).unwrap();
+        assert!(pack(dir.path(), "", &mut Vec::new(), &mut Vec::new()).is_err());
+    }
+}
@@ -1,15 +1,20 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::cli::{BackendOptions, StructOpt};
+use crate::cli::{
+    BackendOptions, FaultOptions, LimitOptions, MetricsOptions, RecordOptions, StructOpt,
+};
+use crate::config::exec_args;
 
 use std::path::PathBuf;
 
+use anyhow::{bail, Result};
+
 /// Execute a (static, PIE) binary inside an Enarx Keep.
 ///
 /// The binary must be a statically linked position-independent executable,
-/// compiled with flags like `-nostdlib -static-pie -fPIC`. No commandline
-/// arguments are passed, the program's argv[0] will be `/init`, and the
-/// environment is empty except for `LANG=C`.
+/// compiled with flags like `-nostdlib -static-pie -fPIC`. The program's
+/// argv[0] will be `/init`, followed by the arguments given after `--`. The
+/// environment contains `LANG=C` and the variables given with `--env`.
 ///
 /// This subcommand is hidden from the main help because it's unlikely to be
 /// useful because of the restrictions above. It's mainly used for
@@ -19,12 +24,53 @@ pub struct Options {
     #[structopt(flatten)]
     pub backend: BackendOptions,
 
+    #[structopt(flatten)]
+    pub limits: LimitOptions,
+
+    #[structopt(flatten)]
+    pub metrics: MetricsOptions,
+
+    #[structopt(flatten)]
+    pub record: RecordOptions,
+
+    #[structopt(flatten)]
+    pub faults: FaultOptions,
+
+    /// Set an environment variable of the binary
+    #[structopt(long = "env", value_name = "KEY=VALUE", number_of_values = 1)]
+    pub env: Vec<String>,
+
     /// Binary to load and run inside the keep
     #[structopt(value_name = "BINARY")]
     pub binpath: PathBuf,
 
+    /// Arguments passed to the binary
+    #[structopt(value_name = "ARGS", last = true)]
+    pub args: Vec<String>,
+
     /// gdb options
     #[cfg(feature = "gdb")]
     #[structopt(long, default_value = "localhost:23456")]
     pub gdblisten: String,
 }
+
+impl Options {
+    /// The serialized arguments and environment of the binary
+    pub fn exec_args(&self) -> Result<Vec<u8>> {
+        if let Some(var) = self.env.iter().find(|v| !v.contains('=')) {
+            bail!(
+                "environment variable {:?} is not of the form KEY=VALUE",
+                var
+            );
+        }
+
+        let args: Vec<_> = std::iter::once("/init".to_string())
+            .chain(self.args.iter().cloned())
+            .collect();
+        let env: Vec<_> = std::iter::once("LANG=C".to_string())
+            .chain(self.env.iter().cloned())
+            .collect();
+
+        exec_args(&args, &env)
+    }
+}
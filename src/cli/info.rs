@@ -1,16 +1,129 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::backend::BACKENDS;
-use crate::cli::{Result, StructOpt};
+use crate::backend::{Datum, BACKENDS};
+use crate::cli::{pick_backend, Result, StructOpt};
+
 use std::ops::Deref;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::{Map, Value};
 
 /// Show details about backend support on this system
 #[derive(StructOpt, Debug)]
-pub struct Options {}
+pub struct Options {
+    /// Output format ("text", "json")
+    #[structopt(long, default_value = "text")]
+    pub format: Format,
+
+    /// The backend to explain the choice for, as `enarx run` would
+    #[structopt(long, env = "ENARX_BACKEND")]
+    pub backend: Option<String>,
+}
+
+/// Output formats for `enarx info`
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("unknown format {:?}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DatumReport {
+    name: String,
+    pass: bool,
+    info: Option<String>,
+    mesg: Option<String>,
+}
+
+impl From<Datum> for DatumReport {
+    fn from(datum: Datum) -> Self {
+        Self {
+            // Leading spaces only indent the text output
+            name: datum.name.trim().into(),
+            pass: datum.pass,
+            info: datum.info,
+            mesg: datum.mesg,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct BackendReport {
+    name: &'static str,
+    supported: bool,
+    data: Vec<DatumReport>,
+    capabilities: Map<String, Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct Pick {
+    /// `None`, if no backend can be used
+    backend: Option<&'static str>,
+    reason: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Report {
+    backends: Vec<BackendReport>,
+    pick: Pick,
+}
 
 impl Options {
-    /// Display nicely-formatted info about each backend
     pub fn display(self) -> Result<()> {
+        match self.format {
+            Format::Text => self.text(),
+            Format::Json => self.json(),
+        }
+    }
+
+    /// Print a machine-readable report of each backend and the backend choice
+    fn json(self) -> Result<()> {
+        let backends = BACKENDS
+            .deref()
+            .iter()
+            .map(|backend| BackendReport {
+                name: backend.name(),
+                supported: backend.have(),
+                data: backend.data().into_iter().map(Into::into).collect(),
+                capabilities: backend
+                    .capabilities()
+                    .into_iter()
+                    .map(|c| (c.name.to_string(), c.value))
+                    .collect(),
+            })
+            .collect();
+
+        let pick = match pick_backend(self.backend.as_deref()) {
+            Ok((backend, reason)) => Pick {
+                backend: Some(backend.name()),
+                reason,
+            },
+            Err(e) => Pick {
+                backend: None,
+                reason: e.to_string(),
+            },
+        };
+
+        let report = Report { backends, pick };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        Ok(())
+    }
+
+    /// Display nicely-formatted info about each backend
+    fn text(self) -> Result<()> {
         use colorful::*;
 
         for backend in BACKENDS.deref() {
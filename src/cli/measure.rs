@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{StructOpt, WorkldrOptions};
+use crate::backend::{sallyport_requires, BACKENDS};
+
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+/// Print the launch measurement of a workload for every compiled-in backend.
+///
+/// No TEE hardware is needed for this. The WebAssembly module itself is not
+/// part of the launch measurement; its SHA-256 digest is printed alongside.
+#[derive(StructOpt, Debug)]
+pub struct Options {
+    #[structopt(flatten)]
+    pub workldr: WorkldrOptions,
+
+    /// Output format ("hex", "json")
+    #[structopt(long, default_value = "hex")]
+    pub format: Format,
+
+    /// Measure a (static, PIE) binary as used by `enarx exec` instead
+    #[structopt(long, value_name = "BINARY", parse(from_os_str))]
+    pub exec: Option<PathBuf>,
+
+    /// Path of the WebAssembly module
+    #[structopt(
+        value_name = "MODULE",
+        parse(from_os_str),
+        required_unless = "exec",
+        conflicts_with = "exec"
+    )]
+    pub module: Option<PathBuf>,
+}
+
+/// Output formats for `enarx measure`
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Hex,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hex" => Ok(Self::Hex),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("unknown format {:?}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Component {
+    name: String,
+    version: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+struct Digest {
+    path: PathBuf,
+    sha256: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Measurement {
+    backend: &'static str,
+    shim_version: &'static str,
+    sallyport_requires: Vec<String>,
+    /// `None`, if the backend has no launch measurement
+    measurement: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Report {
+    sallyport: &'static str,
+    workldr: Option<Component>,
+    exec: Option<Digest>,
+    module: Option<Digest>,
+    backends: Vec<Measurement>,
+}
+
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn digest(path: PathBuf) -> Result<(Vec<u8>, Digest)> {
+    let bytes = std::fs::read(&path).with_context(|| format!("failed to read {:?}", path))?;
+    let sha256 = hex(&openssl::sha::sha256(&bytes));
+    Ok((bytes, Digest { path, sha256 }))
+}
+
+impl Options {
+    pub fn display(self) -> Result<()> {
+        let mut report = Report {
+            sallyport: sallyport::VERSION,
+            workldr: None,
+            exec: None,
+            module: None,
+            backends: Vec::new(),
+        };
+
+        let exec = match (self.exec, self.module) {
+            (Some(path), _) => {
+                let (bytes, digest) = digest(path)?;
+                report.exec = Some(digest);
+                bytes
+            }
+            // An external workldr is reported like an executable
+            (None, Some(path)) if self.workldr.path.is_some() => {
+                let exec = self.workldr.exec()?.into_owned();
+                report.exec = Some(digest(self.workldr.path.unwrap())?.1);
+                report.module = Some(digest(path)?.1);
+                exec
+            }
+            (None, Some(path)) => {
+                let workldr = self.workldr.pick()?;
+                report.workldr = Some(Component {
+                    name: workldr.name().into(),
+                    version: workldr.version(),
+                });
+                report.module = Some(digest(path)?.1);
+                workldr.exec().to_vec()
+            }
+            (None, None) => unreachable!(),
+        };
+
+        for backend in BACKENDS.deref() {
+            let hash = backend
+                .hash(backend.shim(), &exec)
+                .with_context(|| format!("failed to measure for backend {}", backend.name()))?;
+
+            report.backends.push(Measurement {
+                backend: backend.name(),
+                shim_version: backend.shim_version(),
+                sallyport_requires: match backend.shim().is_empty() {
+                    true => Vec::new(),
+                    false => sallyport_requires(backend.shim())?,
+                },
+                measurement: match hash.is_empty() {
+                    true => None,
+                    false => Some(hex(&hash)),
+                },
+            });
+        }
+
+        match self.format {
+            Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            Format::Hex => {
+                println!("sallyport: {}", report.sallyport);
+                if let Some(workldr) = report.workldr {
+                    println!("workldr: {} {}", workldr.name, workldr.version);
+                }
+                if let Some(exec) = report.exec {
+                    println!("exec: {:?} (sha256: {})", exec.path, exec.sha256);
+                }
+                if let Some(module) = report.module {
+                    println!("module: {:?} (sha256: {})", module.path, module.sha256);
+                }
+
+                for m in report.backends {
+                    println!("Backend: {}", m.backend);
+                    println!("  shim version: {}", m.shim_version);
+                    println!("  sallyport requires: {}", m.sallyport_requires.join(", "));
+                    println!(
+                        "  measurement: {}",
+                        m.measurement.as_deref().unwrap_or("none")
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,14 +1,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
+mod bundle;
 mod exec;
 mod info;
 mod log;
+mod measure;
 mod run;
+mod serve;
 #[cfg(feature = "backend-sev")]
 pub mod sev;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use std::borrow::Cow;
 use std::ops::Deref;
+use std::path::PathBuf;
 use structopt::{clap::AppSettings, StructOpt};
 
 pub use self::log::LogOptions;
@@ -20,6 +25,9 @@ pub enum Command {
     #[structopt(setting(AppSettings::Hidden))]
     Exec(exec::Options),
     Run(run::Options),
+    Measure(measure::Options),
+    Bundle(bundle::Options),
+    Serve(serve::Options),
     #[cfg(feature = "backend-sev")]
     Sev(sev::Command),
 }
@@ -28,33 +36,197 @@ pub enum Command {
 // Options & shared setup code for backends/shims
 //
 
-use crate::backend::{Backend, BACKENDS};
+use crate::backend::{check_exec, check_shim, Backend, BACKENDS};
 
 #[derive(StructOpt, Debug)]
 pub struct BackendOptions {
     /// Set which backend to use
     #[structopt(long, env = "ENARX_BACKEND")]
     backend: Option<String>,
-    // TODO: Path to an external shim binary?
-    //shim: Option<PathBuf>,
+
+    /// Path of a shim binary to use instead of the builtin one
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub shim: Option<PathBuf>,
 }
 
 impl BackendOptions {
     pub fn pick(&self) -> Result<&dyn Backend> {
-        if let Some(ref name) = self.backend {
-            BACKENDS
-                .deref()
-                .iter()
-                .find(|b| b.have() && b.name() == name)
-                .ok_or_else(|| anyhow!("Keep backend {:?} is unsupported", name))
-        } else {
-            BACKENDS
-                .deref()
-                .iter()
-                .find(|b| b.have())
-                .ok_or_else(|| anyhow!("No supported backend found"))
+        pick_backend(self.backend.as_deref()).map(|(backend, _)| backend)
+    }
+
+    /// The shim for `backend`: the one given with `--shim`, or the builtin one
+    pub fn shim(&self, backend: &dyn Backend) -> Result<Cow<'static, [u8]>> {
+        match self.shim {
+            Some(ref path) => {
+                let shim = std::fs::read(path)
+                    .with_context(|| format!("failed to read the shim {:?}", path))?;
+                check_shim(&shim).with_context(|| format!("invalid shim {:?}", path))?;
+                Ok(Cow::Owned(shim))
+            }
+            None => Ok(Cow::Borrowed(backend.shim())),
+        }
+    }
+}
+
+/// Why a backend is not supported on this platform
+fn unsupported(backend: &dyn Backend) -> String {
+    let failed: Vec<_> = backend
+        .data()
+        .iter()
+        .filter(|d| !d.pass)
+        .map(|d| d.name.trim().to_string())
+        .collect();
+
+    match failed.is_empty() {
+        true => format!("{} is unsupported", backend.name()),
+        false => format!("{} is unsupported ({})", backend.name(), failed.join(", ")),
+    }
+}
+
+/// Pick the backend called `name`, or the first one the platform supports
+///
+/// Returns the backend along with the reasoning behind the choice.
+pub fn pick_backend(name: Option<&str>) -> Result<(&'static dyn Backend, String)> {
+    let backends = BACKENDS.deref();
+
+    if let Some(name) = name {
+        let backend = backends
+            .iter()
+            .find(|b| b.name() == name)
+            .ok_or_else(|| anyhow!("Keep backend {:?} is unknown", name))?;
+
+        if !backend.have() {
+            bail!("Keep backend {}", unsupported(&**backend));
+        }
+
+        return Ok((&**backend, format!("{} was selected explicitly", name)));
+    }
+
+    let mut skipped = Vec::new();
+
+    // The nil backend offers no protection, so it has to be asked for explicitly
+    for backend in backends.iter().filter(|b| b.name() != "nil") {
+        if backend.have() {
+            let mut reason = format!("{} is the first supported backend", backend.name());
+            if !skipped.is_empty() {
+                reason += &format!("; {}", skipped.join("; "));
+            }
+            return Ok((&**backend, reason));
+        }
+
+        skipped.push(unsupported(&**backend));
+    }
+
+    bail!("No supported backend found: {}", skipped.join("; "))
+}
+
+//
+// Options for resource limits of keeps
+//
+use crate::limits::{parse_duration, parse_size, Limits};
+
+use std::time::Duration;
+
+#[derive(StructOpt, Debug)]
+pub struct LimitOptions {
+    /// Maximum memory of the keep, e.g. `512M`
+    #[structopt(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    pub memory_max: Option<usize>,
+
+    /// Maximum wall-clock time the keep may run, e.g. `30s`
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub timeout: Option<Duration>,
+
+    /// Maximum CPU time the keep may use, e.g. `10s`
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub cpu_time: Option<Duration>,
+}
+
+impl LimitOptions {
+    /// The limits given on the command line, overriding those of `config`
+    pub fn limits(&self, config: Limits) -> Limits {
+        config.overridden_by(Limits {
+            memory_max: self.memory_max,
+            timeout: self.timeout,
+            cpu_time: self.cpu_time,
+        })
+    }
+}
+
+//
+// Options for runtime metrics of keeps
+//
+
+#[derive(StructOpt, Debug)]
+pub struct MetricsOptions {
+    /// Write a JSON summary of the keep's runtime metrics to PATH on exit
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub metrics: Option<PathBuf>,
+
+    /// Serve the keep's runtime metrics for Prometheus on the Unix socket PATH
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub metrics_socket: Option<PathBuf>,
+}
+
+impl MetricsOptions {
+    /// Start collecting the metrics of the keep
+    pub fn start(&self) -> Result<()> {
+        crate::metrics::start(self.metrics.clone(), self.metrics_socket.as_deref())
+    }
+}
+
+//
+// Options for recording and replaying the hostcalls of keeps
+//
+
+#[derive(StructOpt, Debug)]
+pub struct RecordOptions {
+    /// Record the keep's requests to the host and their replies to FILE
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub record: Option<PathBuf>,
+
+    /// Replay the host's replies recorded in FILE instead of executing the keep's requests
+    #[structopt(
+        long,
+        value_name = "FILE",
+        parse(from_os_str),
+        conflicts_with = "record"
+    )]
+    pub replay: Option<PathBuf>,
+}
+
+impl RecordOptions {
+    /// Start recording or replaying the hostcalls of a keep on `backend`
+    pub fn start(&self, backend: &dyn Backend) -> Result<()> {
+        crate::replay::start(
+            self.record.as_deref(),
+            self.replay.as_deref(),
+            backend.name(),
+        )
+    }
+}
+
+//
+// Options for injecting faults into the replies to keeps
+//
+
+#[derive(StructOpt, Debug)]
+pub struct FaultOptions {
+    /// Tamper with replies to the keep like a malicious host would
+    #[structopt(long)]
+    pub inject_faults: bool,
+
+    /// Inject the faults chosen by SEED, implies `--inject-faults`
+    #[structopt(long, value_name = "SEED")]
+    pub fault_seed: Option<u64>,
+}
+
+impl FaultOptions {
+    /// Start injecting faults, if asked to
+    pub fn start(&self) {
+        if self.inject_faults || self.fault_seed.is_some() {
+            crate::faults::start(self.fault_seed);
         }
-        .map(|b| &**b)
     }
 }
 
@@ -65,10 +237,25 @@ use crate::workldr::{Workldr, WORKLDRS};
 
 #[derive(StructOpt, Debug)]
 pub struct WorkldrOptions {
-    // FUTURE: Path to an external workldr binary
+    /// Path of a workldr binary to use instead of the builtin one
+    #[structopt(long = "workldr", value_name = "PATH", parse(from_os_str))]
+    pub path: Option<PathBuf>,
 }
 
 impl WorkldrOptions {
+    /// The workldr binary: the one given with `--workldr`, or the builtin one
+    pub fn exec(&self) -> Result<Cow<'static, [u8]>> {
+        match self.path {
+            Some(ref path) => {
+                let exec = std::fs::read(path)
+                    .with_context(|| format!("failed to read the workldr {:?}", path))?;
+                check_exec(&exec).with_context(|| format!("invalid workldr {:?}", path))?;
+                Ok(Cow::Owned(exec))
+            }
+            None => Ok(Cow::Borrowed(self.pick()?.exec())),
+        }
+    }
+
     pub fn pick(&self) -> Result<&dyn Workldr> {
         WORKLDRS
             .deref()
@@ -1,6 +1,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{BackendOptions, StructOpt, WorkldrOptions};
+use super::{
+    BackendOptions, FaultOptions, LimitOptions, MetricsOptions, RecordOptions, StructOpt,
+    WorkldrOptions,
+};
 
 use std::{fmt::Debug, path::PathBuf};
 
@@ -13,10 +16,30 @@ pub struct Options {
     #[structopt(flatten)]
     pub workldr: WorkldrOptions,
 
+    #[structopt(flatten)]
+    pub limits: LimitOptions,
+
+    #[structopt(flatten)]
+    pub metrics: MetricsOptions,
+
+    #[structopt(flatten)]
+    pub record: RecordOptions,
+
+    #[structopt(flatten)]
+    pub faults: FaultOptions,
+
     /// Path of the WebAssembly module to run
     #[structopt(value_name = "MODULE", parse(from_os_str))]
     pub module: PathBuf,
 
+    /// Path of the keep configuration file (e.g. `Enarx.toml`)
+    #[structopt(long, value_name = "CONFIG", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Call the exported function NAME with the configured arguments instead of `_start`
+    #[structopt(long, value_name = "NAME")]
+    pub invoke: Option<String>,
+
     /// gdb options
     #[cfg(feature = "gdb")]
     #[structopt(long, default_value = "localhost:23456")]
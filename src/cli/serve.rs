@@ -0,0 +1,450 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! `enarx serve`: a local keep manager
+//!
+//! The daemon accepts connections on a Unix socket. Every request is a JSON
+//! object on a line of its own, naming the operation in `op`, and is answered
+//! with a line holding either `{"ok": …}` or `{"error": "…"}`:
+//!
+//! ```text
+//! {"op": "launch", "module": "/srv/app.wasm", "config": "/srv/Enarx.toml"}
+//! {"op": "list"}
+//! {"op": "inspect", "id": 1}
+//! {"op": "read", "id": 1, "stream": "stdout", "offset": 0}
+//! {"op": "write", "id": 1, "data": [105, 110, 10]}
+//! {"op": "close_stdin", "id": 1}
+//! {"op": "stop", "id": 1}
+//! {"op": "remove", "id": 1}
+//! {"op": "backends"}
+//! ```
+//!
+//! The data written to and read from the streams of a keep is binary, so it
+//! is an array of bytes in both directions.
+//!
+//! Each keep runs in a worker process, which is `enarx run` with the backend
+//! the daemon picked, so a crashing keep cannot take the daemon down. The
+//! worker checks for the backend again when it starts. Its exit status has
+//! the same meaning as the one of `enarx run`.
+
+use super::measure::hex;
+use super::{pick_backend, StructOpt, WorkldrOptions};
+use crate::backend::{Backend, BACKENDS};
+use crate::socket;
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::ops::Deref;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// How many bytes of each output stream of a keep are retained
+const OUTPUT_MAX: usize = 1024 * 1024;
+
+/// How long a stopped keep may take to exit, before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run a daemon managing keeps on request over a Unix socket.
+#[derive(StructOpt, Debug)]
+pub struct Options {
+    /// Path of the Unix socket to listen on
+    /// (default: `$XDG_RUNTIME_DIR/enarx.sock` or `/tmp/enarx-<uid>/enarx.sock`)
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub socket: Option<PathBuf>,
+
+    /// Set which backend to use for all keeps
+    #[structopt(long, env = "ENARX_BACKEND")]
+    pub backend: Option<String>,
+
+    #[structopt(flatten)]
+    pub workldr: WorkldrOptions,
+}
+
+/// A request to the daemon
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    /// Start a keep running a WebAssembly module
+    Launch {
+        module: PathBuf,
+        config: Option<PathBuf>,
+    },
+    /// List all keeps
+    List,
+    /// Show the details of a keep
+    Inspect { id: u64 },
+    /// Read the output of a keep, starting at `offset`
+    Read {
+        id: u64,
+        stream: Stream,
+        #[serde(default)]
+        offset: u64,
+    },
+    /// Write to the stdin of a keep
+    Write { id: u64, data: Vec<u8> },
+    /// Close the stdin of a keep
+    CloseStdin { id: u64 },
+    /// Terminate a keep, killing it if it does not exit in time
+    Stop { id: u64 },
+    /// Forget about a keep, which exited
+    Remove { id: u64 },
+    /// Show the backends probed at startup
+    Backends,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// The retained tail of an output stream of a keep
+#[derive(Default)]
+struct Output {
+    /// The stream offset of the first byte of `data`
+    start: u64,
+    data: Vec<u8>,
+    /// Whether the keep closed the stream
+    eof: bool,
+}
+
+impl Output {
+    /// Collect the output read from `reader` in a thread of its own
+    fn collect(reader: impl Read + Send + 'static) -> Arc<Mutex<Self>> {
+        let output = Arc::new(Mutex::new(Self::default()));
+        let collected = output.clone();
+
+        std::thread::spawn(move || {
+            let mut reader = reader;
+            let mut buf = [0u8; 4096];
+
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+
+                let mut output = collected.lock().unwrap();
+                output.data.extend_from_slice(&buf[..n]);
+                if output.data.len() > OUTPUT_MAX {
+                    let excess = output.data.len() - OUTPUT_MAX;
+                    output.data.drain(..excess);
+                    output.start += excess as u64;
+                }
+            }
+
+            collected.lock().unwrap().eof = true;
+        });
+
+        output
+    }
+
+    /// The output from stream offset `offset` on
+    fn read(&self, offset: u64) -> Value {
+        let skip = offset
+            .saturating_sub(self.start)
+            .min(self.data.len() as u64);
+        let data = &self.data[skip as usize..];
+
+        json!({
+            // Output lost to the retention limit is skipped
+            "offset": self.start + skip,
+            "data": data,
+            "next": self.start + self.data.len() as u64,
+            "eof": self.eof,
+        })
+    }
+}
+
+/// A keep run by the daemon
+struct Keep {
+    module: PathBuf,
+    child: Child,
+    /// Shared, so writing does not block the other requests
+    stdin: Option<Arc<Mutex<ChildStdin>>>,
+    stdout: Arc<Mutex<Output>>,
+    stderr: Arc<Mutex<Output>>,
+    status: Option<ExitStatus>,
+    expected_measurement: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct KeepInfo {
+    id: u64,
+    module: PathBuf,
+    pid: u32,
+    running: bool,
+    /// The exit status, if the keep exited
+    status: Option<i32>,
+    /// The signal, which killed the worker
+    signal: Option<i32>,
+    /// The launch measurement the host expects for the keep, if the backend
+    /// has one
+    ///
+    /// This is computed from the shim and workldr, not reported by the
+    /// hardware, so it is what attestation evidence has to be checked against.
+    expected_measurement: Option<String>,
+}
+
+impl Keep {
+    fn info(&mut self, id: u64) -> KeepInfo {
+        if self.status.is_none() {
+            self.status = self.child.try_wait().ok().flatten();
+        }
+
+        KeepInfo {
+            id,
+            module: self.module.clone(),
+            pid: self.child.id(),
+            running: self.status.is_none(),
+            status: self.status.and_then(|s| s.code()),
+            signal: self.status.and_then(|s| s.signal()),
+            expected_measurement: self.expected_measurement.clone(),
+        }
+    }
+}
+
+/// Look up the keep with `id`
+fn keep(keeps: &mut BTreeMap<u64, Keep>, id: u64) -> Result<&mut Keep> {
+    keeps
+        .get_mut(&id)
+        .ok_or_else(|| anyhow!("no keep with id {}", id))
+}
+
+/// The state shared by all connections
+struct Daemon {
+    backend: &'static dyn Backend,
+    /// The workldr given with `--workldr`
+    workldr: Option<PathBuf>,
+    /// The result of probing the backends at startup
+    backends: Value,
+    /// The expected launch measurement of all keeps, as they share shim and
+    /// workldr
+    expected_measurement: Option<String>,
+    keeps: Mutex<(u64, BTreeMap<u64, Keep>)>,
+}
+
+impl Daemon {
+    fn handle(&self, request: Request) -> Result<Value> {
+        // These may block, so they take the lock themselves
+        let request = match request {
+            Request::Write { id, data } => return self.write(id, data),
+            Request::Stop { id } => return self.stop(id),
+            request => request,
+        };
+
+        let mut keeps = self.keeps.lock().unwrap();
+        let (ref mut last_id, ref mut keeps) = *keeps;
+
+        let reply = match request {
+            Request::Launch { module, config } => {
+                let mut cmd = Command::new(std::env::current_exe()?);
+                cmd.arg("run").args(&["--backend", self.backend.name()]);
+                if let Some(ref workldr) = self.workldr {
+                    cmd.arg("--workldr").arg(workldr);
+                }
+                if let Some(config) = config {
+                    cmd.arg("--config").arg(config);
+                }
+
+                let mut child = cmd
+                    .arg(&module)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .context("failed to start the keep worker")?;
+
+                *last_id += 1;
+                let id = *last_id;
+                info!("keep {} started: {:?}", id, module);
+
+                let mut keep = Keep {
+                    module,
+                    stdin: child.stdin.take().map(|s| Arc::new(Mutex::new(s))),
+                    stdout: Output::collect(child.stdout.take().unwrap()),
+                    stderr: Output::collect(child.stderr.take().unwrap()),
+                    child,
+                    status: None,
+                    expected_measurement: self.expected_measurement.clone(),
+                };
+                let info = keep.info(id);
+                keeps.insert(id, keep);
+                serde_json::to_value(info)?
+            }
+
+            Request::List => {
+                let infos: Vec<_> = keeps.iter_mut().map(|(id, k)| k.info(*id)).collect();
+                serde_json::to_value(infos)?
+            }
+
+            Request::Inspect { id } => serde_json::to_value(keep(keeps, id)?.info(id))?,
+
+            Request::Read { id, stream, offset } => {
+                let keep = keep(keeps, id)?;
+                let output = match stream {
+                    Stream::Stdout => &keep.stdout,
+                    Stream::Stderr => &keep.stderr,
+                };
+                output.lock().unwrap().read(offset)
+            }
+
+            Request::CloseStdin { id } => {
+                drop(keep(keeps, id)?.stdin.take());
+                Value::Null
+            }
+
+            Request::Remove { id } => {
+                if keep(keeps, id)?.info(id).running {
+                    bail!("keep {} is still running", id);
+                }
+                keeps.remove(&id);
+                Value::Null
+            }
+
+            Request::Backends => self.backends.clone(),
+
+            Request::Write { .. } | Request::Stop { .. } => unreachable!(),
+        };
+
+        Ok(reply)
+    }
+
+    /// Write `data` to the stdin of a keep, without holding the lock
+    fn write(&self, id: u64, data: Vec<u8>) -> Result<Value> {
+        let stdin = {
+            let mut keeps = self.keeps.lock().unwrap();
+            keep(&mut keeps.1, id)?
+                .stdin
+                .clone()
+                .ok_or_else(|| anyhow!("the stdin of keep {} is closed", id))?
+        };
+
+        stdin.lock().unwrap().write_all(&data)?;
+        Ok(json!({ "written": data.len() }))
+    }
+
+    /// Ask a keep to terminate and kill it after `STOP_TIMEOUT`
+    fn stop(&self, id: u64) -> Result<Value> {
+        {
+            let mut keeps = self.keeps.lock().unwrap();
+            let keep = keep(&mut keeps.1, id)?;
+            if !keep.info(id).running {
+                return Ok(serde_json::to_value(keep.info(id))?);
+            }
+
+            // The keep was not reaped, so the pid was not reused
+            if unsafe { libc::kill(keep.child.id() as _, libc::SIGTERM) } != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        loop {
+            let mut keeps = self.keeps.lock().unwrap();
+            let keep = keep(&mut keeps.1, id)?;
+            if !keep.info(id).running {
+                info!("keep {} stopped", id);
+                return Ok(serde_json::to_value(keep.info(id))?);
+            }
+
+            if Instant::now() >= deadline {
+                keep.child.kill()?;
+                keep.status = Some(keep.child.wait()?);
+                info!("keep {} killed", id);
+                return Ok(serde_json::to_value(keep.info(id))?);
+            }
+
+            drop(keeps);
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Serve the requests of a single client until it disconnects
+    fn serve(&self, stream: UnixStream) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let reply = serde_json::from_str(&line)
+                .map_err(anyhow::Error::from)
+                .and_then(|request| self.handle(request));
+
+            let reply = match reply {
+                Ok(value) => json!({ "ok": value }),
+                Err(e) => json!({ "error": format!("{:#}", e) }),
+            };
+
+            writeln!(writer, "{}", reply)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Options {
+    pub fn run(self) -> Result<()> {
+        let backends = BACKENDS
+            .deref()
+            .iter()
+            .map(|b| json!({ "name": b.name(), "supported": b.have() }))
+            .collect::<Vec<_>>();
+
+        let (backend, reason) = pick_backend(self.backend.as_deref())?;
+        info!("backend {}: {}", backend.name(), reason);
+
+        let workldr = self.workldr.exec()?;
+        let expected_measurement = backend
+            .hash(backend.shim(), &workldr)
+            .ok()
+            .filter(|hash| !hash.is_empty())
+            .map(|hash| hex(&hash));
+
+        let daemon = Arc::new(Daemon {
+            backend,
+            workldr: self.workldr.path.clone(),
+            backends: json!({
+                "backends": backends,
+                "pick": { "backend": backend.name(), "reason": reason },
+            }),
+            expected_measurement,
+            keeps: Mutex::new((0, BTreeMap::new())),
+        });
+
+        // Only the owner may manage keeps
+        let path = match self.socket {
+            Some(path) => path,
+            None => socket::runtime_dir()?.join("enarx.sock"),
+        };
+        let listener = socket::bind(&path)?;
+        eprintln!("Listening on {:?}", path);
+
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let daemon = daemon.clone();
+
+            std::thread::spawn(move || {
+                if let Err(e) = daemon.serve(stream) {
+                    warn!("connection failed: {:#}", e);
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
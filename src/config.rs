@@ -0,0 +1,765 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The keep configuration file (`Enarx.toml`)
+//!
+//! The configuration is parsed and validated on the host. Everything the
+//! workload needs from the host (files, sockets) is opened here, and the
+//! resulting [`KeepConfig`] is serialized and handed to the code inside the
+//! keep on request via the `SYS_ENARX_KEEP_CONFIG` sallyport call.
+//!
+//! ```toml
+//! args = ["--verbose"]
+//!
+//! [env]
+//! GREETING = "Hello"
+//!
+//! [stdio]
+//! stdin = "null"
+//! stderr = "fd:log"
+//!
+//! [invoke]
+//! function = "add"
+//! results = "log"
+//!
+//! [[dirs]]
+//! path = "/etc/app"
+//! host = "/srv/app/config"
+//!
+//! [[dirs]]
+//! path = "/tmp"
+//! writable = true
+//!
+//! [[files]]
+//! name = "data"
+//! path = "/srv/data.txt"
+//!
+//! [[files]]
+//! name = "log"
+//! path = "/var/log/workload.log"
+//! writable = true
+//!
+//! [[sockets]]
+//! name = "api"
+//! addr = "127.0.0.1:8080"
+//! kind = "listen"
+//!
+//! [policy]
+//! socket_families = ["inet"]
+//! addresses = ["127.0.0.0/8"]
+//!
+//! [limits]
+//! memory_max = "512M"
+//! timeout = "1h"
+//! ```
+//!
+//! See [`super::policy`] for the syscall policy and [`super::limits`] for the
+//! resource limits.
+
+use super::limits::{Limits, LimitsConfig};
+use super::policy::PolicyConfig;
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use primordial::Register;
+use sallyport::Block;
+use serde::{Deserialize, Serialize};
+
+/// The sallyport call used by the code inside the keep to fetch its configuration.
+///
+/// Arguments: the number of bytes the keep can receive and the offset into
+/// the serialized configuration. The host copies the data to the start of the
+/// block buffer and replies with the number of bytes copied and the total
+/// size of the configuration.
+pub const SYS_ENARX_KEEP_CONFIG: i64 = 0xEA10;
+
+/// The sallyport call used by the shim to fetch the arguments and environment of the exec.
+///
+/// Same arguments and reply as `SYS_ENARX_KEEP_CONFIG`, for the data
+/// serialized by [`exec_args`].
+pub const SYS_ENARX_EXEC_ARGS: i64 = 0xEA13;
+
+/// The maximum size of the serialized arguments and environment of the exec
+///
+/// The shims fetch them into a buffer of this size.
+pub const EXEC_ARGS_MAX: usize = 16 * 1024;
+
+/// Where the workload finds the resources bundled with its module, see `enarx bundle`
+pub const RESOURCES_PATH: &str = "/resources";
+
+/// The workload configuration as written by the user
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Arguments passed to the workload
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Environment variables passed to the workload
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Handling of the workload's standard I/O
+    #[serde(default)]
+    pub stdio: Stdio,
+
+    /// The exported function to call instead of the WASI entry point
+    pub invoke: Option<InvokeConfig>,
+
+    /// Directories preopened for the workload
+    #[serde(default)]
+    pub dirs: Vec<DirConfig>,
+
+    /// Host files made available to the workload
+    #[serde(default)]
+    pub files: Vec<FileConfig>,
+
+    /// Sockets made available to the workload
+    #[serde(default)]
+    pub sockets: Vec<SocketConfig>,
+
+    /// The policy for syscalls proxied to the host
+    #[serde(default)]
+    pub policy: PolicyConfig,
+
+    /// The resource limits of the keep
+    #[serde(default)]
+    pub limits: LimitsConfig,
+}
+
+/// Handling of a single standard I/O stream
+///
+/// Written as `"inherit"`, `"null"` or `"fd:NAME"`, which `wasmldr` parses
+/// into its own `StdioMode`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum StdioMode {
+    /// Use the corresponding stream of the `enarx` process
+    Inherit,
+    /// Reads return EOF, writes are discarded
+    Null,
+    /// The file or socket of the configuration called `NAME`
+    Fd(String),
+}
+
+impl Default for StdioMode {
+    fn default() -> Self {
+        Self::Inherit
+    }
+}
+
+impl TryFrom<String> for StdioMode {
+    type Error = anyhow::Error;
+
+    fn try_from(mode: String) -> Result<Self> {
+        Ok(match mode.as_str() {
+            "inherit" => Self::Inherit,
+            "null" => Self::Null,
+            _ => match mode.strip_prefix("fd:") {
+                Some(name) => Self::Fd(name.into()),
+                None => bail!(
+                    "invalid stdio mode {:?}, expected inherit, null or fd:NAME",
+                    mode
+                ),
+            },
+        })
+    }
+}
+
+impl From<StdioMode> for String {
+    fn from(mode: StdioMode) -> Self {
+        match mode {
+            StdioMode::Inherit => "inherit".into(),
+            StdioMode::Null => "null".into(),
+            StdioMode::Fd(name) => format!("fd:{}", name),
+        }
+    }
+}
+
+/// Handling of the standard I/O streams
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Stdio {
+    #[serde(default)]
+    pub stdin: StdioMode,
+    #[serde(default)]
+    pub stdout: StdioMode,
+    #[serde(default)]
+    pub stderr: StdioMode,
+}
+
+/// A function exported by the module, called with the workload arguments
+///
+/// The arguments are parsed according to the signature of the function and
+/// its results are written as JSON.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct InvokeConfig {
+    /// The name of the exported function
+    pub function: String,
+
+    /// The writable file or socket the results are written to, stdout if unset
+    #[serde(default)]
+    pub results: Option<String>,
+}
+
+/// A directory preopened for the workload
+///
+/// Without `host`, the directory lives in the memory of the keep and starts
+/// out empty. Its contents never reach the host.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DirConfig {
+    /// The path the workload finds the directory at
+    pub path: String,
+
+    /// The host directory, accessed through the proxied file syscalls
+    ///
+    /// The policy confines the workload to paths beneath the directory.
+    #[serde(default)]
+    pub host: Option<PathBuf>,
+
+    /// Whether the workload may modify the directory
+    #[serde(default)]
+    pub writable: bool,
+}
+
+/// A host file preopened for the workload
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// The name the workload knows the file by
+    pub name: String,
+
+    /// The path of the file on the host
+    pub path: PathBuf,
+
+    /// Whether the workload may write to the file
+    #[serde(default)]
+    pub writable: bool,
+}
+
+/// The kind of a preopened socket
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SocketKind {
+    /// A TCP socket listening on `addr`
+    Listen,
+    /// A TCP connection to `addr`
+    Connect,
+}
+
+impl Default for SocketKind {
+    fn default() -> Self {
+        Self::Listen
+    }
+}
+
+/// A socket preopened for the workload
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SocketConfig {
+    /// The name the workload knows the socket by
+    pub name: String,
+
+    /// The address to listen on or connect to
+    pub addr: String,
+
+    /// Whether to listen on or connect to `addr`
+    #[serde(default)]
+    pub kind: SocketKind,
+}
+
+/// The kind of a file descriptor handed to the keep
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FdKind {
+    File,
+    Listen,
+    Stream,
+}
+
+/// A file descriptor handed to the keep
+#[derive(Serialize, Debug)]
+pub struct KeepFd {
+    pub name: String,
+    pub fd: RawFd,
+    pub kind: FdKind,
+    pub writable: bool,
+}
+
+/// A directory preopened for the workload, see [`DirConfig`]
+#[derive(Serialize, Debug)]
+pub struct KeepDir {
+    pub path: String,
+    /// The host directory, if any
+    pub fd: Option<RawFd>,
+    pub writable: bool,
+}
+
+/// The function called by the workldr, see [`InvokeConfig`]
+#[derive(Serialize, Debug)]
+pub struct KeepInvoke {
+    pub function: String,
+    /// The host file descriptor the results are written to
+    pub results_fd: RawFd,
+}
+
+/// The configuration as delivered into the keep
+#[derive(Serialize, Debug)]
+pub struct KeepConfig {
+    /// The file descriptor the WebAssembly module can be read from
+    pub module_fd: RawFd,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub stdio: Stdio,
+    pub invoke: Option<KeepInvoke>,
+    pub dirs: Vec<KeepDir>,
+    pub fds: Vec<KeepFd>,
+}
+
+/// A host resource which has to stay open as long as the keep runs
+#[derive(Debug)]
+pub enum Handle {
+    File(File),
+    Listener(TcpListener),
+    Stream(TcpStream),
+}
+
+impl AsRawFd for Handle {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::File(f) => f.as_raw_fd(),
+            Self::Listener(l) => l.as_raw_fd(),
+            Self::Stream(s) => s.as_raw_fd(),
+        }
+    }
+}
+
+impl Config {
+    /// Read and validate a configuration file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {:?}", path))?;
+        let config: Self = toml::from_str(&text)
+            .with_context(|| format!("failed to parse config file {:?}", path))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the configuration for values the keep could not represent
+    pub fn validate(&self) -> Result<()> {
+        Limits::new(&self.limits)?;
+
+        for arg in &self.args {
+            if arg.contains('\0') {
+                bail!("argument {:?} contains a NUL byte", arg);
+            }
+        }
+
+        for (k, v) in &self.env {
+            if k.is_empty() || k.contains('=') || k.contains('\0') {
+                bail!("invalid environment variable name {:?}", k);
+            }
+            if v.contains('\0') {
+                bail!("environment variable {:?} contains a NUL byte", k);
+            }
+        }
+
+        let mut paths = HashSet::new();
+        for dir in &self.dirs {
+            // The path is part of the workload's `FD_NAMES` variable, too
+            if dir.path.is_empty() || dir.path.contains(':') || dir.path.contains('\0') {
+                bail!("invalid directory path {:?}", dir.path);
+            }
+            if !paths.insert(&dir.path) {
+                bail!("duplicate directory path {:?}", dir.path);
+            }
+            if dir.path.trim_end_matches('/') == RESOURCES_PATH {
+                bail!(
+                    "directory path {:?} is reserved for bundled resources",
+                    dir.path
+                );
+            }
+        }
+
+        let mut names = HashSet::new();
+        let all = self.files.iter().map(|f| &f.name);
+        for name in all.chain(self.sockets.iter().map(|s| &s.name)) {
+            // `:` separates the names in the workload's `FD_NAMES` variable
+            if name.is_empty() || name.contains(':') || name.contains('\0') {
+                bail!("invalid file or socket name {:?}", name);
+            }
+            if !names.insert(name) {
+                bail!("duplicate file or socket name {:?}", name);
+            }
+        }
+
+        if let Some(invoke) = &self.invoke {
+            if invoke.function.is_empty() {
+                bail!("the function to invoke has no name");
+            }
+        }
+
+        let results = self.invoke.as_ref().and_then(|i| i.results.as_ref());
+        let streams = [
+            ("stdin", &self.stdio.stdin),
+            ("stdout", &self.stdio.stdout),
+            ("stderr", &self.stdio.stderr),
+        ];
+        let streams = streams.into_iter().filter_map(|(stream, mode)| match mode {
+            StdioMode::Fd(name) => Some((stream, name)),
+            _ => None,
+        });
+        for (stream, name) in streams.chain(results.map(|name| ("results", name))) {
+            let writable = match self.files.iter().find(|f| &f.name == name) {
+                Some(file) => file.writable,
+                None if self.sockets.iter().any(|s| &s.name == name) => true,
+                None => bail!("{} refers to the unknown file or socket {:?}", stream, name),
+            };
+            if stream != "stdin" && !writable {
+                bail!(
+                    "{} refers to the file {:?}, which is not writable",
+                    stream,
+                    name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Open all host resources and build the configuration for the keep
+    ///
+    /// The returned handles must outlive the keep.
+    pub fn open(&self, module: File) -> Result<(KeepConfig, Vec<Handle>)> {
+        let mut handles = Vec::new();
+        let mut dirs = Vec::new();
+        let mut fds = Vec::new();
+
+        for dir in &self.dirs {
+            let fd = match &dir.host {
+                Some(host) => {
+                    let f = OpenOptions::new()
+                        .read(true)
+                        .custom_flags(libc::O_DIRECTORY)
+                        .open(host)
+                        .with_context(|| format!("failed to open directory {:?}", host))?;
+                    let fd = f.as_raw_fd();
+                    handles.push(Handle::File(f));
+                    Some(fd)
+                }
+                None => None,
+            };
+            dirs.push(KeepDir {
+                path: dir.path.clone(),
+                fd,
+                writable: dir.writable,
+            });
+        }
+
+        for file in &self.files {
+            let f = OpenOptions::new()
+                .read(true)
+                .write(file.writable)
+                .open(&file.path)
+                .with_context(|| format!("failed to open file {:?}", file.path))?;
+            fds.push(KeepFd {
+                name: file.name.clone(),
+                fd: f.as_raw_fd(),
+                kind: FdKind::File,
+                writable: file.writable,
+            });
+            handles.push(Handle::File(f));
+        }
+
+        for socket in &self.sockets {
+            let handle = match socket.kind {
+                SocketKind::Listen => TcpListener::bind(&socket.addr).map(Handle::Listener),
+                SocketKind::Connect => TcpStream::connect(&socket.addr).map(Handle::Stream),
+            }
+            .with_context(|| format!("failed to open socket {:?}", socket.addr))?;
+            fds.push(KeepFd {
+                name: socket.name.clone(),
+                fd: handle.as_raw_fd(),
+                kind: match handle {
+                    Handle::Listener(..) => FdKind::Listen,
+                    _ => FdKind::Stream,
+                },
+                writable: true,
+            });
+            handles.push(handle);
+        }
+
+        let invoke = self.invoke.as_ref().map(|invoke| KeepInvoke {
+            function: invoke.function.clone(),
+            results_fd: match &invoke.results {
+                Some(name) => fds.iter().find(|fd| &fd.name == name).map_or(1, |fd| fd.fd),
+                None => 1,
+            },
+        });
+
+        let config = KeepConfig {
+            module_fd: module.as_raw_fd(),
+            args: self.args.clone(),
+            env: self.env.clone(),
+            stdio: self.stdio.clone(),
+            invoke,
+            dirs,
+            fds,
+        };
+        handles.push(Handle::File(module));
+
+        Ok((config, handles))
+    }
+}
+
+impl KeepConfig {
+    /// The host file descriptors handed to the keep
+    pub fn raw_fds(&self) -> impl Iterator<Item = RawFd> + '_ {
+        let dirs = self.dirs.iter().filter_map(|d| d.fd);
+        std::iter::once(self.module_fd)
+            .chain(dirs)
+            .chain(self.fds.iter().map(|f| f.fd))
+    }
+
+    /// The host directories handed to the keep and whether they are writable
+    pub fn host_dirs(&self) -> impl Iterator<Item = (RawFd, bool)> + '_ {
+        self.dirs
+            .iter()
+            .filter_map(|d| d.fd.map(|fd| (fd, d.writable)))
+    }
+
+    /// Serialize the configuration for delivery into the keep
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        toml::to_vec(self).map_err(|e| anyhow!("failed to serialize keep config: {}", e))
+    }
+}
+
+/// Serialize the arguments and environment of the exec
+///
+/// The number of arguments as little-endian `u64` is followed by the
+/// arguments and then the environment variables (`KEY=VALUE`), each
+/// terminated by a NUL byte.
+pub fn exec_args(args: &[String], env: &[String]) -> Result<Vec<u8>> {
+    if args.is_empty() {
+        bail!("the exec needs at least one argument");
+    }
+
+    let mut bytes = (args.len() as u64).to_le_bytes().to_vec();
+    for s in args.iter().chain(env) {
+        if s.contains('\0') {
+            bail!("argument {:?} contains a NUL byte", s);
+        }
+
+        bytes.extend_from_slice(s.as_bytes());
+        bytes.push(0);
+    }
+
+    if bytes.len() > EXEC_ARGS_MAX {
+        bail!(
+            "the arguments and environment take {} bytes, at most {} are supported",
+            bytes.len(),
+            EXEC_ARGS_MAX
+        );
+    }
+
+    Ok(bytes)
+}
+
+/// Handle a `SYS_ENARX_KEEP_CONFIG` or `SYS_ENARX_EXEC_ARGS` request for `config`
+pub fn deliver(config: Option<&[u8]>, block: &mut Block) -> Result<[Register<usize>; 2], i32> {
+    let config = config.ok_or(libc::ENOENT)?;
+
+    let req = unsafe { block.msg.req };
+    let len: usize = req.arg[0].into();
+    let offset: usize = req.arg[1].into();
+
+    let rest = config.get(offset..).ok_or(libc::EINVAL)?;
+    let n = rest.len().min(len).min(Block::buf_capacity());
+
+    let c = block.cursor();
+    c.copy_from_slice(&rest[..n]).map_err(|_| libc::ENOBUFS)?;
+
+    Ok([n.into(), config.len().into()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config: Config = toml::from_str(
+            r#"
+            args = ["a", "b"]
+
+            [env]
+            K = "v"
+
+            [stdio]
+            stdin = "null"
+            stderr = "fd:api"
+
+            [[sockets]]
+            name = "api"
+            addr = "127.0.0.1:0"
+            "#,
+        )
+        .unwrap();
+
+        config.validate().unwrap();
+        assert_eq!(config.args, vec!["a", "b"]);
+        assert_eq!(config.env["K"], "v");
+        assert_eq!(config.stdio.stdin, StdioMode::Null);
+        assert_eq!(config.stdio.stdout, StdioMode::Inherit);
+        assert_eq!(config.stdio.stderr, StdioMode::Fd("api".into()));
+        assert_eq!(config.sockets[0].kind, SocketKind::Listen);
+    }
+
+    #[test]
+    fn exec_args() {
+        let args = ["/init".to_string(), "-v".into(), "".into()];
+        let env = ["LANG=C".to_string()];
+        let bytes = super::exec_args(&args, &env).unwrap();
+        assert_eq!(bytes, b"\x03\0\0\0\0\0\0\0/init\0-v\0\0LANG=C\0");
+
+        assert!(super::exec_args(&[], &env).is_err());
+        assert!(super::exec_args(&["a\0b".into()], &[]).is_err());
+        assert!(super::exec_args(&["a".repeat(EXEC_ARGS_MAX)], &[]).is_err());
+    }
+
+    #[test]
+    fn unknown_key() {
+        assert!(toml::from_str::<Config>("argz = []").is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let config: Config = toml::from_str("[env]\n\"A=B\" = \"c\"").unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str(
+            r#"
+            [[files]]
+            name = "x"
+            path = "/dev/null"
+
+            [[sockets]]
+            name = "x"
+            addr = "127.0.0.1:0"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        assert!(toml::from_str::<Config>("[stdio]\nstdout = \"file\"").is_err());
+
+        let config: Config = toml::from_str("[stdio]\nstdout = \"fd:nope\"").unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str(
+            r#"
+            [stdio]
+            stdout = "fd:data"
+
+            [[files]]
+            name = "data"
+            path = "/dev/null"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn invoke() {
+        let config: Config = toml::from_str(
+            r#"
+            args = ["1", "2"]
+
+            [invoke]
+            function = "add"
+            results = "out"
+
+            [[files]]
+            name = "out"
+            path = "/dev/null"
+            writable = true
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let module = File::open("/dev/null").unwrap();
+        let (keep, handles) = config.open(module).unwrap();
+        let invoke = keep.invoke.as_ref().unwrap();
+        assert_eq!(invoke.function, "add");
+        assert_eq!(invoke.results_fd, handles[0].as_raw_fd());
+        keep.to_bytes().unwrap();
+
+        let config: Config = toml::from_str("[invoke]\nfunction = \"add\"").unwrap();
+        let (keep, _) = config.open(File::open("/dev/null").unwrap()).unwrap();
+        assert_eq!(keep.invoke.unwrap().results_fd, 1);
+
+        let config: Config =
+            toml::from_str("[invoke]\nfunction = \"add\"\nresults = \"nope\"").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn dirs() {
+        let config: Config = toml::from_str(
+            r#"
+            [[dirs]]
+            path = "/etc/app"
+            host = "/"
+
+            [[dirs]]
+            path = "/tmp"
+            writable = true
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let (keep, _handles) = config.open(File::open("/dev/null").unwrap()).unwrap();
+        assert!(keep.dirs[0].fd.is_some());
+        assert!(!keep.dirs[0].writable);
+        assert_eq!(keep.dirs[1].fd, None);
+        assert!(keep.dirs[1].writable);
+        assert_eq!(keep.raw_fds().count(), 2);
+        assert_eq!(
+            keep.host_dirs().collect::<Vec<_>>(),
+            [(keep.dirs[0].fd.unwrap(), false)]
+        );
+        keep.to_bytes().unwrap();
+
+        let config: Config = toml::from_str("[[dirs]]\npath = \"a:b\"").unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str("[[dirs]]\npath = \"/resources/\"").unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config =
+            toml::from_str("[[dirs]]\npath = \"/etc/app\"\nhost = \"/dev/null\"").unwrap();
+        assert!(config.open(File::open("/dev/null").unwrap()).is_err());
+    }
+
+    #[test]
+    fn stdio_roundtrip() {
+        for mode in ["inherit", "null", "fd:log"] {
+            let parsed = StdioMode::try_from(mode.to_string()).unwrap();
+            assert_eq!(String::from(parsed), mode);
+        }
+
+        // The workload would not see anything written to a pipe
+        assert!(StdioMode::try_from("pipe".to_string()).is_err());
+    }
+}
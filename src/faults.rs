@@ -0,0 +1,344 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fault injection, playing a malicious host
+//!
+//! With `--inject-faults` or `--fault-seed SEED`, `enarx` tampers with some
+//! of its replies to the keep: it fails syscalls, claims more bytes than
+//! requested, mutates the buffers of the keep, hands out bogus memory
+//! information and returns unexpected addresses for ballooned memory.
+//!
+//! After a fault, the next move of the keep thread tells how the shim took
+//! it: if the keep stops, the shim detected the fault; if the thread makes
+//! another request or the workload exits, the shim trusted the reply. Every
+//! outcome is logged as a warning and a summary is printed on exit.
+//!
+//! Errors and mutated data are within the rights of the host, so the shim
+//! may pass them on. Trusting an impossible length or bogus memory
+//! information is a finding.
+
+use std::cell::Cell;
+use std::fmt;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use primordial::Register;
+use sallyport::syscall::enarx::MemInfo;
+use sallyport::Block;
+use spinning::Lazy;
+
+/// One in this many replies is tampered with
+const FAULT_RATE: u64 = 16;
+
+/// The errors injected into syscalls
+const ERRORS: [libc::c_int; 4] = [libc::EIO, libc::EAGAIN, libc::ENOMEM, libc::EFAULT];
+
+/// The syscalls returning a byte count and the index of the argument it is limited by
+const COUNTED: [(i64, usize); 7] = [
+    (libc::SYS_read, 2),
+    (libc::SYS_write, 2),
+    (libc::SYS_pread64, 2),
+    (libc::SYS_pwrite64, 2),
+    (libc::SYS_recvfrom, 2),
+    (libc::SYS_sendto, 2),
+    (libc::SYS_getrandom, 1),
+];
+
+/// A tampered reply
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Fault {
+    /// The syscall failed with an error
+    Error(libc::c_int),
+
+    /// The syscall claims more bytes than requested
+    Oversized { requested: usize, claimed: usize },
+
+    /// A byte of a buffer in the block was flipped
+    Mutated { offset: usize },
+
+    /// The number of free memory slots is bogus
+    MemInfo { mem_slots: usize },
+
+    /// The ballooned memory is somewhere else than the host said
+    BalloonAddress { claimed: usize },
+}
+
+impl Fault {
+    /// Whether a shim trusting this fault is vulnerable
+    fn must_detect(&self) -> bool {
+        matches!(
+            self,
+            Self::Oversized { .. } | Self::MemInfo { .. } | Self::BalloonAddress { .. }
+        )
+    }
+}
+
+/// A fault injected into the reply to request `nr`
+#[derive(Clone, Debug)]
+struct Injected {
+    nr: i64,
+    fault: Fault,
+}
+
+impl fmt::Display for Injected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "hostcall {}: ", self.nr)?;
+        match self.fault {
+            Fault::Error(errno) => write!(f, "failed with errno {}", errno),
+            Fault::Oversized { requested, claimed } => {
+                write!(f, "claimed {} bytes of {} requested", claimed, requested)
+            }
+            Fault::Mutated { offset } => write!(f, "mutated the block at offset {:#x}", offset),
+            Fault::MemInfo { mem_slots } => write!(f, "claimed {} free memory slots", mem_slots),
+            Fault::BalloonAddress { claimed } => {
+                write!(f, "claimed the ballooned memory at {:#x}", claimed)
+            }
+        }
+    }
+}
+
+/// The state of the fault injection
+struct Injector {
+    seed: u64,
+    rng: u64,
+    injected: u64,
+    detected: u64,
+    trusted: u64,
+
+    /// Faults trusted, which the shim must detect
+    missed: u64,
+}
+
+impl Injector {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: seed,
+            injected: 0,
+            detected: 0,
+            trusted: 0,
+            missed: 0,
+        }
+    }
+
+    /// The next pseudo-random number (SplitMix64)
+    fn next(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Whether to tamper with the next reply
+    fn roll(&mut self) -> bool {
+        self.next() % FAULT_RATE == 0
+    }
+
+    /// A random index below `len`
+    fn pick(&mut self, len: usize) -> usize {
+        (self.next() % len as u64) as usize
+    }
+}
+
+/// Whether faults are injected
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+static INJECTOR: Lazy<Mutex<Option<Injector>>> = Lazy::new(Default::default);
+
+thread_local! {
+    /// The fault injected into the last reply to the keep thread on this host thread
+    static PENDING: Cell<Option<Injected>> = Cell::new(None);
+}
+
+/// Start injecting faults chosen by `seed`, or by a random seed
+pub fn start(seed: Option<u64>) {
+    let seed = seed.unwrap_or_else(|| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_nanos() as u64 ^ u64::from(std::process::id())
+    });
+
+    *INJECTOR.lock().unwrap() = Some(Injector::new(seed));
+    ACTIVE.store(true, Ordering::SeqCst);
+}
+
+/// Run `f` with the injector, if faults are injected
+///
+/// The fault pending for the calling thread is resolved as trusted first,
+/// as the keep thread made another request.
+fn inject(nr: i64, f: impl FnOnce(&mut Injector) -> Option<Fault>) {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return;
+    }
+
+    resolve(false);
+
+    let mut injector = INJECTOR.lock().unwrap();
+    let injector = injector.as_mut().unwrap();
+    if !injector.roll() {
+        return;
+    }
+
+    if let Some(fault) = f(injector) {
+        injector.injected += 1;
+        PENDING.with(|p| p.set(Some(Injected { nr, fault })));
+    }
+}
+
+/// Record how the shim took the fault pending for the calling thread, if any
+///
+/// `detected` tells whether the keep stopped.
+pub fn resolve(detected: bool) {
+    let injected = match PENDING.with(Cell::take) {
+        Some(injected) => injected,
+        None => return,
+    };
+
+    let mut injector = INJECTOR.lock().unwrap();
+    let injector = injector.as_mut().unwrap();
+
+    if detected {
+        injector.detected += 1;
+        warn!("fault injection: the shim detected {}", injected);
+    } else if injected.fault.must_detect() {
+        injector.trusted += 1;
+        injector.missed += 1;
+        warn!(
+            "fault injection: the shim trusted {}, which it must detect",
+            injected
+        );
+    } else {
+        injector.trusted += 1;
+        warn!("fault injection: the shim trusted {}", injected);
+    }
+}
+
+/// The range of host addresses of the data buffer of `block`
+fn buffer(block: &Block) -> std::ops::Range<usize> {
+    let end = block as *const Block as usize + size_of::<Block>();
+    end - Block::buf_capacity()..end
+}
+
+/// Maybe tamper with the reply `ret` to syscall `nr` in `block`
+pub fn syscall(nr: i64, block: &mut Block, ret: sallyport::Result) -> sallyport::Result {
+    let mut ret = ret;
+    let req = unsafe { block.msg.req };
+    let args: [usize; 6] = req.arg.map(|arg| arg.into());
+    let buffer = buffer(block);
+
+    inject(nr, |injector| {
+        let mut faults = vec![Fault::Error(ERRORS[injector.pick(ERRORS.len())])];
+
+        if let Some((_, count)) = COUNTED.iter().find(|(n, _)| *n == nr) {
+            if ret.is_ok() {
+                let requested = args[*count];
+                let claimed = requested.saturating_add(1 + injector.pick(4096));
+                faults.push(Fault::Oversized { requested, claimed });
+            }
+        }
+
+        let pointers: Vec<_> = args.iter().filter(|a| buffer.contains(*a)).collect();
+        if !pointers.is_empty() {
+            let addr = *pointers[injector.pick(pointers.len())];
+            let addr = addr + injector.pick((buffer.end - addr).min(64));
+            faults.push(Fault::Mutated {
+                offset: addr - buffer.start,
+            });
+        }
+
+        let fault = faults.swap_remove(injector.pick(faults.len()));
+        match fault {
+            Fault::Error(errno) => ret = Err(errno),
+            Fault::Oversized { claimed, .. } => ret = Ok([claimed.into(), 0.into()]),
+            Fault::Mutated { offset } => {
+                let flip = 1 + injector.pick(255) as u8;
+                unsafe { *((buffer.start + offset) as *mut u8) ^= flip };
+            }
+            _ => unreachable!(),
+        }
+
+        Some(fault)
+    });
+
+    ret
+}
+
+/// Maybe tamper with the memory information for the shim
+pub fn meminfo(info: &mut MemInfo) {
+    inject(sallyport::syscall::SYS_ENARX_MEM_INFO, |injector| {
+        let mem_slots = match injector.pick(3) {
+            0 => 0,
+            1 => usize::MAX,
+            _ => info.mem_slots.saturating_add(1 << 20),
+        };
+        info.mem_slots = mem_slots;
+        Some(Fault::MemInfo { mem_slots })
+    })
+}
+
+/// Maybe tamper with the address of ballooned memory in `rep`
+pub fn balloon(rep: &mut sallyport::Result) {
+    inject(sallyport::syscall::SYS_ENARX_BALLOON_MEMORY, |injector| {
+        let [addr, _] = rep.as_mut().ok()?;
+        let claimed = match injector.pick(3) {
+            0 => 0,
+            1 => usize::from(*addr).wrapping_add(4096),
+            _ => injector.next() as usize & !0xfff,
+        };
+        *addr = Register::from(claimed);
+        Some(Fault::BalloonAddress { claimed })
+    })
+}
+
+/// Print a summary of the injected faults
+///
+/// This is called right before `enarx` exits.
+pub fn finish() {
+    if !ACTIVE.load(Ordering::SeqCst) {
+        return;
+    }
+
+    // The keep stopped, so a fault pending now was detected
+    resolve(true);
+
+    if let Some(injector) = INJECTOR.lock().unwrap().as_ref() {
+        eprintln!(
+            "Fault injection with seed {}: {} faults injected, {} detected, {} trusted, {} of them must be detected",
+            injector.seed, injector.injected, injector.detected, injector.trusted, injector.missed
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        let mut a = Injector::new(42);
+        let mut b = Injector::new(42);
+        let mut c = Injector::new(43);
+
+        let a: Vec<_> = (0..8).map(|_| a.next()).collect();
+        let b: Vec<_> = (0..8).map(|_| b.next()).collect();
+        let c: Vec<_> = (0..8).map(|_| c.next()).collect();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn must_detect() {
+        assert!(!Fault::Error(libc::EIO).must_detect());
+        assert!(!Fault::Mutated { offset: 0 }.must_detect());
+        assert!(Fault::Oversized {
+            requested: 1,
+            claimed: 2
+        }
+        .must_detect());
+        assert!(Fault::MemInfo { mem_slots: 0 }.must_detect());
+    }
+}
@@ -0,0 +1,309 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Resource limits of a keep
+//!
+//! The memory limit is enforced by the backends: KVM based keeps fail to
+//! balloon beyond it with `ENOMEM`, while SGX enclaves, which cannot grow,
+//! must fit into it from the start. The time limits are enforced by a
+//! watchdog thread, which terminates `enarx` with [`KEEP_TIMEOUT_STATUS`].
+//! The CPU time of a keep includes its child process, if the backend runs
+//! the keep in one.
+//!
+//! ```toml
+//! [limits]
+//! memory_max = "512M"
+//! timeout = "30s"
+//! cpu_time = "10s"
+//! ```
+
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+/// The exit status of `enarx`, if the keep exceeded a time limit
+///
+/// This is the status `timeout(1)` uses.
+pub const KEEP_TIMEOUT_STATUS: i32 = 124;
+
+/// How often the watchdog checks the CPU time used
+const CPU_TIME_POLL: Duration = Duration::from_millis(100);
+
+/// The process running the keep, if it is a child process
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// The limits as written by the user
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// The maximum memory of the keep, e.g. `"512M"`
+    pub memory_max: Option<String>,
+
+    /// The maximum wall-clock time the keep may run, e.g. `"30s"`
+    pub timeout: Option<String>,
+
+    /// The maximum CPU time the keep may use, e.g. `"10s"`
+    pub cpu_time: Option<String>,
+}
+
+/// The resource limits of a keep
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum memory of the keep in bytes
+    pub memory_max: Option<usize>,
+
+    /// The maximum wall-clock time the keep may run
+    pub timeout: Option<Duration>,
+
+    /// The maximum CPU time the keep may use
+    pub cpu_time: Option<Duration>,
+}
+
+impl Limits {
+    /// Parse the limits of a configuration file
+    pub fn new(config: &LimitsConfig) -> Result<Self> {
+        Ok(Self {
+            memory_max: config
+                .memory_max
+                .as_deref()
+                .map(parse_size)
+                .transpose()
+                .context("invalid limits.memory_max")?,
+            timeout: config
+                .timeout
+                .as_deref()
+                .map(parse_duration)
+                .transpose()
+                .context("invalid limits.timeout")?,
+            cpu_time: config
+                .cpu_time
+                .as_deref()
+                .map(parse_duration)
+                .transpose()
+                .context("invalid limits.cpu_time")?,
+        })
+    }
+
+    /// Replace the limits with those set in `other`
+    pub fn overridden_by(self, other: Self) -> Self {
+        Self {
+            memory_max: other.memory_max.or(self.memory_max),
+            timeout: other.timeout.or(self.timeout),
+            cpu_time: other.cpu_time.or(self.cpu_time),
+        }
+    }
+
+    /// Start a watchdog thread enforcing the time limits
+    ///
+    /// If a limit is exceeded, the whole process exits with
+    /// [`KEEP_TIMEOUT_STATUS`], which tears down the keep.
+    pub fn watch(&self) {
+        if self.timeout.is_none() && self.cpu_time.is_none() {
+            return;
+        }
+
+        let limits = *self;
+        let start = Instant::now();
+
+        std::thread::spawn(move || loop {
+            if let Some(timeout) = limits.timeout {
+                if start.elapsed() >= timeout {
+                    timed_out(format!("exceeded the time limit of {:?}", timeout));
+                }
+            }
+
+            if let Some(cpu_time) = limits.cpu_time {
+                if process_cpu_time() + child_cpu_time() >= cpu_time {
+                    timed_out(format!("exceeded the CPU time limit of {:?}", cpu_time));
+                }
+            }
+
+            // The CPU time can only be polled, the wall-clock time is known
+            let nap = match (limits.timeout, limits.cpu_time) {
+                (Some(timeout), None) => timeout.saturating_sub(start.elapsed()),
+                (Some(timeout), Some(_)) => {
+                    CPU_TIME_POLL.min(timeout.saturating_sub(start.elapsed()))
+                }
+                (None, _) => CPU_TIME_POLL,
+            };
+            std::thread::sleep(nap);
+        });
+    }
+}
+
+/// Terminate `enarx` because the keep exceeded a time limit
+fn timed_out(reason: String) -> ! {
+    eprintln!("Error: the keep {}", reason);
+    crate::finish();
+    std::process::exit(KEEP_TIMEOUT_STATUS)
+}
+
+/// The CPU time used by all threads of this process, including the vCPUs
+fn process_cpu_time() -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut ts) };
+    Duration::new(ts.tv_sec as _, ts.tv_nsec as _)
+}
+
+/// Register the child process `pid` running the keep
+///
+/// The kernel accounts its CPU time to the child only, so the watchdog adds
+/// it to the CPU time of this process.
+pub fn register_child(pid: libc::pid_t) {
+    CHILD.store(pid, Ordering::SeqCst);
+}
+
+/// The CPU time used by the registered child process so far
+fn child_cpu_time() -> Duration {
+    match CHILD.load(Ordering::SeqCst) {
+        0 => Duration::ZERO,
+        pid => std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .ok()
+            .and_then(|stat| stat_cpu_time(&stat))
+            .unwrap_or_default(),
+    }
+}
+
+/// The user and system time of a `/proc/<pid>/stat` line
+fn stat_cpu_time(stat: &str) -> Option<Duration> {
+    // The command name may contain spaces, but ends with the last `)`
+    let fields = stat.get(stat.rfind(')')? + 1..)?;
+
+    // `utime` and `stime` are the fields 14 and 15, counting from the pid
+    let mut fields = fields.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+
+    let ticks = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => return None,
+    };
+
+    let ms = utime.saturating_add(stime).saturating_mul(1000) / ticks;
+    Some(Duration::from_millis(ms))
+}
+
+/// Parse a size in bytes with an optional binary suffix, e.g. `512M`
+pub fn parse_size(s: &str) -> Result<usize> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let shift = match c.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => bail!("unknown size suffix {:?} in {:?}", c, s),
+            };
+            (&s[..i], shift)
+        }
+        _ => (s, 0),
+    };
+
+    let value: usize = digits
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid size {:?}", s))?;
+
+    value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| anyhow!("size {:?} is too large", s))
+}
+
+/// Parse a duration with an optional unit (`ms`, `s`, `m` or `h`), e.g. `30s`
+///
+/// Without a unit, the value is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+
+    let value: u64 = digits
+        .parse()
+        .with_context(|| format!("invalid duration {:?}", s))?;
+
+    let duration = match unit.trim() {
+        "ms" => Duration::from_millis(value),
+        "" | "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value.saturating_mul(60)),
+        "h" => Duration::from_secs(value.saturating_mul(60 * 60)),
+        unit => bail!("unknown unit {:?} in duration {:?}", unit, s),
+    };
+
+    if duration.is_zero() {
+        bail!("duration {:?} is zero", s);
+    }
+
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("4k").unwrap(), 4096);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("2G").unwrap(), 2 << 30);
+        assert!(parse_size("2X").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("1d").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+
+    #[test]
+    fn stat() {
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+        let stat = format!(
+            "42 (a) b) S 1 42 42 0 -1 4194560 100 0 0 0 {} {} 0 0 20 0 1 0 1 0 0",
+            3 * ticks,
+            ticks / 2
+        );
+        assert_eq!(stat_cpu_time(&stat), Some(Duration::from_millis(3500)));
+        assert_eq!(stat_cpu_time("42 (a) S 1"), None);
+
+        let own = std::fs::read_to_string("/proc/self/stat").unwrap();
+        assert!(stat_cpu_time(&own).is_some());
+    }
+
+    #[test]
+    fn config() {
+        let config: LimitsConfig = toml::from_str(
+            r#"
+            memory_max = "1G"
+            timeout = "1m"
+            "#,
+        )
+        .unwrap();
+
+        let limits = Limits::new(&config).unwrap();
+        assert_eq!(limits.memory_max, Some(1 << 30));
+        assert_eq!(limits.timeout, Some(Duration::from_secs(60)));
+        assert_eq!(limits.cpu_time, None);
+
+        let cli = Limits {
+            timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let limits = limits.overridden_by(cli);
+        assert_eq!(limits.memory_max, Some(1 << 30));
+        assert_eq!(limits.timeout, Some(Duration::from_secs(5)));
+    }
+}
@@ -45,6 +45,39 @@
 //!
 //! If you want to suppress the debug output, add `2>/dev/null`.
 //!
+//! # Configure the workload
+//!
+//! Arguments, environment variables, stdio handling and preopened
+//! directories, files or sockets for the workload are read from a
+//! configuration file. A preopened directory is either a host directory, to
+//! which the policy confines the workload, or an in-memory directory, which
+//! never leaves the keep:
+//!
+//!     $ enarx run --config Enarx.toml target/wasm32-wasi/release/hello-world.wasm
+//!
+//! Instead of running the WASI entry point, `--invoke NAME` (or `function`
+//! in the `[invoke]` section) calls an exported function with the workload
+//! arguments, parsed according to its signature. The results are written as
+//! a JSON array to stdout or the file or socket named by `results`:
+//!
+//!     $ enarx run --config Enarx.toml --invoke add add.wasm
+//!     [{"type":"i32","value":3}]
+//!
+//! # Bundle resources
+//!
+//! Static assets can be shipped inside the module itself. The files of a
+//! directory packed into the module are read-only at `/resources` for the
+//! workload and covered by the digest of the module:
+//!
+//!     $ enarx bundle assets/ target/wasm32-wasi/release/hello-world.wasm
+//!
+//! # Reference measurements
+//!
+//! To print the launch measurement each compiled-in backend would produce for
+//! a workload (no TEE hardware required), run:
+//!
+//!     $ enarx measure --format json target/wasm32-wasi/release/hello-world.wasm
+//!
 //! # Select a Different Backend
 //!
 //! `enarx` will probe the machine it is running on in an attempt to deduce an
@@ -53,11 +86,113 @@
 //!
 //!     $ enarx info
 //!
+//! For tooling, `enarx info --format json` reports the same tests along with
+//! platform capabilities and the backend `enarx run` would choose, and why.
+//!
 //! You can manually select a backend with the `--backend` option, or by
 //! setting the `ENARX_BACKEND` environment variable:
 //!
 //!     $ enarx run --backend=sgx target/wasm32-wasi/release/hello-world.wasm
 //!     $ ENARX_BACKEND=sgx enarx run target/wasm32-wasi/release/hello-world.wasm
+//!
+//! The `nil` backend runs the workload as a normal process under `ptrace`,
+//! without any isolation from the host. It is never picked automatically and
+//! exists so that `enarx` and its integration tests can run on machines
+//! without KVM or a TEE:
+//!
+//!     $ ENARX_BACKEND=nil cargo test
+//!
+//! # Custom shims and workldrs
+//!
+//! To try a patched shim or workldr without rebuilding `enarx`, pass its path
+//! with `--shim` or `--workldr`. The shim has to note a sallyport version
+//! requirement that `enarx` satisfies:
+//!
+//!     $ enarx run --shim shim-sev --workldr wasmldr hello-world.wasm
+//!
+//! # SGX attestation
+//!
+//! On SGX, quotes are obtained from the quoting enclave via `aesmd`. If it
+//! listens on a socket other than `/var/run/aesmd/aesm.socket`, set the
+//! `ENARX_AESM_SOCKET` environment variable:
+//!
+//!     $ ENARX_AESM_SOCKET=/tmp/aesm.socket enarx run --backend=sgx hello-world.wasm
+//!
+//! # Syscall policy
+//!
+//! Syscalls the keep asks the host to execute are checked against a policy
+//! first. The syscalls, file descriptors, socket families and IP address
+//! ranges the keep may use can be restricted in the `[policy]` section of the
+//! configuration file. Denied syscalls fail with `EPERM` and are logged as
+//! warnings, which are shown with `-v`.
+//!
+//! # Exit status
+//!
+//! `enarx run` exits with the exit status of the workload, e.g. the status
+//! passed to WASI `proc_exit`. A trapping workload prints the trap and its
+//! wasm backtrace and exits with status 65. If the keep fails
+//! instead, e.g. because the shim crashed, the error is printed and `enarx`
+//! aborts, i.e. it is killed by `SIGABRT`. Workloads can exit with any
+//! status, but cannot send signals to `enarx`, so this tells both apart.
+//!
+//! # Managing keeps with a daemon
+//!
+//! `enarx serve` launches, lists, inspects and stops keeps on request. Its
+//! JSON API is served on a Unix socket, one request and one reply per line:
+//!
+//!     $ enarx serve --socket /tmp/enarx.sock &
+//!     $ echo '{"op": "launch", "module": "hello-world.wasm"}' | nc -U /tmp/enarx.sock
+//!     {"ok":{"id":1,…,"running":true,…}}
+//!
+//! # Resource limits
+//!
+//! The memory of a keep and the time it may run can be limited, either in
+//! the `[limits]` section of the configuration file or on the command line:
+//!
+//!     $ enarx run --memory-max 512M --timeout 30s --cpu-time 10s hello-world.wasm
+//!
+//! Beyond the memory limit, allocations of the workload fail with `ENOMEM`.
+//! A keep exceeding a time limit is killed and `enarx` exits with status 124.
+//!
+//! # Signals
+//!
+//! `enarx` forwards `SIGHUP`, `SIGINT` and `SIGTERM` to the workload, which
+//! sees them on its next syscall. WebAssembly workloads stop gracefully on
+//! `SIGTERM`. A signal the workload does not handle terminates it, and
+//! `enarx` exits with status 128 plus the signal number. If the same signal
+//! arrives again before the workload took it, `enarx` terminates right away.
+//!
+//! # Runtime metrics
+//!
+//! To see where a workload spends its time, `enarx` counts the syscalls the
+//! keep asks the host to execute and how long they take, along with CPUID
+//! exits, ballooned memory and the guest memory. A JSON summary is written
+//! on exit with `--metrics`, and `--metrics-socket` serves the metrics live
+//! for Prometheus:
+//!
+//!     $ enarx run --metrics metrics.json --metrics-socket /tmp/keep.sock hello-world.wasm
+//!     $ curl --unix-socket /tmp/keep.sock http://localhost/metrics
+//!
+//! # Record and replay
+//!
+//! To debug a keep failing intermittently, record its interaction with the
+//! host and replay it later, on any machine with the same backend. During
+//! the replay, the recorded replies are returned to the keep instead of
+//! executing its syscalls, and the first request differing from the
+//! recording is reported:
+//!
+//!     $ enarx run --record keep.rec hello-world.wasm
+//!     $ enarx run --replay keep.rec hello-world.wasm
+//!
+//! # Fault injection
+//!
+//! To check that the shims treat the host as untrusted, `enarx` can play a
+//! malicious host and tamper with some of its replies to the keep. It logs
+//! whether the shim detected each fault or trusted it, shown with `-v`, and
+//! prints a summary on exit. The seed in the summary reproduces the faults:
+//!
+//!     $ enarx run -v --inject-faults hello-world.wasm
+//!     $ enarx run -v --fault-seed 1234 hello-world.wasm
 
 #![deny(clippy::all)]
 #![deny(missing_docs)]
@@ -67,16 +202,29 @@
 
 mod backend;
 mod cli;
+mod config;
+mod faults;
+mod limits;
+mod metrics;
+mod policy;
 mod protobuf;
+mod replay;
+mod signal;
+mod socket;
 mod workldr;
 
 use backend::{Backend, Command};
+use config::{Config, InvokeConfig, SYS_ENARX_EXEC_ARGS, SYS_ENARX_KEEP_CONFIG};
+use limits::Limits;
+use policy::{Policy, PolicyConfig};
+use replay::Kind;
+use signal::SYS_ENARX_SIGNAL;
 
 use std::convert::TryInto;
 use std::fs::File;
-use std::os::unix::io::AsRawFd;
+use std::time::Instant;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use log::info;
 use structopt::StructOpt;
 
@@ -113,50 +261,185 @@ fn main() -> Result<()> {
             #[cfg(feature = "gdb")]
             let gdblisten = Some(exec.gdblisten);
 
-            keep_exec(backend, backend.shim(), binary, gdblisten)
+            let data = KeepData {
+                config: None,
+                args: exec.exec_args()?,
+                policy: Policy::new(&PolicyConfig::default(), None, None)?,
+            };
+            let limits = exec.limits.limits(Limits::default());
+            let shim = exec.backend.shim(backend)?;
+            exec.metrics.start()?;
+            exec.record.start(backend)?;
+            exec.faults.start();
+            let status = keep_exec(backend, shim, binary, &limits, data, gdblisten);
+            exit(status)
         }
         cli::Command::Run(run) => {
-            let modfile = File::open(run.module)?;
-            let open_fd = modfile.as_raw_fd();
-            // FIXME (v0.1.0 KEEP-CONFIG HACK): since we don't have any way to
-            // pass configuration or data into a keep yet, for v0.1.0 we've
-            // just hardcoded wasmldr to assume the module is open for reading
-            // on FD3. That *should* always be the case here (since nothing
-            // above opens files or anything), but if that assumption is wrong
-            // then things will break mysteriously later on. So this assert
-            // is just here to make them break earlier, and with less mystery.
-            assert!(open_fd == 3, "module got unexpected fd {}", open_fd);
-            // TODO: pass open_fd (or its contents) into the keep.
+            let mut config = match run.config {
+                Some(ref path) => Config::load(path)?,
+                None => Config::default(),
+            };
+            if let Some(function) = run.invoke.clone() {
+                let results = config.invoke.and_then(|invoke| invoke.results);
+                config.invoke = Some(InvokeConfig { function, results });
+                config.validate()?;
+            }
+            let limits = run.limits.limits(Limits::new(&config.limits)?);
+            let modfile = File::open(&run.module)?;
+
+            // The handles have to stay open until the keep is gone.
+            let (keep_config, handles) = config.open(modfile)?;
+            info!("keep config: {:?}", &keep_config);
+            let data = KeepData {
+                policy: Policy::new(
+                    &config.policy,
+                    keep_config.raw_fds(),
+                    keep_config.host_dirs(),
+                )?,
+                config: Some(keep_config.to_bytes()?),
+                args: config::exec_args(&["/init".into()], &["LANG=C".into()])?,
+            };
+
             let backend = run.backend.pick()?;
-            let workldr = run.workldr.pick()?;
+            let shim = run.backend.shim(backend)?;
+            let workldr = run.workldr.exec()?;
+            run.metrics.start()?;
+            run.record.start(backend)?;
+            run.faults.start();
             #[cfg(not(feature = "gdb"))]
             let gdblisten = None;
 
             #[cfg(feature = "gdb")]
             let gdblisten = Some(run.gdblisten);
 
-            keep_exec(backend, backend.shim(), workldr.exec(), gdblisten)
+            let status = keep_exec(backend, shim, workldr, &limits, data, gdblisten);
+            drop(handles);
+            exit(status)
         }
+        cli::Command::Measure(measure) => measure.display(),
+        cli::Command::Bundle(bundle) => bundle.run(),
+        cli::Command::Serve(serve) => serve.run(),
         #[cfg(feature = "backend-sev")]
         cli::Command::Sev(cmd) => cli::sev::run(cmd),
     }
 }
 
+/// Report what was collected about the keep, right before `enarx` exits
+fn finish() {
+    metrics::finish();
+    replay::finish();
+    faults::finish();
+}
+
+/// Exit with the workload's status, or abort, if the keep failed
+fn exit(status: Result<i32>) -> ! {
+    finish();
+
+    match status {
+        Ok(status) => std::process::exit(status),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+
+            // The failure is reported already, a core dump would not help
+            let none = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            unsafe { libc::setrlimit(libc::RLIMIT_CORE, &none) };
+            std::process::abort()
+        }
+    }
+}
+
+/// What the host provides to a keep while it runs
+struct KeepData {
+    /// The serialized keep configuration for the workldr
+    config: Option<Vec<u8>>,
+
+    /// The serialized arguments and environment of the exec
+    args: Vec<u8>,
+
+    /// The policy for proxied syscalls
+    policy: Policy,
+}
+
+/// Run a keep until the workload exits and return its exit status
+///
+/// The keep is torn down before returning. If it exceeds a time limit of
+/// `limits`, `enarx` exits right away.
 fn keep_exec(
     backend: &dyn Backend,
     shim: impl AsRef<[u8]>,
     exec: impl AsRef<[u8]>,
-    _gdblisten: Option<String>,
-) -> Result<()> {
-    let keep = backend.keep(shim.as_ref(), exec.as_ref())?;
-    let mut thread = keep.clone().spawn()?.unwrap();
+    limits: &Limits,
+    data: KeepData,
+    gdblisten: Option<String>,
+) -> Result<i32> {
+    let keep = backend.keep(shim.as_ref(), exec.as_ref(), limits.memory_max)?;
+    signal::install()?;
+    signal::register();
+    replay::register(0);
+    limits.watch();
+    let mut thread = keep.spawn()?.unwrap();
+
     loop {
-        match thread.enter()? {
-            Command::SysCall(block) => unsafe {
-                block.msg.rep = block.msg.req.syscall();
-            },
+        if let Some(status) = thread_step(thread.as_mut(), &data, gdblisten.as_ref())? {
+            return Ok(status);
+        }
+    }
+}
+
+/// Enter the keep on `thread` and handle the request it exits with
+///
+/// Returns the exit status of the workload, if it exited.
+fn thread_step(
+    thread: &mut dyn backend::Thread,
+    data: &KeepData,
+    _gdblisten: Option<&String>,
+) -> Result<Option<i32>> {
+    let command = match thread.enter() {
+        Ok(command) => command,
+        Err(e) => {
+            // A shim detecting an attack may crash the keep on purpose
+            faults::resolve(true);
+            return Err(e);
+        }
+    };
+
+    match command {
+        Command::SysCall(block) => {
+            faults::resolve(false);
+
+            let nr = i64::from(unsafe { block.msg.req.num });
+            let start = Instant::now();
+
+            replay::hostcall(Kind::Proxied, block, |block| unsafe {
+                block.msg.rep = match nr {
+                    SYS_ENARX_KEEP_CONFIG => config::deliver(data.config.as_deref(), block).into(),
+                    SYS_ENARX_EXEC_ARGS => config::deliver(Some(&data.args), block).into(),
+                    SYS_ENARX_SIGNAL => {
+                        sallyport::Result::Ok([signal::take().into(), 0.into()]).into()
+                    }
+                    nr if signal::interrupts(nr) => sallyport::Result::Err(libc::EINTR).into(),
+                    _ => match data.policy.check(block) {
+                        Ok(()) => {
+                            let ret: sallyport::Result = block.msg.req.syscall().into();
+                            data.policy.record(&block.msg.req, &ret);
+                            faults::syscall(nr, block, ret).into()
+                        }
+                        Err(e) => sallyport::Result::Err(e).into(),
+                    },
+                };
+            })?;
+
+            metrics::hostcall(nr, start.elapsed());
+        }
 
-            Command::CpuId(block) => unsafe {
+        Command::CpuId(block) => {
+            faults::resolve(false);
+            metrics::cpuid();
+
+            replay::hostcall(Kind::CpuId, block, |block| unsafe {
                 let cpuid = core::arch::x86_64::__cpuid_count(
                     block.msg.req.arg[0].try_into().unwrap(),
                     block.msg.req.arg[1].try_into().unwrap(),
@@ -166,14 +449,28 @@ fn keep_exec(
                 block.msg.req.arg[1] = cpuid.ebx.into();
                 block.msg.req.arg[2] = cpuid.ecx.into();
                 block.msg.req.arg[3] = cpuid.edx.into();
-            },
+            })?;
+        }
 
-            #[cfg(feature = "gdb")]
-            Command::Gdb(block, gdb_fd) => {
-                backend::handle_gdb(block, gdb_fd, _gdblisten.as_ref().unwrap());
-            }
+        #[cfg(feature = "gdb")]
+        Command::Gdb(block, gdb_fd) => {
+            backend::handle_gdb(block, gdb_fd, _gdblisten.unwrap());
+        }
 
-            Command::Continue => (),
+        Command::Continue => (),
+
+        // The shims run a single thread, so the keep ends with it, like a
+        // process with its last thread.
+        Command::ThreadExit(status) | Command::Exit(status) => {
+            faults::resolve(false);
+            return Ok(Some(status));
+        }
+
+        Command::ShimExit(status) => {
+            faults::resolve(true);
+            bail!("the shim terminated the keep with status {}", status)
         }
     }
+
+    Ok(None)
 }
@@ -0,0 +1,444 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runtime metrics of a keep
+//!
+//! Every request a keep thread exits with is counted per syscall number,
+//! along with a histogram of how long the host took to handle it. Besides
+//! that, CPUID exits, ballooning and the guest memory are recorded.
+//!
+//! With `--metrics PATH`, a JSON summary is written to `PATH` when `enarx`
+//! exits. With `--metrics-socket PATH`, the metrics are served live in the
+//! Prometheus text format over HTTP on the Unix socket `PATH`.
+
+use crate::socket;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use spinning::Lazy;
+
+/// The upper bounds of the latency histogram buckets in microseconds
+///
+/// A last bucket counts everything slower.
+const BUCKETS: [u64; 16] = [
+    1, 2, 5, 10, 20, 50, 100, 200, 500, 1_000, 2_000, 5_000, 10_000, 100_000, 1_000_000, 10_000_000,
+];
+
+/// How long a client of the socket may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The maximum size of a request to the socket
+const REQUEST_MAX: usize = 8192;
+
+/// A latency histogram
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Histogram {
+    /// The number of samples per bucket
+    counts: [u64; BUCKETS.len() + 1],
+
+    /// The sum of all samples
+    sum: Duration,
+}
+
+impl Histogram {
+    fn record(&mut self, elapsed: Duration) {
+        let us = elapsed.as_micros();
+        let bucket = BUCKETS
+            .iter()
+            .position(|&bound| us <= bound.into())
+            .unwrap_or(BUCKETS.len());
+
+        self.counts[bucket] += 1;
+        self.sum += elapsed;
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The upper bounds of the buckets in seconds and their cumulative counts
+    fn cumulative(&self) -> impl Iterator<Item = (Option<f64>, u64)> + '_ {
+        let bounds = BUCKETS
+            .iter()
+            .map(|&us| Some(us as f64 / 1e6))
+            .chain(std::iter::once(None));
+
+        bounds.zip(self.counts.iter().scan(0, |total, count| {
+            *total += count;
+            Some(*total)
+        }))
+    }
+}
+
+/// The metrics of the keep run by this process
+#[derive(Debug)]
+pub struct Metrics {
+    start: Instant,
+
+    /// The latency histograms per syscall number
+    hostcalls: Mutex<BTreeMap<i64, Histogram>>,
+
+    cpuid: AtomicU64,
+    balloon: AtomicU64,
+    balloon_bytes: AtomicU64,
+    deflate: AtomicU64,
+    deflate_bytes: AtomicU64,
+    meminfo: AtomicU64,
+
+    /// The current guest memory in bytes
+    memory: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            hostcalls: Default::default(),
+            cpuid: Default::default(),
+            balloon: Default::default(),
+            balloon_bytes: Default::default(),
+            deflate: Default::default(),
+            deflate_bytes: Default::default(),
+            meminfo: Default::default(),
+            memory: Default::default(),
+        }
+    }
+}
+
+impl Metrics {
+    /// Record that the host handled syscall `nr` in `elapsed`
+    pub fn hostcall(&self, nr: i64, elapsed: Duration) {
+        self.hostcalls
+            .lock()
+            .unwrap()
+            .entry(nr)
+            .or_default()
+            .record(elapsed);
+    }
+
+    /// Record a CPUID exit
+    pub fn cpuid(&self) {
+        self.cpuid.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that `bytes` of memory were ballooned into the guest
+    pub fn balloon(&self, bytes: usize) {
+        self.balloon.fetch_add(1, Ordering::Relaxed);
+        self.balloon_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Record that the guest returned `bytes` of memory
+    pub fn deflate(&self, bytes: usize) {
+        self.deflate.fetch_add(1, Ordering::Relaxed);
+        self.deflate_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Record a memory information request of the shim
+    pub fn meminfo(&self) {
+        self.meminfo.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set the current guest memory to `bytes`
+    pub fn memory(&self, bytes: usize) {
+        self.memory.store(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// The metrics as a JSON summary
+    pub fn summary(&self) -> Value {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let hostcalls: Vec<_> = self
+            .hostcalls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(nr, histogram)| {
+                let buckets: Vec<_> = histogram
+                    .cumulative()
+                    .map(|(le, count)| json!({ "le": le, "count": count }))
+                    .collect();
+
+                json!({
+                    "nr": nr,
+                    "count": histogram.count(),
+                    "seconds": histogram.sum.as_secs_f64(),
+                    "buckets": buckets,
+                })
+            })
+            .collect();
+
+        json!({
+            "uptime_seconds": self.start.elapsed().as_secs_f64(),
+            "memory_bytes": load(&self.memory),
+            "cpuid_exits": load(&self.cpuid),
+            "balloon": {
+                "events": load(&self.balloon),
+                "bytes": load(&self.balloon_bytes),
+            },
+            "deflate": {
+                "events": load(&self.deflate),
+                "bytes": load(&self.deflate_bytes),
+            },
+            "meminfo_exits": load(&self.meminfo),
+            "hostcalls": hostcalls,
+        })
+    }
+
+    /// The metrics in the Prometheus text format
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        };
+
+        metric(
+            "enarx_keep_uptime_seconds",
+            "gauge",
+            "Time since the keep was started.",
+            self.start.elapsed().as_secs_f64().to_string(),
+        );
+        metric(
+            "enarx_keep_memory_bytes",
+            "gauge",
+            "Memory of the guest.",
+            load(&self.memory).to_string(),
+        );
+        metric(
+            "enarx_keep_cpuid_exits_total",
+            "counter",
+            "CPUID instructions handled by the host.",
+            load(&self.cpuid).to_string(),
+        );
+        metric(
+            "enarx_keep_balloon_events_total",
+            "counter",
+            "Memory regions ballooned into the guest.",
+            load(&self.balloon).to_string(),
+        );
+        metric(
+            "enarx_keep_balloon_bytes_total",
+            "counter",
+            "Memory ballooned into the guest.",
+            load(&self.balloon_bytes).to_string(),
+        );
+        metric(
+            "enarx_keep_deflate_events_total",
+            "counter",
+            "Memory regions returned by the guest.",
+            load(&self.deflate).to_string(),
+        );
+        metric(
+            "enarx_keep_deflate_bytes_total",
+            "counter",
+            "Memory returned by the guest.",
+            load(&self.deflate_bytes).to_string(),
+        );
+        metric(
+            "enarx_keep_meminfo_exits_total",
+            "counter",
+            "Memory information requests of the shim.",
+            load(&self.meminfo).to_string(),
+        );
+
+        let name = "enarx_keep_hostcall_duration_seconds";
+        writeln!(out, "# HELP {} Time the host took per syscall.", name).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        for (nr, histogram) in self.hostcalls.lock().unwrap().iter() {
+            for (le, count) in histogram.cumulative() {
+                let le = le.map_or_else(|| "+Inf".to_string(), |le| le.to_string());
+                writeln!(
+                    out,
+                    "{}_bucket{{nr=\"{}\",le=\"{}\"}} {}",
+                    name, nr, le, count
+                )
+                .unwrap();
+            }
+            let sum = histogram.sum.as_secs_f64();
+            writeln!(out, "{}_sum{{nr=\"{}\"}} {}", name, nr, sum).unwrap();
+            writeln!(out, "{}_count{{nr=\"{}\"}} {}", name, nr, histogram.count()).unwrap();
+        }
+
+        out
+    }
+}
+
+/// The metrics of the keep run by this process
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+/// Where the metrics go
+#[derive(Debug, Default)]
+struct Outputs {
+    summary: Option<PathBuf>,
+    socket: Option<PathBuf>,
+}
+
+static OUTPUTS: Lazy<Mutex<Outputs>> = Lazy::new(Default::default);
+
+/// Record that the host handled syscall `nr` in `elapsed`
+pub fn hostcall(nr: i64, elapsed: Duration) {
+    METRICS.hostcall(nr, elapsed)
+}
+
+/// Record a CPUID exit
+pub fn cpuid() {
+    METRICS.cpuid()
+}
+
+/// Record that `bytes` of memory were ballooned into the guest
+pub fn balloon(bytes: usize) {
+    METRICS.balloon(bytes)
+}
+
+/// Record that the guest returned `bytes` of memory
+pub fn deflate(bytes: usize) {
+    METRICS.deflate(bytes)
+}
+
+/// Record a memory information request of the shim
+pub fn meminfo() {
+    METRICS.meminfo()
+}
+
+/// Set the current guest memory to `bytes`
+pub fn memory(bytes: usize) {
+    METRICS.memory(bytes)
+}
+
+/// Start collecting metrics
+///
+/// The JSON summary is written to `summary` by [`finish`]. If `socket` is
+/// set, the metrics are served on it until then.
+pub fn start(summary: Option<PathBuf>, socket: Option<&Path>) -> Result<()> {
+    Lazy::force(&METRICS);
+
+    let mut outputs = OUTPUTS.lock().unwrap();
+    outputs.summary = summary;
+
+    if let Some(path) = socket {
+        // The metrics reveal what the workload is doing
+        let listener = socket::bind(path)?;
+        outputs.socket = Some(path.to_owned());
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = respond(stream);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Write the JSON summary and stop serving the metrics
+///
+/// This is called right before `enarx` exits.
+pub fn finish() {
+    let mut outputs = OUTPUTS.lock().unwrap();
+
+    if let Some(path) = outputs.socket.take() {
+        let _ = std::fs::remove_file(path);
+    }
+
+    if let Some(path) = outputs.summary.take() {
+        let summary = METRICS.summary().to_string() + "\n";
+        if let Err(e) = std::fs::write(&path, summary) {
+            eprintln!("Error: failed to write the metrics to {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Answer a scrape with the metrics, whatever the request
+fn respond(mut stream: UnixStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    // Read the request head, if any, so the client sees no reset
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while request.len() < REQUEST_MAX && !request.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    let body = METRICS.prometheus();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = Histogram::default();
+        histogram.record(Duration::from_nanos(500));
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_secs(60));
+
+        assert_eq!(histogram.count(), 3);
+        assert_eq!(histogram.counts[0], 1);
+        assert_eq!(histogram.counts[2], 1);
+        assert_eq!(histogram.counts[BUCKETS.len()], 1);
+
+        let cumulative: Vec<_> = histogram.cumulative().collect();
+        assert_eq!(cumulative.len(), BUCKETS.len() + 1);
+        assert_eq!(cumulative[0], (Some(1e-6), 1));
+        assert_eq!(cumulative[1], (Some(2e-6), 1));
+        assert_eq!(cumulative[2], (Some(5e-6), 2));
+        assert_eq!(cumulative[BUCKETS.len()], (None, 3));
+    }
+
+    #[test]
+    fn export() {
+        let metrics = Metrics::default();
+        metrics.hostcall(libc::SYS_read, Duration::from_micros(7));
+        metrics.hostcall(libc::SYS_read, Duration::from_micros(70));
+        metrics.hostcall(libc::SYS_write, Duration::from_micros(7));
+        metrics.balloon(4096);
+        metrics.balloon(8192);
+        metrics.memory(1 << 20);
+        metrics.cpuid();
+
+        let summary = metrics.summary();
+        assert_eq!(summary["memory_bytes"], 1 << 20);
+        assert_eq!(summary["cpuid_exits"], 1);
+        assert_eq!(summary["balloon"]["events"], 2);
+        assert_eq!(summary["balloon"]["bytes"], 12288);
+        assert_eq!(summary["hostcalls"][0]["nr"], libc::SYS_read);
+        assert_eq!(summary["hostcalls"][0]["count"], 2);
+        assert_eq!(summary["hostcalls"][1]["nr"], libc::SYS_write);
+
+        let text = metrics.prometheus();
+        assert!(text.contains("enarx_keep_memory_bytes 1048576\n"));
+        assert!(text.contains("enarx_keep_balloon_bytes_total 12288\n"));
+        assert!(text.contains("enarx_keep_hostcall_duration_seconds_count{nr=\"0\"} 2\n"));
+        assert!(
+            text.contains("enarx_keep_hostcall_duration_seconds_bucket{nr=\"0\",le=\"+Inf\"} 2\n")
+        );
+    }
+}
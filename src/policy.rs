@@ -0,0 +1,757 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The host-side policy for syscalls proxied out of the keep
+//!
+//! Every syscall the keep asks the host to execute is checked against the
+//! [`Policy`] before it is proxied. A compromised workload can only use the
+//! syscalls, file descriptors, socket families and addresses the policy
+//! allows. Denied calls fail with `EPERM` and are logged as warnings.
+//!
+//! The policy is set in the `[policy]` section of the keep configuration.
+//! All keys are optional and default to what the shims need:
+//!
+//! ```toml
+//! [policy]
+//! syscalls = ["read", "write", "close", "socket", "connect"]
+//! fds = [3]
+//! socket_families = ["inet", "inet6"]
+//! addresses = ["127.0.0.0/8", "::1"]
+//! ```
+//!
+//! The standard I/O streams, the module and all preopened files and sockets
+//! are always allowed, as are file descriptors the keep has created itself.
+//!
+//! Files in preopened host directories are only opened relative to the
+//! directory, by paths that stay beneath it: absolute paths, `..` and
+//! symlinks are denied. Nothing in a directory that is not writable can be
+//! created, modified, renamed or removed.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::ffi::{CString, OsStr};
+use std::mem::size_of;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use primordial::Register;
+use sallyport::{Block, Request};
+use serde::Deserialize;
+
+/// The syscalls the shims proxy to the host
+///
+/// A policy can only narrow this list down.
+const SYSCALLS: &[(&str, libc::c_long)] = &[
+    ("read", libc::SYS_read),
+    ("write", libc::SYS_write),
+    ("pread64", libc::SYS_pread64),
+    ("readv", libc::SYS_readv),
+    ("writev", libc::SYS_writev),
+    ("close", libc::SYS_close),
+    ("fstat", libc::SYS_fstat),
+    ("openat", libc::SYS_openat),
+    ("newfstatat", libc::SYS_newfstatat),
+    ("getdents64", libc::SYS_getdents64),
+    ("readlinkat", libc::SYS_readlinkat),
+    ("mkdirat", libc::SYS_mkdirat),
+    ("unlinkat", libc::SYS_unlinkat),
+    ("renameat", libc::SYS_renameat),
+    ("fcntl", libc::SYS_fcntl),
+    ("ioctl", libc::SYS_ioctl),
+    ("poll", libc::SYS_poll),
+    ("dup", libc::SYS_dup),
+    ("dup2", libc::SYS_dup2),
+    ("dup3", libc::SYS_dup3),
+    ("eventfd2", libc::SYS_eventfd2),
+    ("epoll_create1", libc::SYS_epoll_create1),
+    ("epoll_ctl", libc::SYS_epoll_ctl),
+    ("epoll_wait", libc::SYS_epoll_wait),
+    ("epoll_pwait", libc::SYS_epoll_pwait),
+    ("socket", libc::SYS_socket),
+    ("bind", libc::SYS_bind),
+    ("listen", libc::SYS_listen),
+    ("connect", libc::SYS_connect),
+    ("accept", libc::SYS_accept),
+    ("accept4", libc::SYS_accept4),
+    ("getsockname", libc::SYS_getsockname),
+    ("recvfrom", libc::SYS_recvfrom),
+    ("sendto", libc::SYS_sendto),
+    ("setsockopt", libc::SYS_setsockopt),
+    ("clock_gettime", libc::SYS_clock_gettime),
+    ("nanosleep", libc::SYS_nanosleep),
+    ("exit", libc::SYS_exit),
+    ("exit_group", libc::SYS_exit_group),
+];
+
+/// The socket families a policy can allow
+const FAMILIES: &[(&str, libc::c_int)] = &[
+    ("unix", libc::AF_UNIX),
+    ("inet", libc::AF_INET),
+    ("inet6", libc::AF_INET6),
+    ("netlink", libc::AF_NETLINK),
+];
+
+/// The socket families allowed by default
+const DEFAULT_FAMILIES: &[&str] = &["unix", "inet", "inet6"];
+
+/// The `openat` flags that create or modify a file
+const WRITE_FLAGS: libc::c_int =
+    libc::O_WRONLY | libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC | libc::O_APPEND;
+
+/// The file descriptors of the standard I/O streams
+const STDIO_FDS: &[RawFd] = &[libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO];
+
+/// The policy as written by the user
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyConfig {
+    /// Names of the syscalls the keep may use (default: all the shims proxy)
+    pub syscalls: Option<Vec<String>>,
+
+    /// Additional host file descriptors the keep may use
+    #[serde(default)]
+    pub fds: Vec<RawFd>,
+
+    /// Socket families the keep may create sockets of
+    pub socket_families: Option<Vec<String>>,
+
+    /// IP address ranges the keep may bind to, connect to or send to (default: any)
+    pub addresses: Option<Vec<String>>,
+}
+
+/// A range of IP addresses in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr
+            .parse()
+            .with_context(|| format!("invalid address range {:?}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            None => max,
+            Some(p) => p
+                .parse()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| anyhow!("invalid prefix length in address range {:?}", s))?,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl IpRange {
+    /// Check whether `addr` is part of the range
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The name of syscall `num` for log messages
+fn name(num: libc::c_long) -> &'static str {
+    SYSCALLS
+        .iter()
+        .find(|(_, n)| *n == num)
+        .map(|(name, _)| *name)
+        .unwrap_or("unknown")
+}
+
+fn arg(req: &Request, idx: usize) -> usize {
+    req.arg[idx].into()
+}
+
+/// The policy enforced on all syscalls proxied for a keep
+#[derive(Debug)]
+pub struct Policy {
+    syscalls: HashSet<libc::c_long>,
+    families: HashSet<libc::c_int>,
+    addresses: Option<Vec<IpRange>>,
+
+    /// The host file descriptors the keep may use; grows and shrinks with
+    /// the file descriptors the keep opens and closes
+    fds: Mutex<HashSet<RawFd>>,
+
+    /// The host directories the keep may open files beneath and whether it
+    /// may modify them; grows with the directories opened beneath them
+    dirs: Mutex<HashMap<RawFd, bool>>,
+}
+
+impl Policy {
+    /// Create the policy from its configuration
+    ///
+    /// `fds` are the host file descriptors handed to the keep and `dirs` the
+    /// host directories among them, with whether they are writable.
+    pub fn new(
+        config: &PolicyConfig,
+        fds: impl IntoIterator<Item = RawFd>,
+        dirs: impl IntoIterator<Item = (RawFd, bool)>,
+    ) -> Result<Self> {
+        let syscalls = match config.syscalls {
+            None => SYSCALLS.iter().map(|(_, num)| *num).collect(),
+            Some(ref names) => names
+                .iter()
+                .map(|name| {
+                    SYSCALLS
+                        .iter()
+                        .find(|(n, _)| n == name)
+                        .map(|(_, num)| *num)
+                        .ok_or_else(|| anyhow!("unknown or unsupported syscall {:?}", name))
+                })
+                .collect::<Result<_>>()?,
+        };
+
+        let families = match config.socket_families {
+            None => DEFAULT_FAMILIES.iter().map(|f| f.to_string()).collect(),
+            Some(ref families) => families.clone(),
+        };
+        let families = families
+            .iter()
+            .map(|name| {
+                FAMILIES
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, af)| *af)
+                    .ok_or_else(|| anyhow!("unknown or unsupported socket family {:?}", name))
+            })
+            .collect::<Result<_>>()?;
+
+        let addresses = match config.addresses {
+            None => None,
+            Some(ref ranges) => Some(
+                ranges
+                    .iter()
+                    .map(|r| r.parse())
+                    .collect::<Result<Vec<IpRange>>>()?,
+            ),
+        };
+
+        for fd in &config.fds {
+            if *fd < 0 {
+                bail!("invalid file descriptor {} in policy", fd);
+            }
+        }
+
+        let fds = STDIO_FDS
+            .iter()
+            .copied()
+            .chain(config.fds.iter().copied())
+            .chain(fds)
+            .collect();
+
+        Ok(Self {
+            syscalls,
+            families,
+            addresses,
+            fds: Mutex::new(fds),
+            dirs: Mutex::new(dirs.into_iter().collect()),
+        })
+    }
+
+    fn deny(&self, num: libc::c_long, why: impl std::fmt::Display) -> i32 {
+        warn!("policy: denied syscall {} ({}): {}", name(num), num, why);
+        libc::EPERM
+    }
+
+    fn check_fd(&self, num: libc::c_long, fd: usize) -> Result<(), i32> {
+        // The kernel only looks at the lower 32 bits
+        let fd = fd as u32 as RawFd;
+        if !self.fds.lock().unwrap().contains(&fd) {
+            return Err(self.deny(num, format_args!("file descriptor {} is not allowed", fd)));
+        }
+
+        Ok(())
+    }
+
+    /// Get `len` bytes at the host address `ptr`, if they are part of `block`
+    fn in_block<'a>(
+        &self,
+        num: libc::c_long,
+        block: &'a Block,
+        ptr: usize,
+        len: usize,
+    ) -> Result<&'a [u8], i32> {
+        let start = block as *const Block as usize;
+        let end = start + size_of::<Block>();
+
+        match ptr.checked_add(len) {
+            Some(last) if ptr >= start && last <= end => {
+                Ok(unsafe { std::slice::from_raw_parts(ptr as *const u8, len) })
+            }
+            _ => Err(self.deny(
+                num,
+                format_args!("{:#x} is outside the sallyport block", ptr),
+            )),
+        }
+    }
+
+    /// Check the path at `ptr`, which is opened relative to the directory `dirfd`
+    ///
+    /// Only the thread of the keep proxies syscalls, so nothing can swap a
+    /// checked path component for a symlink before the syscall is executed.
+    fn check_path(
+        &self,
+        num: libc::c_long,
+        block: &Block,
+        dirfd: usize,
+        ptr: usize,
+        write: bool,
+    ) -> Result<(), i32> {
+        let dirfd = dirfd as u32 as RawFd;
+        match self.dirs.lock().unwrap().get(&dirfd) {
+            None => {
+                return Err(self.deny(num, format_args!("{} is not a preopened directory", dirfd)))
+            }
+            Some(false) if write => {
+                return Err(self.deny(num, format_args!("directory {} is read-only", dirfd)))
+            }
+            Some(_) => (),
+        }
+
+        let end = block as *const Block as usize + size_of::<Block>();
+        let bytes = self.in_block(num, block, ptr, end.saturating_sub(ptr))?;
+        let len = bytes
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| self.deny(num, "path is not terminated"))?;
+        let path = Path::new(OsStr::from_bytes(&bytes[..len]));
+
+        if path.as_os_str().is_empty()
+            || !path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(self.deny(num, format_args!("path {:?} leaves the directory", path)));
+        }
+
+        let mut prefix = PathBuf::new();
+        for component in path.components() {
+            prefix.push(component);
+            let c = CString::new(prefix.as_os_str().as_bytes()).unwrap();
+            let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+            let ret =
+                unsafe { libc::fstatat(dirfd, c.as_ptr(), &mut st, libc::AT_SYMLINK_NOFOLLOW) };
+            if ret != 0 {
+                // The rest of the path does not exist (yet)
+                break;
+            }
+            if st.st_mode & libc::S_IFMT == libc::S_IFLNK {
+                return Err(self.deny(num, format_args!("path {:?} is a symlink", prefix)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_sockaddr(
+        &self,
+        num: libc::c_long,
+        block: &Block,
+        ptr: usize,
+        len: usize,
+    ) -> Result<(), i32> {
+        let addresses = match self.addresses {
+            None => return Ok(()),
+            Some(ref addresses) => addresses,
+        };
+
+        let bytes = self.in_block(num, block, ptr, len)?;
+        let family = match bytes.get(..2) {
+            Some(f) => u16::from_ne_bytes(f.try_into().unwrap()) as libc::c_int,
+            None => return Err(libc::EINVAL),
+        };
+
+        let addr = match family {
+            libc::AF_INET if len >= size_of::<libc::sockaddr_in>() => {
+                let sa = unsafe { (ptr as *const libc::sockaddr_in).read_unaligned() };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(sa.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 if len >= size_of::<libc::sockaddr_in6>() => {
+                let sa = unsafe { (ptr as *const libc::sockaddr_in6).read_unaligned() };
+                IpAddr::V6(Ipv6Addr::from(sa.sin6_addr.s6_addr))
+            }
+            libc::AF_INET | libc::AF_INET6 => return Err(libc::EINVAL),
+            _ => return Ok(()),
+        };
+
+        if !addresses.iter().any(|r| r.contains(addr)) {
+            return Err(self.deny(num, format_args!("address {} is not allowed", addr)));
+        }
+
+        Ok(())
+    }
+
+    /// Check the request in `block` before it is proxied to the host
+    pub fn check(&self, block: &Block) -> Result<(), i32> {
+        let req = unsafe { block.msg.req };
+        let num = i64::from(req.num);
+
+        if !self.syscalls.contains(&num) {
+            return Err(self.deny(num, "syscall is not allowed"));
+        }
+
+        match num {
+            libc::SYS_socket => {
+                let family = arg(&req, 0) as libc::c_int;
+                if !self.families.contains(&family) {
+                    return Err(self.deny(num, format_args!("socket family {}", family)));
+                }
+            }
+
+            libc::SYS_bind | libc::SYS_connect => {
+                self.check_fd(num, arg(&req, 0))?;
+                self.check_sockaddr(num, block, arg(&req, 1), arg(&req, 2))?;
+            }
+
+            libc::SYS_sendto => {
+                self.check_fd(num, arg(&req, 0))?;
+                if arg(&req, 4) != 0 {
+                    self.check_sockaddr(num, block, arg(&req, 4), arg(&req, 5))?;
+                }
+            }
+
+            libc::SYS_epoll_ctl => {
+                self.check_fd(num, arg(&req, 0))?;
+                self.check_fd(num, arg(&req, 2))?;
+            }
+
+            // Duplicating onto an arbitrary host file descriptor would replace it
+            libc::SYS_dup2 | libc::SYS_dup3 => {
+                self.check_fd(num, arg(&req, 0))?;
+                self.check_fd(num, arg(&req, 1))?;
+            }
+
+            libc::SYS_openat => {
+                let write = arg(&req, 2) as libc::c_int & WRITE_FLAGS != 0;
+                self.check_path(num, block, arg(&req, 0), arg(&req, 1), write)?;
+            }
+
+            libc::SYS_newfstatat | libc::SYS_readlinkat => {
+                self.check_path(num, block, arg(&req, 0), arg(&req, 1), false)?;
+            }
+
+            libc::SYS_mkdirat | libc::SYS_unlinkat => {
+                self.check_path(num, block, arg(&req, 0), arg(&req, 1), true)?;
+            }
+
+            libc::SYS_renameat => {
+                self.check_path(num, block, arg(&req, 0), arg(&req, 1), true)?;
+                self.check_path(num, block, arg(&req, 2), arg(&req, 3), true)?;
+            }
+
+            libc::SYS_poll => {
+                let len = arg(&req, 1)
+                    .checked_mul(size_of::<libc::pollfd>())
+                    .ok_or(libc::EINVAL)?;
+                self.in_block(num, block, arg(&req, 0), len)?;
+
+                let fds = arg(&req, 0) as *const libc::pollfd;
+                for i in 0..arg(&req, 1) {
+                    let pfd = unsafe { fds.add(i).read_unaligned() };
+                    // Negative file descriptors are ignored by the kernel
+                    if pfd.fd >= 0 {
+                        self.check_fd(num, pfd.fd as usize)?;
+                    }
+                }
+            }
+
+            libc::SYS_read
+            | libc::SYS_write
+            | libc::SYS_pread64
+            | libc::SYS_readv
+            | libc::SYS_writev
+            | libc::SYS_close
+            | libc::SYS_fstat
+            | libc::SYS_getdents64
+            | libc::SYS_fcntl
+            | libc::SYS_ioctl
+            | libc::SYS_dup
+            | libc::SYS_epoll_wait
+            | libc::SYS_epoll_pwait
+            | libc::SYS_listen
+            | libc::SYS_accept
+            | libc::SYS_accept4
+            | libc::SYS_getsockname
+            | libc::SYS_recvfrom
+            | libc::SYS_setsockopt => self.check_fd(num, arg(&req, 0))?,
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Track the file descriptors opened and closed by a proxied request
+    pub fn record(&self, req: &Request, ret: &sallyport::Result) {
+        let ret: [Register<usize>; 2] = match ret {
+            Ok(ret) => *ret,
+            Err(_) => return,
+        };
+
+        let num = i64::from(req.num);
+        let fd = usize::from(ret[0]) as RawFd;
+
+        match num {
+            libc::SYS_close => {
+                self.fds.lock().unwrap().remove(&(arg(req, 0) as RawFd));
+                self.dirs.lock().unwrap().remove(&(arg(req, 0) as RawFd));
+            }
+
+            // Whatever is opened beneath a directory is as writable as it is
+            libc::SYS_openat => {
+                let mut dirs = self.dirs.lock().unwrap();
+                if let Some(writable) = dirs.get(&(arg(req, 0) as u32 as RawFd)).copied() {
+                    dirs.insert(fd, writable);
+                }
+                self.fds.lock().unwrap().insert(fd);
+            }
+
+            libc::SYS_fcntl => {
+                let cmd = arg(req, 1) as libc::c_int;
+                if cmd == libc::F_DUPFD || cmd == libc::F_DUPFD_CLOEXEC {
+                    self.fds.lock().unwrap().insert(fd);
+                }
+            }
+
+            libc::SYS_socket
+            | libc::SYS_accept
+            | libc::SYS_accept4
+            | libc::SYS_dup
+            | libc::SYS_dup2
+            | libc::SYS_dup3
+            | libc::SYS_eventfd2
+            | libc::SYS_epoll_create1 => {
+                self.fds.lock().unwrap().insert(fd);
+            }
+
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use sallyport::request;
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    fn policy(toml: &str) -> Policy {
+        let config: PolicyConfig = toml::from_str(toml).unwrap();
+        Policy::new(&config, vec![7, 9], vec![(9, false)]).unwrap()
+    }
+
+    #[test]
+    fn ip_range() {
+        let range: IpRange = "10.1.0.0/16".parse().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("10.2.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+
+        let range: IpRange = "::1".parse().unwrap();
+        assert!(range.contains("::1".parse().unwrap()));
+        assert!(!range.contains("::2".parse().unwrap()));
+
+        let range: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(range.contains("192.168.1.1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("localhost".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        for toml in &[
+            "syscalls = [\"execve\"]",
+            "socket_families = [\"bluetooth\"]",
+            "addresses = [\"10.0.0.0/40\"]",
+            "fds = [-1]",
+        ] {
+            let config: PolicyConfig = toml::from_str(toml).unwrap();
+            assert!(Policy::new(&config, None, None).is_err(), "{}", toml);
+        }
+    }
+
+    #[test]
+    fn syscalls() {
+        let policy = policy("syscalls = [\"read\"]");
+        let mut block = Block::default();
+
+        block.msg.req = request!(libc::SYS_read => 0, 0, 0);
+        assert_eq!(policy.check(&block), Ok(()));
+
+        block.msg.req = request!(libc::SYS_write => 1, 0, 0);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+
+        block.msg.req = request!(libc::SYS_execve => 0, 0, 0);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+
+        // Neither x32 syscalls (`__X32_SYSCALL_BIT | nr`) nor unknown Enarx
+        // calls reach the host
+        let policy = policy("");
+        block.msg.req = request!(0x4000_0000 | libc::SYS_openat => 0, 0, 0);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+
+        block.msg.req = request!(0xEAFF => 0, 0, 0);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+    }
+
+    #[test]
+    fn fds() {
+        let policy = policy("");
+        let mut block = Block::default();
+
+        block.msg.req = request!(libc::SYS_read => 7, 0, 0);
+        assert_eq!(policy.check(&block), Ok(()));
+
+        block.msg.req = request!(libc::SYS_read => 8, 0, 0);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+
+        block.msg.req = request!(libc::SYS_pread64 => 8, 0, 0, 0);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+
+        // A socket created by the keep can be used ...
+        let req = request!(libc::SYS_socket => libc::AF_INET, libc::SOCK_STREAM, 0);
+        policy.record(&req, &Ok([8usize.into(), 0usize.into()]));
+        block.msg.req = request!(libc::SYS_read => 8, 0, 0);
+        assert_eq!(policy.check(&block), Ok(()));
+
+        // ... until it is closed
+        let req = request!(libc::SYS_close => 8);
+        policy.record(&req, &Ok([0usize.into(), 0usize.into()]));
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+    }
+
+    #[test]
+    fn sockets() {
+        let policy = policy("socket_families = [\"inet\"]\naddresses = [\"127.0.0.0/8\"]");
+        let mut block = Block::default();
+
+        block.msg.req = request!(libc::SYS_socket => libc::AF_INET, libc::SOCK_STREAM, 0);
+        assert_eq!(policy.check(&block), Ok(()));
+
+        block.msg.req = request!(libc::SYS_socket => libc::AF_UNIX, libc::SOCK_STREAM, 0);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+
+        let connect = |block: &mut Block, ip: Ipv4Addr| {
+            let sa = libc::sockaddr_in {
+                sin_family: libc::AF_INET as _,
+                sin_port: 80u16.to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from(ip).to_be(),
+                },
+                sin_zero: [0; 8],
+            };
+
+            let len = size_of::<libc::sockaddr_in>();
+            let ptr = unsafe { (block as *mut Block as *mut u8).add(size_of::<Block>() - len) };
+            unsafe { (ptr as *mut libc::sockaddr_in).write_unaligned(sa) };
+            block.msg.req = request!(libc::SYS_connect => 7, ptr, len);
+        };
+
+        connect(&mut block, Ipv4Addr::LOCALHOST);
+        assert_eq!(policy.check(&block), Ok(()));
+
+        connect(&mut block, Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+
+        // The address has to be part of the sallyport block
+        let sa = [0u8; 16];
+        block.msg.req = request!(libc::SYS_connect => 7, sa.as_ptr(), sa.len());
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+    }
+
+    #[test]
+    fn dirs() {
+        let host = tempfile::tempdir().unwrap();
+        std::fs::create_dir(host.path().join("sub")).unwrap();
+        std::os::unix::fs::symlink("/etc", host.path().join("link")).unwrap();
+
+        let ro = File::open(host.path()).unwrap();
+        let rw = File::open(host.path()).unwrap();
+        let (ro, rw) = (ro.as_raw_fd(), rw.as_raw_fd());
+        let policy = Policy::new(
+            &PolicyConfig::default(),
+            vec![ro, rw],
+            vec![(ro, false), (rw, true)],
+        )
+        .unwrap();
+
+        let mut block = Block::default();
+        let open = |block: &mut Block, dirfd: RawFd, path: &str, flags: libc::c_int| {
+            let ptr = unsafe { (block as *mut Block as *mut u8).add(size_of::<Block>() - 64) };
+            let bytes = unsafe { std::slice::from_raw_parts_mut(ptr, 64) };
+            bytes.fill(0);
+            bytes[..path.len()].copy_from_slice(path.as_bytes());
+            block.msg.req = request!(libc::SYS_openat => dirfd, ptr, flags, 0);
+        };
+
+        open(&mut block, ro, "sub/file", libc::O_RDONLY);
+        assert_eq!(policy.check(&block), Ok(()));
+
+        // Read-only directories cannot be written to
+        open(&mut block, ro, "sub/file", libc::O_WRONLY | libc::O_CREAT);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+        open(&mut block, rw, "sub/file", libc::O_WRONLY | libc::O_CREAT);
+        assert_eq!(policy.check(&block), Ok(()));
+
+        // The path has to stay beneath the directory
+        for path in [
+            "/etc/passwd",
+            "../etc/passwd",
+            "sub/../../etc",
+            "link/passwd",
+            "",
+        ] {
+            open(&mut block, rw, path, libc::O_RDONLY);
+            assert_eq!(policy.check(&block), Err(libc::EPERM), "{:?}", path);
+        }
+
+        // Only preopened directories can be used
+        open(&mut block, libc::AT_FDCWD, "sub", libc::O_RDONLY);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+        open(&mut block, 7, "sub", libc::O_RDONLY);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+
+        // A directory opened beneath a read-only one is read-only
+        let req = request!(libc::SYS_openat => ro, 0, libc::O_DIRECTORY, 0);
+        policy.record(&req, &Ok([100usize.into(), 0usize.into()]));
+        open(&mut block, 100, "file", libc::O_RDWR);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+        open(&mut block, 100, "file", libc::O_RDONLY);
+        assert_eq!(policy.check(&block), Ok(()));
+
+        // The path of a request has to be part of the sallyport block
+        let path = b"sub\0";
+        block.msg.req = request!(libc::SYS_openat => ro, path.as_ptr(), libc::O_RDONLY, 0);
+        assert_eq!(policy.check(&block), Err(libc::EPERM));
+    }
+}
@@ -0,0 +1,624 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording and replaying the hostcalls of a keep
+//!
+//! With `--record FILE`, every request a keep thread exits with is written to
+//! `FILE`, along with the block as it is returned to the keep. With
+//! `--replay FILE`, the recorded blocks are returned instead of executing the
+//! requests on the host. So a keep, which behaves deterministically given the
+//! same host replies, runs exactly like the recorded one.
+//!
+//! Requests, which change the keep itself, like ballooning, cannot be faked
+//! and are executed again during a replay.
+//!
+//! A replay is checked against the recording: if a keep thread makes a
+//! request other than the recorded one, the replay stops and reports this
+//! first divergence. Arguments pointing into the block are compared relative
+//! to the block, as the block lives at a different address in every run.
+//! The data a request hands to the host in the block, like the buffer of a
+//! `write()` or a path, is compared, too.
+
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, Context, Result};
+use primordial::Register;
+use sallyport::Block;
+use spinning::Lazy;
+
+/// The start of a recording
+const MAGIC: &[u8; 8] = b"ENARXREC";
+
+/// The version of the recording format
+const VERSION: u32 = 2;
+
+/// How the host handles a request
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// A request answered by the host without changing the keep, which is replayed
+    Proxied,
+
+    /// A CPUID exit, which is replayed
+    CpuId,
+
+    /// A request changing the keep, which is executed again during a replay
+    Backend,
+}
+
+impl Kind {
+    fn from_u8(kind: u8) -> Result<Self> {
+        Ok(match kind {
+            0 => Self::Proxied,
+            1 => Self::CpuId,
+            2 => Self::Backend,
+            _ => bail!("invalid hostcall kind {}", kind),
+        })
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Proxied => 0,
+            Self::CpuId => 1,
+            Self::Backend => 2,
+        }
+    }
+}
+
+/// An argument of a request
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Arg {
+    /// A plain value
+    Value(u64),
+
+    /// An address inside of the block, relative to the block
+    Block(u64),
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Value(value) => write!(f, "{:#x}", value),
+            Self::Block(offset) => write!(f, "block+{:#x}", offset),
+        }
+    }
+}
+
+/// A request as seen by the host
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Request {
+    kind: Kind,
+    num: u64,
+    args: [Arg; 6],
+}
+
+impl Request {
+    fn new(kind: Kind, block: &Block) -> Self {
+        let start = block as *const Block as u64;
+        let end = start + size_of::<Block>() as u64;
+        let arg = |reg: Register<usize>| {
+            let value: usize = reg.into();
+            match value as u64 {
+                addr if (start..end).contains(&addr) => Arg::Block(addr - start),
+                value => Arg::Value(value),
+            }
+        };
+
+        let req = unsafe { block.msg.req };
+        let num: usize = req.num.into();
+
+        Self {
+            kind,
+            num: num as u64,
+            args: req.arg.map(arg),
+        }
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Kind::CpuId => write!(f, "cpuid(")?,
+            _ => write!(f, "hostcall {}(", self.num)?,
+        }
+
+        for (i, arg) in self.args.iter().enumerate() {
+            match i {
+                0 => write!(f, "{}", arg)?,
+                _ => write!(f, ", {}", arg)?,
+            }
+        }
+
+        write!(f, ")")
+    }
+}
+
+/// The data the request in `block` hands to the host, besides its arguments
+///
+/// These are the bytes in the block its arguments point to and describe the
+/// length of. Where the block holds pointers, only the data they point to is
+/// included, as the block lives at a different address in every run.
+fn payload(block: &Block) -> Vec<u8> {
+    let start = block as *const Block as usize;
+    let end = start + size_of::<Block>();
+
+    let req = unsafe { block.msg.req };
+    let num: usize = req.num.into();
+    let arg = |idx: usize| -> usize { req.arg[idx].into() };
+
+    let mut payload = Vec::new();
+    let mut bytes = |ptr: usize, len: usize| {
+        // Data outside of the block does not reach the host
+        if let Some(last) = ptr.checked_add(len) {
+            if ptr >= start && last <= end {
+                payload.extend_from_slice(unsafe { std::slice::from_raw_parts(ptr as _, len) });
+            }
+        }
+    };
+    let cstr = |ptr: usize| match ptr {
+        ptr if (start..end).contains(&ptr) => {
+            let rest = unsafe { std::slice::from_raw_parts(ptr as *const u8, end - ptr) };
+            rest.iter()
+                .position(|b| *b == 0)
+                .map_or(rest.len(), |n| n + 1)
+        }
+        _ => 0,
+    };
+
+    match num as libc::c_long {
+        libc::SYS_write | libc::SYS_pwrite64 | libc::SYS_bind | libc::SYS_connect => {
+            bytes(arg(1), arg(2))
+        }
+
+        libc::SYS_sendto => {
+            bytes(arg(1), arg(2));
+            bytes(arg(4), arg(5));
+        }
+
+        libc::SYS_setsockopt => bytes(arg(3), arg(4)),
+
+        libc::SYS_writev | libc::SYS_pwritev => {
+            let iov = arg(1);
+            let size = size_of::<libc::iovec>();
+            for i in 0..arg(2) {
+                let ptr = iov.wrapping_add(i * size);
+                if ptr < start || ptr.saturating_add(size) > end {
+                    break;
+                }
+                let iov = unsafe { (ptr as *const libc::iovec).read_unaligned() };
+                bytes(iov.iov_base as usize, iov.iov_len);
+            }
+        }
+
+        libc::SYS_poll => {
+            let len = arg(1).saturating_mul(size_of::<libc::pollfd>());
+            bytes(arg(0), len)
+        }
+
+        libc::SYS_epoll_ctl => bytes(arg(3), size_of::<libc::epoll_event>()),
+
+        libc::SYS_nanosleep => bytes(arg(0), size_of::<libc::timespec>()),
+
+        libc::SYS_openat
+        | libc::SYS_newfstatat
+        | libc::SYS_readlinkat
+        | libc::SYS_mkdirat
+        | libc::SYS_unlinkat => bytes(arg(1), cstr(arg(1))),
+
+        libc::SYS_renameat => {
+            bytes(arg(1), cstr(arg(1)));
+            bytes(arg(3), cstr(arg(3)));
+        }
+
+        _ => (),
+    }
+
+    payload
+}
+
+/// A recorded request and the block returned to the keep
+struct Entry {
+    /// The position in the recording
+    index: u64,
+
+    /// The keep thread, which made the request
+    thread: u32,
+
+    request: Request,
+
+    /// The data the request handed to the host, see [`payload`]
+    payload: Vec<u8>,
+
+    /// The block after handling the request, without trailing zeros
+    reply: Vec<u8>,
+}
+
+impl Entry {
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(self.payload.len() + self.reply.len() + 128);
+
+        buf.extend(self.thread.to_le_bytes());
+        buf.push(self.request.kind.to_u8());
+        buf.extend(self.request.num.to_le_bytes());
+        for arg in self.request.args {
+            let (tag, value) = match arg {
+                Arg::Value(value) => (0, value),
+                Arg::Block(offset) => (1, offset),
+            };
+            buf.push(tag);
+            buf.extend(value.to_le_bytes());
+        }
+        buf.extend((self.payload.len() as u32).to_le_bytes());
+        buf.extend(&self.payload);
+        buf.extend((self.reply.len() as u32).to_le_bytes());
+        buf.extend(&self.reply);
+
+        // An entry is written at once, so a crashing keep leaves a usable recording
+        out.write_all(&buf)
+    }
+
+    /// Read the next entry, `None` at the end of the recording
+    fn read(input: &mut impl Read, index: u64) -> Result<Option<Self>> {
+        let thread = match read_u32(input) {
+            Ok(thread) => thread,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut kind = [0u8];
+        input.read_exact(&mut kind)?;
+        let kind = Kind::from_u8(kind[0])?;
+        let num = read_u64(input)?;
+
+        let mut args = [Arg::Value(0); 6];
+        for arg in args.iter_mut() {
+            let mut tag = [0u8];
+            input.read_exact(&mut tag)?;
+            let value = read_u64(input)?;
+            *arg = match tag[0] {
+                0 => Arg::Value(value),
+                1 => Arg::Block(value),
+                tag => bail!("invalid argument tag {}", tag),
+            };
+        }
+
+        let len = read_u32(input)? as usize;
+        if len > size_of::<Block>() {
+            bail!("recorded request data of {} bytes is too large", len);
+        }
+        let mut payload = vec![0; len];
+        input.read_exact(&mut payload)?;
+
+        let len = read_u32(input)? as usize;
+        if len > size_of::<Block>() {
+            bail!("recorded block of {} bytes is too large", len);
+        }
+        let mut reply = vec![0; len];
+        input.read_exact(&mut reply)?;
+
+        Ok(Some(Self {
+            index,
+            thread,
+            request: Request { kind, num, args },
+            payload,
+            reply,
+        }))
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// The bytes of `block`
+fn as_bytes(block: &mut Block) -> &mut [u8] {
+    unsafe { std::slice::from_raw_parts_mut(block as *mut Block as *mut u8, size_of::<Block>()) }
+}
+
+enum Mode {
+    Record { file: File, next: u64 },
+    Replay(HashMap<u32, VecDeque<Entry>>),
+}
+
+/// Whether hostcalls are recorded or replayed
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+static MODE: Lazy<Mutex<Option<Mode>>> = Lazy::new(Default::default);
+
+thread_local! {
+    /// The number of the keep thread running on this host thread
+    static THREAD: Cell<u32> = Cell::new(0);
+}
+
+/// Write the header of a recording of a keep running on `backend`
+fn write_header(out: &mut impl Write, backend: &str) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&(size_of::<Block>() as u32).to_le_bytes())?;
+    out.write_all(&(backend.len() as u32).to_le_bytes())?;
+    out.write_all(backend.as_bytes())
+}
+
+/// Check the header of a recording against `backend`
+fn read_header(input: &mut impl Read, backend: &str) -> Result<()> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("not a recording of hostcalls");
+    }
+
+    let version = read_u32(input)?;
+    if version != VERSION {
+        bail!("unsupported recording version {}", version);
+    }
+
+    let block = read_u32(input)? as usize;
+    if block != size_of::<Block>() {
+        bail!(
+            "the recording has blocks of {} bytes instead of {}",
+            block,
+            size_of::<Block>()
+        );
+    }
+
+    let len = read_u32(input)? as usize;
+    if len > 64 {
+        bail!("invalid backend name of {} bytes", len);
+    }
+    let mut name = vec![0; len];
+    input.read_exact(&mut name)?;
+    let name = String::from_utf8_lossy(&name);
+    if name != backend {
+        bail!(
+            "the recording was made with the {} backend, not with {}",
+            name,
+            backend
+        );
+    }
+
+    Ok(())
+}
+
+/// Load the recording at `path` of a keep running on `backend`
+fn load(path: &Path, backend: &str) -> Result<HashMap<u32, VecDeque<Entry>>> {
+    let mut input = BufReader::new(File::open(path)?);
+    read_header(&mut input, backend)?;
+
+    let mut threads: HashMap<u32, VecDeque<Entry>> = HashMap::new();
+    for index in 0.. {
+        match Entry::read(&mut input, index) {
+            Ok(Some(entry)) => threads.entry(entry.thread).or_default().push_back(entry),
+            Ok(None) => break,
+
+            // A keep crashing while recording may leave a truncated entry
+            Err(e) => {
+                log::warn!(
+                    "ignoring the rest of the recording from hostcall {}: {:#}",
+                    index,
+                    e
+                );
+                break;
+            }
+        }
+    }
+
+    Ok(threads)
+}
+
+/// Start recording to `record` or replaying from `replay` for a keep running on `backend`
+pub fn start(record: Option<&Path>, replay: Option<&Path>, backend: &str) -> Result<()> {
+    let mode = match (record, replay) {
+        (Some(path), None) => {
+            let mut file = File::create(path)
+                .with_context(|| format!("failed to create the recording {:?}", path))?;
+            write_header(&mut file, backend)
+                .with_context(|| format!("failed to write the recording {:?}", path))?;
+            Mode::Record { file, next: 0 }
+        }
+        (None, Some(path)) => Mode::Replay(
+            load(path, backend).with_context(|| format!("invalid recording {:?}", path))?,
+        ),
+        (None, None) => return Ok(()),
+        (Some(_), Some(_)) => bail!("cannot record and replay at the same time"),
+    };
+
+    *MODE.lock().unwrap() = Some(mode);
+    ACTIVE.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Register the calling host thread as running keep thread `thread`
+///
+/// The keep threads are numbered in the order they are started, which is the
+/// same in a replay of a deterministic keep.
+pub fn register(thread: u32) {
+    THREAD.with(|t| t.set(thread));
+}
+
+/// Handle the request in `block` with `handle`, unless it is replayed
+///
+/// Fails on the first request, which diverges from the recording.
+pub fn hostcall(kind: Kind, block: &mut Block, handle: impl FnOnce(&mut Block)) -> Result<()> {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        handle(block);
+        return Ok(());
+    }
+
+    let thread = THREAD.with(|t| t.get());
+    let request = Request::new(kind, block);
+    let payload = match kind {
+        Kind::Proxied => payload(block),
+        Kind::CpuId | Kind::Backend => Vec::new(),
+    };
+
+    let replayed = match MODE.lock().unwrap().as_mut() {
+        Some(Mode::Replay(threads)) => Some(threads.get_mut(&thread).and_then(VecDeque::pop_front)),
+        _ => None,
+    };
+
+    if let Some(entry) = replayed {
+        let entry = entry.ok_or_else(|| {
+            anyhow!(
+                "replay diverged: keep thread {} made the request {}, but its recording ends here",
+                thread,
+                request
+            )
+        })?;
+
+        if entry.request != request {
+            bail!(
+                "replay diverged at hostcall {} of the recording: keep thread {} made the request {}, but the recorded request is {}",
+                entry.index,
+                thread,
+                request,
+                entry.request
+            );
+        }
+
+        if entry.payload != payload {
+            bail!(
+                "replay diverged at hostcall {} of the recording: keep thread {} made the request {} with other data than recorded",
+                entry.index,
+                thread,
+                request
+            );
+        }
+
+        match kind {
+            Kind::Backend => handle(block),
+            Kind::Proxied | Kind::CpuId => {
+                let (reply, rest) = as_bytes(block).split_at_mut(entry.reply.len());
+                reply.copy_from_slice(&entry.reply);
+                rest.fill(0);
+            }
+        }
+
+        return Ok(());
+    }
+
+    // The request is handled without holding the lock, as it may block
+    handle(block);
+
+    let bytes = as_bytes(block);
+    let len = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+
+    let mut mode = MODE.lock().unwrap();
+    if let Some(Mode::Record { file, next }) = mode.as_mut() {
+        let entry = Entry {
+            index: *next,
+            thread,
+            request,
+            payload,
+            reply: bytes[..len].to_vec(),
+        };
+        *next += 1;
+
+        entry.write(file).context("failed to write the recording")?;
+    }
+
+    Ok(())
+}
+
+/// Report the recorded requests, which were not replayed
+///
+/// This is called right before `enarx` exits.
+pub fn finish() {
+    if let Some(Mode::Replay(threads)) = MODE.lock().unwrap().as_ref() {
+        let left: usize = threads.values().map(VecDeque::len).sum();
+        if left > 0 {
+            eprintln!(
+                "Warning: the replay ended with {} recorded hostcalls left",
+                left
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entry() {
+        let entry = Entry {
+            index: 7,
+            thread: 1,
+            request: Request {
+                kind: Kind::Proxied,
+                num: libc::SYS_write as u64,
+                args: [
+                    Arg::Value(1),
+                    Arg::Block(0x40),
+                    Arg::Value(5),
+                    Arg::Value(0),
+                    Arg::Value(0),
+                    Arg::Value(0),
+                ],
+            },
+            payload: b"hello".to_vec(),
+            reply: b"\x05".to_vec(),
+        };
+
+        let mut buf = Vec::new();
+        entry.write(&mut buf).unwrap();
+
+        let mut input = &buf[..];
+        let read = Entry::read(&mut input, 7).unwrap().unwrap();
+        assert_eq!(read.index, 7);
+        assert_eq!(read.thread, 1);
+        assert_eq!(read.request, entry.request);
+        assert_eq!(read.payload, entry.payload);
+        assert_eq!(read.reply, entry.reply);
+        assert!(Entry::read(&mut input, 8).unwrap().is_none());
+
+        assert_eq!(
+            entry.request.to_string(),
+            "hostcall 1(0x1, block+0x40, 0x5, 0x0, 0x0, 0x0)"
+        );
+    }
+
+    #[test]
+    fn payload() {
+        let mut block = Block::default();
+        let ptr = unsafe { (&mut block as *mut Block as *mut u8).add(size_of::<Block>() - 8) };
+        unsafe { ptr.copy_from(b"hello\0\0\0".as_ptr(), 8) };
+
+        block.msg.req = sallyport::request!(libc::SYS_write => 1, ptr, 5);
+        assert_eq!(super::payload(&block), b"hello");
+
+        block.msg.req = sallyport::request!(libc::SYS_openat => libc::AT_FDCWD, ptr, 0, 0);
+        assert_eq!(super::payload(&block), b"hello\0");
+
+        // Data outside of the block is not part of the request
+        let outside = [0u8; 5];
+        block.msg.req = sallyport::request!(libc::SYS_write => 1, outside.as_ptr(), 5);
+        assert!(super::payload(&block).is_empty());
+
+        block.msg.req = sallyport::request!(libc::SYS_read => 0, ptr, 5);
+        assert!(super::payload(&block).is_empty());
+    }
+
+    #[test]
+    fn header() {
+        let mut buf = Vec::new();
+        write_header(&mut buf, "kvm").unwrap();
+
+        assert!(read_header(&mut &buf[..], "kvm").is_ok());
+        assert!(read_header(&mut &buf[..], "sgx").is_err());
+        assert!(read_header(&mut &b"garbage!"[..], "kvm").is_err());
+    }
+}
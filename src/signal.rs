@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Forwarding of host signals into the keep
+//!
+//! `enarx` catches the [`FORWARDED`] signals instead of dying from them. A
+//! caught signal becomes pending for the keep and interrupts all keep
+//! threads, so a proxied syscall blocking in the host fails with `EINTR`.
+//! While a signal is pending, blocking syscalls fail the same way before
+//! they are executed, see [`interrupts`]. The shim then fetches the signal
+//! with [`SYS_ENARX_SIGNAL`] and delivers it to the workload.
+//!
+//! If a signal arrives again while it is still pending, the keep did not
+//! take it and `enarx` itself gets the default action. So a second Ctrl-C
+//! always terminates a stuck keep.
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{Context, Result};
+
+/// Fetch a signal `enarx` received for the keep
+///
+/// The reply is the number of a pending signal, which is then no longer
+/// pending, or zero.
+pub const SYS_ENARX_SIGNAL: i64 = 0xEA15;
+
+/// The signals forwarded into the keep
+pub const FORWARDED: [libc::c_int; 3] = [libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+/// `si_code` of a signal sent with `tgkill()`, like `pthread_kill()` does
+const SI_TKILL: libc::c_int = -6;
+
+/// The maximum number of keep threads interrupted by signals
+const MAX_THREADS: usize = 1024;
+
+/// The pending signals, bit `n - 1` for signal `n`
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// The `pthread_t` of the keep threads, unused slots are zero
+#[allow(clippy::declare_interior_mutable_const)]
+static THREADS: [AtomicU64; MAX_THREADS] = {
+    const EMPTY: AtomicU64 = AtomicU64::new(0);
+    [EMPTY; MAX_THREADS]
+};
+
+/// The syscalls, which may block and therefore fail with `EINTR` while a signal is pending
+const INTERRUPTIBLE: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_pread64,
+    libc::SYS_preadv,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_pwrite64,
+    libc::SYS_pwritev,
+    libc::SYS_poll,
+    libc::SYS_ppoll,
+    libc::SYS_select,
+    libc::SYS_pselect6,
+    libc::SYS_epoll_wait,
+    libc::SYS_epoll_pwait,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_accept,
+    libc::SYS_accept4,
+    libc::SYS_connect,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmsg,
+    libc::SYS_sendto,
+    libc::SYS_sendmsg,
+    libc::SYS_wait4,
+];
+
+/// Catch the [`FORWARDED`] signals
+///
+/// The handlers are installed without `SA_RESTART`, so they interrupt
+/// blocking syscalls.
+pub fn install() -> Result<()> {
+    for sig in FORWARDED {
+        let mut act: libc::sigaction = unsafe { MaybeUninit::zeroed().assume_init() };
+        act.sa_sigaction = handle as usize;
+        act.sa_flags = libc::SA_SIGINFO;
+        unsafe { libc::sigemptyset(&mut act.sa_mask) };
+
+        if unsafe { libc::sigaction(sig, &act, null_mut()) } != 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("failed to catch signal {}", sig));
+        }
+    }
+
+    Ok(())
+}
+
+/// Register the calling thread as a keep thread to be interrupted by signals
+pub fn register() {
+    let me = unsafe { libc::pthread_self() } as u64;
+
+    for slot in THREADS.iter() {
+        if slot
+            .compare_exchange(0, me, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return;
+        }
+    }
+}
+
+/// Whether a pending signal interrupts syscall `nr`
+pub fn interrupts(nr: i64) -> bool {
+    PENDING.load(Ordering::SeqCst) != 0 && INTERRUPTIBLE.contains(&(nr as libc::c_long))
+}
+
+/// Take the lowest pending signal, zero if there is none
+pub fn take() -> usize {
+    let mut pending = PENDING.load(Ordering::SeqCst);
+
+    while pending != 0 {
+        let bit = pending & pending.wrapping_neg();
+        match PENDING.compare_exchange(pending, pending & !bit, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => return bit.trailing_zeros() as usize + 1,
+            Err(now) => pending = now,
+        }
+    }
+
+    0
+}
+
+/// The signal handler, which may only call async-signal-safe functions
+extern "C" fn handle(sig: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    // Another thread of `enarx` only interrupts this one
+    let info = unsafe { &*info };
+    if info.si_code == SI_TKILL && unsafe { info.si_pid() == libc::getpid() } {
+        return;
+    }
+
+    let bit = 1u64 << (sig - 1);
+    if PENDING.fetch_or(bit, Ordering::SeqCst) & bit != 0 {
+        // The keep did not take the signal, so give up on it
+        unsafe {
+            libc::signal(sig, libc::SIG_DFL);
+            libc::raise(sig);
+        }
+        return;
+    }
+
+    let me = unsafe { libc::pthread_self() } as u64;
+    for slot in THREADS.iter() {
+        match slot.load(Ordering::SeqCst) {
+            0 => break,
+            thread if thread == me => continue,
+            thread => unsafe {
+                libc::pthread_kill(thread as libc::pthread_t, sig);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pending() {
+        assert_eq!(take(), 0);
+        assert!(!interrupts(libc::SYS_read));
+
+        PENDING.fetch_or(1 << (libc::SIGTERM - 1), Ordering::SeqCst);
+        PENDING.fetch_or(1 << (libc::SIGINT - 1), Ordering::SeqCst);
+
+        assert!(interrupts(libc::SYS_read));
+        assert!(!interrupts(libc::SYS_close));
+
+        assert_eq!(take(), libc::SIGINT as usize);
+        assert_eq!(take(), libc::SIGTERM as usize);
+        assert_eq!(take(), 0);
+        assert!(!interrupts(libc::SYS_read));
+    }
+}
@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Unix sockets only the owner can connect to
+//!
+//! A socket is created with the permissions of the umask, so setting its
+//! permissions after `bind()` leaves a window, in which other users could
+//! connect. Instead, the socket is bound in a private directory, made
+//! accessible to the owner only and then moved to its path.
+
+use std::io::Error;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+/// A directory only the current user can access, for sockets
+///
+/// This is `$XDG_RUNTIME_DIR`, or else `/tmp/enarx-<uid>`, which is created
+/// if needed.
+pub fn runtime_dir() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return Ok(dir.into());
+    }
+
+    let uid = unsafe { libc::getuid() };
+    let dir = PathBuf::from(format!("/tmp/enarx-{}", uid));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+            return Err(e).with_context(|| format!("failed to create {:?}", dir))
+        }
+        _ => (),
+    }
+
+    // Another user may have created it first
+    let meta = std::fs::symlink_metadata(&dir)?;
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        bail!("{:?} is not a private directory", dir);
+    }
+
+    Ok(dir)
+}
+
+/// Bind a socket to `path`, which only the owner can connect to
+///
+/// A stale socket at `path` is replaced, but not one something listens on
+/// and nothing that is not a socket.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if !meta.file_type().is_socket() => bail!("{:?} is not a socket", path),
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("failed to inspect {:?}", path))
+        }
+        _ => (),
+    }
+
+    if UnixStream::connect(path).is_ok() {
+        bail!("something is listening on {:?} already", path);
+    }
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut template = parent.join(".enarx-XXXXXX").as_os_str().as_bytes().to_vec();
+    template.push(0);
+    if unsafe { libc::mkdtemp(template.as_mut_ptr() as _) }.is_null() {
+        return Err(Error::last_os_error())
+            .with_context(|| format!("failed to create a directory in {:?}", parent));
+    }
+    template.pop();
+    let private = PathBuf::from(std::ffi::OsStr::from_bytes(&template));
+
+    let bound = private.join("sock");
+    let listener = UnixListener::bind(&bound)
+        .and_then(|listener| {
+            std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&bound, path)?;
+            Ok(listener)
+        })
+        .with_context(|| format!("failed to bind to {:?}", path));
+
+    let _ = std::fs::remove_file(&bound);
+    let _ = std::fs::remove_dir(&private);
+    listener
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn private() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.sock");
+
+        let _listener = bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(UnixStream::connect(&path).is_ok());
+
+        // Only the socket is left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // The socket is in use
+        assert!(bind(&path).is_err());
+    }
+
+    #[test]
+    fn stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.sock");
+
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(bind(&path).is_ok());
+    }
+
+    #[test]
+    fn not_a_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.sock");
+
+        std::fs::write(&path, "data").unwrap();
+        assert!(bind(&path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"data");
+
+        // Nor is a symlink followed or replaced
+        let link = dir.path().join("link.sock");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        assert!(bind(&link).is_err());
+        assert!(std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+}
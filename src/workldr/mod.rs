@@ -38,6 +38,9 @@ pub trait Workldr: Sync + Send {
 
     /// The builtin Workldr binary (e.g. wasmldr)
     fn exec(&self) -> &'static [u8];
+
+    /// The version of the builtin Workldr binary
+    fn version(&self) -> &'static str;
 }
 
 pub static WORKLDRS: Lazy<Vec<Box<dyn Workldr>>> = Lazy::new(|| {
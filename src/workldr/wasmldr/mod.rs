@@ -12,6 +12,11 @@ impl crate::workldr::Workldr for Wasmldr {
     fn exec(&self) -> &'static [u8] {
         include_bytes!(concat!(env!("OUT_DIR"), "/bin/wasmldr"))
     }
+
+    #[inline]
+    fn version(&self) -> &'static str {
+        env!("ENARX_WASMLDR_VERSION")
+    }
 }
 
 #[cfg(test)]
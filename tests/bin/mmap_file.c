@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+#include <sys/mman.h>
+
+/* The size of the file on stdin */
+#define LEN 5000
+#define PAGE 4096
+
+static void *sys_mmap(void *addr, size_t length, int prot, int flags, int fd, off_t offset) {
+    long rax;
+    register long r10 asm("r10") = flags;
+    register long r8 asm("r8") = fd;
+    register long r9 asm("r9") = offset;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_mmap), "D" (addr), "S" (length), "d" (prot), "r" (r10), "r" (r8), "r" (r9)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return (void *) rax;
+}
+
+static int zero(const char *buf, size_t len) {
+    for (size_t i = 0; i < len; i++) {
+        if (buf[i] != 0) {
+            return 0;
+        }
+    }
+
+    return 1;
+}
+
+/* Map the file on stdin as a whole and from its second page on, and write
+ * both mappings to stdout. The rest of their last pages must be zero. */
+int main(void) {
+    const char *all = sys_mmap(NULL, LEN, PROT_READ, MAP_PRIVATE, STDIN_FILENO, 0);
+    if ((unsigned long) all > -4096UL) {
+        return 1;
+    }
+
+    const char *tail = sys_mmap(NULL, LEN - PAGE, PROT_READ, MAP_PRIVATE, STDIN_FILENO, PAGE);
+    if ((unsigned long) tail > -4096UL) {
+        return 2;
+    }
+
+    if (!zero(all + LEN, 2 * PAGE - LEN) || !zero(tail + LEN - PAGE, 2 * PAGE - LEN)) {
+        return 3;
+    }
+
+    if (write(STDOUT_FILENO, all, LEN) != LEN) {
+        return 4;
+    }
+
+    if (write(STDOUT_FILENO, tail, LEN - PAGE) != LEN - PAGE) {
+        return 5;
+    }
+
+    return 0;
+}
@@ -103,6 +103,33 @@ fn readv() {
     run_test("readv", 0, &INPUT[..], &INPUT[..], None);
 }
 
+/// Map a file from the host into the keep, which only the SEV shim supports
+#[cfg(feature = "backend-kvm")]
+#[test]
+fn mmap_file() {
+    let input: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&input).unwrap();
+
+    let bin = std::path::Path::new(common::CRATE)
+        .join(common::OUT_DIR)
+        .join(common::TEST_BINS_OUT)
+        .join("mmap_file");
+
+    let output = std::process::Command::new(common::KEEP_BIN)
+        .args(&["exec", "--backend", "kvm"])
+        .arg(bin)
+        .stdin(file)
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr);
+
+    let expected = [&input[..], &input[4096..]].concat();
+    assert_eq_slices(&expected, &output.stdout, "stdout");
+}
+
 #[test]
 fn echo() {
     let mut input: Vec<u8> = Vec::with_capacity(2 * 1024 * 1024);
@@ -276,3 +303,269 @@ fn memory_stress_test() {
 fn cpuid() {
     run_crate("integration/simple", "cpuid", 0, None, None, None);
 }
+
+#[test]
+fn info_json() {
+    let output = std::process::Command::new(common::KEEP_BIN)
+        .args(&["info", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let backends = info["backends"].as_array().unwrap();
+    assert!(!backends.is_empty());
+
+    for backend in backends {
+        assert!(backend["name"].is_string());
+        assert!(backend["supported"].is_boolean());
+        assert!(backend["capabilities"].is_object());
+
+        for datum in backend["data"].as_array().unwrap() {
+            assert!(datum["pass"].is_boolean());
+        }
+    }
+
+    assert!(info["pick"]["reason"].is_string());
+}
+
+#[test]
+fn invalid_workldr() {
+    let dir = Builder::new().prefix("workldr").tempdir().unwrap();
+    let workldr = dir.path().join("workldr");
+    let module = dir.path().join("module.wasm");
+    fs::write(&workldr, b"not an ELF binary").unwrap();
+    fs::write(&module, b"\0asm\x01\0\0\0").unwrap();
+
+    let output = std::process::Command::new(common::KEEP_BIN)
+        .arg("measure")
+        .arg("--workldr")
+        .arg(&workldr)
+        .arg(&module)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid workldr"), "{}", stderr);
+}
+
+#[test]
+fn timeout() {
+    let bin = std::path::Path::new(common::CRATE)
+        .join(common::OUT_DIR)
+        .join(common::TEST_BINS_OUT)
+        .join("read");
+
+    // `read` blocks, as its stdin stays open
+    let mut child = std::process::Command::new(common::KEEP_BIN)
+        .args(&["exec", "--timeout", "1s"])
+        .arg(bin)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let stdin = child.stdin.take();
+    let output = child.wait_with_output().unwrap();
+    drop(stdin);
+
+    assert_eq!(output.status.code(), Some(124));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("time limit"), "{}", stderr);
+}
+
+#[test]
+fn sigterm() {
+    let bin = std::path::Path::new(common::CRATE)
+        .join(common::OUT_DIR)
+        .join(common::TEST_BINS_OUT)
+        .join("read");
+
+    // `read` blocks, as its stdin stays open
+    let mut child = std::process::Command::new(common::KEEP_BIN)
+        .arg("exec")
+        .arg(bin)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Give the keep time to start up
+    thread::sleep(Duration::from_secs(2));
+    unsafe { libc::kill(child.id() as _, libc::SIGTERM) };
+
+    let stdin = child.stdin.take();
+    let status = child.wait().unwrap();
+    drop(stdin);
+
+    // `read` does not handle the signal, so it is terminated by it
+    assert_eq!(status.code(), Some(128 + libc::SIGTERM));
+}
+
+#[test]
+fn metrics() {
+    let bin = std::path::Path::new(common::CRATE)
+        .join(common::OUT_DIR)
+        .join(common::TEST_BINS_OUT)
+        .join("write_stdout");
+
+    let dir = Builder::new().prefix("metrics").tempdir().unwrap();
+    let path = dir.path().join("metrics.json");
+
+    let status = std::process::Command::new(common::KEEP_BIN)
+        .arg("exec")
+        .arg("--metrics")
+        .arg(&path)
+        .arg(bin)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let metrics: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    let write = metrics["hostcalls"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["nr"] == libc::SYS_write)
+        .unwrap_or_else(|| panic!("no write in {}", metrics));
+    assert!(write["count"].as_u64().unwrap() >= 1, "{}", metrics);
+    assert!(metrics["uptime_seconds"].is_number(), "{}", metrics);
+}
+
+#[test]
+fn record_replay() {
+    let bin = std::path::Path::new(common::CRATE)
+        .join(common::OUT_DIR)
+        .join(common::TEST_BINS_OUT)
+        .join("write_stdout");
+
+    let dir = Builder::new().prefix("record").tempdir().unwrap();
+    let path = dir.path().join("keep.rec");
+
+    let keep = |mode: &str| {
+        std::process::Command::new(common::KEEP_BIN)
+            .arg("exec")
+            .arg(mode)
+            .arg(&path)
+            .arg(&bin)
+            .output()
+            .unwrap()
+    };
+
+    let recorded = keep("--record");
+    assert!(recorded.status.success());
+    assert!(!recorded.stdout.is_empty());
+
+    // The writes are replayed instead of executed
+    let replayed = keep("--replay");
+    let stderr = String::from_utf8_lossy(&replayed.stderr);
+    assert!(replayed.status.success(), "{}", stderr);
+    assert!(replayed.stdout.is_empty());
+    assert!(!stderr.contains("diverged"), "{}", stderr);
+}
+
+#[test]
+fn fault_injection() {
+    let bin = std::path::Path::new(common::CRATE)
+        .join(common::OUT_DIR)
+        .join(common::TEST_BINS_OUT)
+        .join("write_stdout");
+
+    let output = std::process::Command::new(common::KEEP_BIN)
+        .args(&["exec", "--fault-seed", "1"])
+        .arg(bin)
+        .output()
+        .unwrap();
+
+    // Whatever the keep made of the faults, they are accounted for
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Fault injection with seed 1:"),
+        "{}",
+        stderr
+    );
+}
+
+/// Run the test binary `bin` in the nil backend
+#[cfg(feature = "backend-nil")]
+fn nil_exec(bin: &str) -> std::process::Output {
+    let bin = std::path::Path::new(common::CRATE)
+        .join(common::OUT_DIR)
+        .join(common::TEST_BINS_OUT)
+        .join(bin);
+
+    std::process::Command::new(common::KEEP_BIN)
+        .args(&["exec", "--backend", "nil"])
+        .arg(bin)
+        .env_remove("ENARX_BACKEND")
+        .output()
+        .unwrap()
+}
+
+#[cfg(feature = "backend-nil")]
+#[test]
+fn nil_write_stdout() {
+    let output = nil_exec("write_stdout");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr);
+    assert_eq!(output.stdout, b"hi\n");
+}
+
+#[cfg(feature = "backend-nil")]
+#[test]
+fn nil_seccomp() {
+    // Only the limits of the keep process itself are accessible
+    let output = nil_exec("prlimit");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr);
+}
+
+#[test]
+fn serve() {
+    use std::io::{BufRead, BufReader};
+
+    let dir = Builder::new().prefix("serve").tempdir().unwrap();
+    let socket = dir.path().join("enarx.sock");
+
+    let mut daemon = std::process::Command::new(common::KEEP_BIN)
+        .arg("serve")
+        .arg("--socket")
+        .arg(&socket)
+        .spawn()
+        .unwrap();
+
+    let stream = loop {
+        match UnixStream::connect(&socket) {
+            Ok(stream) => break stream,
+            Err(_) => {
+                assert!(daemon.try_wait().unwrap().is_none(), "daemon exited");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    };
+
+    let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+    let mut request = |request: &str| -> serde_json::Value {
+        writeln!(&stream, "{}", request).unwrap();
+        serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap()
+    };
+
+    let reply = request(r#"{"op": "backends"}"#);
+    assert!(reply["ok"]["pick"]["backend"].is_string(), "{}", reply);
+
+    let reply = request(r#"{"op": "list"}"#);
+    assert_eq!(reply["ok"], serde_json::json!([]));
+
+    let reply = request(r#"{"op": "inspect", "id": 1}"#);
+    assert!(reply["error"].is_string(), "{}", reply);
+
+    let reply = request(r#"{"op": "bogus"}"#);
+    assert!(reply["error"].is_string(), "{}", reply);
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
@@ -0,0 +1,54 @@
+;;; SPDX-License-Identifier: Apache-2.0
+
+;;; Write the contents of "hello.txt" in the bundled resources to stdout.
+(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $__wasi_path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $__wasi_fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $__wasi_fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit"
+    (func $__wasi_proc_exit (param i32)))
+  (func $_start
+    (block
+      ;; Open the file for reading in the resources, the first preopen
+      (br_if 0
+        (call $__wasi_path_open
+          (i32.const 3)
+          (i32.const 0)
+          (i32.const 0)
+          (i32.const 9)
+          (i32.const 0)
+          (i64.const 2)
+          (i64.const 0)
+          (i32.const 0)
+          (i32.const 16)))
+
+      ;; Read up to 64 bytes into the buffer at 64
+      (i32.store (i32.const 20) (i32.const 64))
+      (i32.store (i32.const 24) (i32.const 64))
+      (br_if 0
+        (call $__wasi_fd_read
+          (i32.load (i32.const 16))
+          (i32.const 20)
+          (i32.const 1)
+          (i32.const 28)))
+
+      ;; Write what was read to stdout
+      (i32.store (i32.const 24) (i32.load (i32.const 28)))
+      (br_if 0
+        (call $__wasi_fd_write
+          (i32.const 1)
+          (i32.const 20)
+          (i32.const 1)
+          (i32.const 28)))
+      (return)
+    )
+    (call $__wasi_proc_exit (i32.const 1))
+  )
+  (memory 1)
+  (export "memory" (memory 0))
+  (export "_start" (func $_start))
+  (data (i32.const 0) "hello.txt")
+)
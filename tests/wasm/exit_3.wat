@@ -0,0 +1,9 @@
+;;; SPDX-License-Identifier: Apache-2.0
+
+(module
+  (import "wasi_snapshot_preview1" "proc_exit"
+    (func $__wasi_proc_exit (param i32)))
+  (func (export "_start")
+    (call $__wasi_proc_exit (i32.const 3)))
+  (memory (export "memory") 1)
+)
@@ -0,0 +1,8 @@
+;;; SPDX-License-Identifier: Apache-2.0
+
+(module $trap
+  (func $boom
+    unreachable)
+  (func (export "_start")
+    (call $boom))
+)
@@ -23,15 +23,24 @@ fn create(path: &Path) {
     }
 }
 
-pub fn enarx_run<'a>(wasm: &str, input: impl Into<Option<&'a [u8]>>) -> Output {
+pub fn enarx_run<'a>(
+    wasm: &str,
+    config: Option<&Path>,
+    input: impl Into<Option<&'a [u8]>>,
+) -> Output {
     let wasm_path = Path::new(CRATE)
         .join(OUT_DIR)
         .join(TEST_BINS_OUT)
         .join(wasm);
 
-    let mut child = Command::new(&String::from(KEEP_BIN))
-        .current_dir(CRATE)
-        .arg("run")
+    let mut cmd = Command::new(&String::from(KEEP_BIN));
+    cmd.current_dir(CRATE).arg("run");
+
+    if let Some(config) = config {
+        cmd.arg("--config").arg(config);
+    }
+
+    let mut child = cmd
         .arg(wasm_path)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
@@ -106,7 +115,7 @@ fn run_wasm_test<'a>(
 ) -> Output {
     compile(wasm);
 
-    let output = enarx_run(wasm, input);
+    let output = enarx_run(wasm, None, input);
     check_output(&output, status, expected_stdout, expected_stderr);
     output
 }
@@ -145,3 +154,88 @@ fn no_export() {
     // which wasmldr maps to EX_DATAERR (65) at process exit.
     run_wasm_test("no_export.wasm", 65, None, None, None);
 }
+
+#[test]
+fn hello_wasi_snapshot1_stdout_null() {
+    // With stdout set to "null" in the keep config, the greeting goes nowhere.
+    let mut config = tempfile::NamedTempFile::new().unwrap();
+    writeln!(config, "[stdio]\nstdout = \"null\"").unwrap();
+
+    compile("hello_wasi_snapshot1.wasm");
+    let output = enarx_run("hello_wasi_snapshot1.wasm", Some(config.path()), None);
+    check_output(&output, 0, &b""[..], None);
+}
+
+#[test]
+fn hello_wasi_snapshot1_stdout_fd() {
+    // With stdout set to a preopened file, the greeting ends up in the file.
+    let log = tempfile::NamedTempFile::new().unwrap();
+    let mut config = tempfile::NamedTempFile::new().unwrap();
+    writeln!(
+        config,
+        "[stdio]\nstdout = \"fd:log\"\n\n[[files]]\nname = \"log\"\npath = {:?}\nwritable = true",
+        log.path()
+    )
+    .unwrap();
+
+    compile("hello_wasi_snapshot1.wasm");
+    let output = enarx_run("hello_wasi_snapshot1.wasm", Some(config.path()), None);
+    check_output(&output, 0, &b""[..], None);
+    assert_eq!(std::fs::read(log.path()).unwrap(), b"Hello, world!\n");
+}
+
+#[test]
+fn invoke_add() {
+    // The arguments of the invoked function come from the keep config and
+    // its result is written to stdout as JSON.
+    let mut config = tempfile::NamedTempFile::new().unwrap();
+    writeln!(
+        config,
+        "args = [\"1\", \"2\"]\n\n[invoke]\nfunction = \"add\""
+    )
+    .unwrap();
+
+    compile("add.wasm");
+    let output = enarx_run("add.wasm", Some(config.path()), None);
+    check_output(&output, 0, &b"[{\"type\":\"i32\",\"value\":3}]\n"[..], None);
+}
+
+#[test]
+fn exit_3() {
+    // The status passed to `proc_exit` is the exit status of the keep.
+    run_wasm_test("exit_3.wasm", 3, None, &b""[..], None);
+}
+
+#[test]
+fn trap() {
+    // A trap is reported with its code and the wasm backtrace.
+    let output = run_wasm_test("trap.wasm", 65, None, &b""[..], None);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("UnreachableCodeReached"), "{}", stderr);
+    assert!(stderr.contains("trap!boom"), "{}", stderr);
+}
+
+#[test]
+fn bundle() {
+    // Resources packed by `enarx bundle` are unpacked by wasmldr, so the
+    // workload finds them in its first preopened directory.
+    compile("cat_resource.wasm");
+    let out_dir = Path::new(CRATE).join(OUT_DIR).join(TEST_BINS_OUT);
+
+    let resources = tempfile::tempdir().unwrap();
+    std::fs::write(resources.path().join("hello.txt"), b"Hello, resources!\n").unwrap();
+
+    let status = Command::new(&String::from(KEEP_BIN))
+        .current_dir(CRATE)
+        .arg("bundle")
+        .arg(resources.path())
+        .arg(out_dir.join("cat_resource.wasm"))
+        .arg("--output")
+        .arg(out_dir.join("cat_resource_bundled.wasm"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = enarx_run("cat_resource_bundled.wasm", None, None);
+    check_output(&output, 0, &b"Hello, resources!\n"[..], None);
+}